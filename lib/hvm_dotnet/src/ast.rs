@@ -16,9 +16,9 @@
 //   Copied and adapted from Higher-order Virtual Machine 2: 
 //      https://github.com/HigherOrderCO/HVM.git
 
-use TSPL::{new_parser, Parser};
-use highlight_error::highlight_error;
+use TSPL::Parser;
 use crate::hvm;
+use crate::error::{Error, ErrorKind};
 use std::{collections::BTreeMap, fmt::{Debug, Display}};
 
 // Types
@@ -47,6 +47,7 @@ pub struct Net {
     pub rbag: Vec<Redex>,
 }
 
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Book {
     pub defs: BTreeMap<String, Net>,
 }
@@ -54,9 +55,40 @@ pub struct Book {
 // Parser
 // ------
 
-new_parser!(CoreParser);
+// Written out rather than with `new_parser!`, so that errors are plain text:
+// TSPL's own are styled for terminals with ANSI escapes.
+pub struct CoreParser<'i> {
+    input: &'i str,
+    index: usize,
+}
+
+impl<'i> Parser<'i> for CoreParser<'i> {
+    fn input(&mut self) -> &'i str {
+        self.input
+    }
+
+    fn index(&mut self) -> &mut usize {
+        &mut self.index
+    }
+
+    // Says what was expected and what was found instead. Where is given by
+    // the error's span.
+    fn expected<T>(&mut self, exp: &str) -> Result<T, String> {
+        let found = match self.peek_one() {
+            Some(chr) => format!("`{}`", chr),
+            None => "the end of the input".to_string(),
+        };
+        let line = self.input[..self.index].matches('\n').count() + 1;
+        let column = self.input[..self.index].rsplit('\n').next().map_or(0, |text| text.chars().count()) + 1;
+        Err(format!("Expected {} at line {}, column {}, but found {}", exp, line, column, found))
+    }
+}
 
 impl<'i> CoreParser<'i> {
+    pub fn new(input: &'i str) -> Self {
+        CoreParser { input, index: 0 }
+    }
+
 
     pub fn parse_numb_sym(&mut self) -> Result<Numb, String> {
         self.consume("[")?;
@@ -115,11 +147,9 @@ impl<'i> CoreParser<'i> {
     }
 
     pub fn parse_numb_lit(&mut self) -> Result<Numb, String> {
-        let ini = self.index;
         let num = self.take_while(|x| x.is_alphanumeric() || x == '+' || x == '-' || x == '.');
-        let end = self.index;
         Ok(Numb(if num.contains('.') || num.contains("inf") || num.contains("NaN") {
-            let val: f32 = num.parse().map_err(|err| format!("invalid number literal: {}\n{}", err, highlight_error(ini, end, self.input)))?;
+            let val: f32 = num.parse().map_err(|err| format!("Invalid number literal `{}`: {}", num, err))?;
             hvm::Numb::new_f24(val)
         } else if num.starts_with('+') || num.starts_with('-') {
             let val = Self::parse_int(&num[1..])? as i32;
@@ -131,13 +161,14 @@ impl<'i> CoreParser<'i> {
     }
 
     fn parse_int(input: &str) -> Result<u64, String> {
-        if let Some(rest) = input.strip_prefix("0x") {
-            u64::from_str_radix(rest, 16).map_err(|err| format!("{err:?}"))
+        let val = if let Some(rest) = input.strip_prefix("0x") {
+            u64::from_str_radix(rest, 16)
         } else if let Some(rest) = input.strip_prefix("0b") {
-            u64::from_str_radix(rest, 2).map_err(|err| format!("{err:?}"))
+            u64::from_str_radix(rest, 2)
         } else {
-            input.parse::<u64>().map_err(|err| format!("{err:?}"))
-        }
+            input.parse::<u64>()
+        };
+        val.map_err(|err| format!("Invalid number literal `{}`: {}", input, err))
     }
 
     pub fn parse_numb(&mut self) -> Result<Numb, String> {
@@ -381,11 +412,13 @@ impl Book {
 // --------
 
 impl Tree {
+    // Vars are peeked rather than entered, so a net can still be evaluated
+    // after parts of it are read back (see `ffn`).
     pub fn readback(net: &hvm::GNet, port: hvm::Port, fids: &BTreeMap<hvm::Val, String>) -> Option<Tree> {
         //println!("reading {}", port.show());
         match port.get_tag() {
             hvm::VAR => {
-                let got = net.enter(port);
+                let got = net.peek(port);
                 if got != port {
                     return Tree::readback(net, got, fids);
                 } else {
@@ -426,7 +459,7 @@ impl Tree {
                 return Some(Tree::Swi { fst: Box::new(fst), snd: Box::new(snd) });
             }
             _ => {
-                unreachable!()
+                return None;
             }
         }
     }
@@ -449,91 +482,111 @@ impl Net {
 // -----------
 
 impl Tree {
-    pub fn build(&self, def: &mut hvm::Def, fids: &BTreeMap<String, hvm::Val>, vars: &mut BTreeMap<String, hvm::Val>) -> hvm::Port {
+    pub fn build(&self, def: &mut hvm::Def, fids: &BTreeMap<String, hvm::Val>, vars: &mut BTreeMap<String, hvm::Val>) -> Result<hvm::Port, Error> {
         match self {
             Tree::Var { nam } => {
                 if !vars.contains_key(nam) {
                     vars.insert(nam.clone(), vars.len() as hvm::Val);
                     def.vars += 1;
                 }
-                return hvm::Port::new(hvm::VAR, *vars.get(nam).unwrap());
+                return Ok(hvm::Port::new(hvm::VAR, vars[nam]));
             }
             Tree::Ref { nam } => {
                 if let Some(fid) = fids.get(nam) {
-                    return hvm::Port::new(hvm::REF, *fid);
+                    return Ok(hvm::Port::new(hvm::REF, *fid));
                 } else {
-                    panic!("Unbound definition: {}", nam);
+                    return Err(Error::new(ErrorKind::BUILD, format!("Unbound definition: {}", nam)));
                 }
             }
             Tree::Era => {
-                return hvm::Port::new(hvm::ERA, 0);
+                return Ok(hvm::Port::new(hvm::ERA, 0));
             }
             Tree::Num { val } => {
-                return hvm::Port::new(hvm::NUM, val.0);
+                return Ok(hvm::Port::new(hvm::NUM, val.0));
             }
             Tree::Con { fst, snd } => {
                 let index = def.node.len();
                 def.node.push(hvm::Pair(0));
-                let p1 = fst.build(def, fids, vars);
-                let p2 = snd.build(def, fids, vars);
+                let p1 = fst.build(def, fids, vars)?;
+                let p2 = snd.build(def, fids, vars)?;
                 def.node[index] = hvm::Pair::new(p1, p2);
-                return hvm::Port::new(hvm::CON, index as hvm::Val);
+                return Ok(hvm::Port::new(hvm::CON, index as hvm::Val));
             }
             Tree::Dup { fst, snd } => {
                 def.safe = false;
                 let index = def.node.len();
                 def.node.push(hvm::Pair(0));
-                let p1 = fst.build(def, fids, vars);
-                let p2 = snd.build(def, fids, vars);
+                let p1 = fst.build(def, fids, vars)?;
+                let p2 = snd.build(def, fids, vars)?;
                 def.node[index] = hvm::Pair::new(p1, p2);
-                return hvm::Port::new(hvm::DUP, index as hvm::Val);
+                return Ok(hvm::Port::new(hvm::DUP, index as hvm::Val));
             },
             Tree::Opr { fst, snd } => {
                 let index = def.node.len();
                 def.node.push(hvm::Pair(0));
-                let p1 = fst.build(def, fids, vars);
-                let p2 = snd.build(def, fids, vars);
+                let p1 = fst.build(def, fids, vars)?;
+                let p2 = snd.build(def, fids, vars)?;
                 def.node[index] = hvm::Pair::new(p1, p2);
-                return hvm::Port::new(hvm::OPR, index as hvm::Val);
+                return Ok(hvm::Port::new(hvm::OPR, index as hvm::Val));
             },
             Tree::Swi { fst, snd } => {
                 let index = def.node.len();
                 def.node.push(hvm::Pair(0));
-                let p1 = fst.build(def, fids, vars);
-                let p2 = snd.build(def, fids, vars);
+                let p1 = fst.build(def, fids, vars)?;
+                let p2 = snd.build(def, fids, vars)?;
                 def.node[index] = hvm::Pair::new(p1, p2);
-                return hvm::Port::new(hvm::SWI, index as hvm::Val);
+                return Ok(hvm::Port::new(hvm::SWI, index as hvm::Val));
             },
         }
     }
 }
 
 impl Net {
-    pub fn build(&self, def: &mut hvm::Def, fids: &BTreeMap<String, hvm::Val>, vars: &mut BTreeMap<String, hvm::Val>) {
+    pub fn build(&self, def: &mut hvm::Def, fids: &BTreeMap<String, hvm::Val>, vars: &mut BTreeMap<String, hvm::Val>) -> Result<(), Error> {
         let index = def.node.len();
-        def.root = self.root.build(def, fids, vars);
+        def.root = self.root.build(def, fids, vars)?;
         for (par, fst, snd) in &self.rbag {
             let index = def.rbag.len();
             def.rbag.push(hvm::Pair(0));
-            let p1 = fst.build(def, fids, vars);
-            let p2 = snd.build(def, fids, vars);
+            let p1 = fst.build(def, fids, vars)?;
+            let p2 = snd.build(def, fids, vars)?;
             let rx = hvm::Pair::new(p1, p2);
             let rx = if *par { rx.set_par_flag() } else { rx };
             def.rbag[index] = rx;
         }
+        Ok(())
     }
 }
 
-impl Book {
+impl Tree {
+    // Parses a single tree, like a result shown by the C runtime.
     pub fn parse(code: &str) -> Result<Self, String> {
-        CoreParser::new(code).parse_book()
+        let mut parser = CoreParser::new(code);
+        let tree = parser.parse_tree()?;
+        parser.skip_trivia();
+        if !parser.is_eof() {
+            return Err(format!("Unexpected input after the tree at byte {}", parser.index));
+        }
+        Ok(tree)
     }
+}
 
-    pub fn build(&self) -> Result<hvm::Book, &'static str> {
+impl Book {
+    pub fn parse(code: &str) -> Result<Self, Error> {
+        let mut parser = CoreParser::new(code);
+        // On failure, the parser is left where the error was detected
+        parser.parse_book().map_err(|e| Error::new(ErrorKind::PARSE, e).with_span(parser.index, (parser.index + 1).min(code.len())))
+    }
+
+    pub fn build(&self) -> Result<hvm::Book, Error> {
         let mut name_to_fid = BTreeMap::new();
         let mut fid_to_name = BTreeMap::new();
-        fid_to_name.insert(0, "main".to_string());
-        name_to_fid.insert("main".to_string(), 0);
+        // `@main` comes first when there's one, but any definition can be the
+        // entry, so it's not required
+        if self.defs.contains_key("main") {
+            fid_to_name.insert(0, "main".to_string());
+            name_to_fid.insert("main".to_string(), 0);
+        }
         for (_i, (name, _)) in self.defs.iter().enumerate() {
             if name != "main" {
                 fid_to_name.insert(name_to_fid.len() as hvm::Val, name.clone());
@@ -542,10 +595,7 @@ impl Book {
         }
         let mut book = hvm::Book { defs: Vec::new() };
         for (fid, name) in &fid_to_name {
-            let ast_def = match self.defs.get(name){
-                Some(v) => v,
-                None => { return Err("missing `@main` definition"); }
-            };
+            let ast_def = &self.defs[name];
             let mut def = hvm::Def {
                 name: name.clone(),
                 safe: true,
@@ -554,9 +604,137 @@ impl Book {
                 node: vec![],
                 vars: 0,
             };
-            ast_def.build(&mut def, &name_to_fid, &mut BTreeMap::new());
+            ast_def.build(&mut def, &name_to_fid, &mut BTreeMap::new())?;
             book.defs.push(def);
         }
         return Ok(book);
     }
 }
+
+// Def Decompiler
+// --------------
+
+impl Tree {
+    pub fn decompile(def: &hvm::Def, port: hvm::Port, fids: &BTreeMap<hvm::Val, String>, vars: &mut BTreeMap<hvm::Val, String>, seen: &mut [bool]) -> Option<Tree> {
+        let tag = port.get_tag();
+        match tag {
+            hvm::VAR => {
+                let len = vars.len();
+                let nam = vars.entry(port.get_val()).or_insert_with(|| var_name(len));
+                return Some(Tree::Var { nam: nam.clone() });
+            }
+            hvm::REF => {
+                return Some(Tree::Ref { nam: fids.get(&port.get_val())?.clone() });
+            }
+            hvm::ERA => {
+                return Some(Tree::Era);
+            }
+            hvm::NUM => {
+                return Some(Tree::Num { val: Numb(port.get_val()) });
+            }
+            _ => {
+                // Each node belongs to a single tree, so a revisit means the def is cyclic
+                let index = port.get_val() as usize;
+                if std::mem::replace(seen.get_mut(index)?, true) {
+                    return None;
+                }
+                let pair = &def.node[index];
+                let fst = Box::new(Tree::decompile(def, pair.get_fst(), fids, vars, seen)?);
+                let snd = Box::new(Tree::decompile(def, pair.get_snd(), fids, vars, seen)?);
+                return Some(match tag {
+                    hvm::CON => Tree::Con { fst, snd },
+                    hvm::DUP => Tree::Dup { fst, snd },
+                    hvm::OPR => Tree::Opr { fst, snd },
+                    _ => Tree::Swi { fst, snd },
+                });
+            }
+        }
+    }
+}
+
+impl Net {
+    // Rebuilds a def's net, naming vars in order of appearance. Returns `None`
+    // if the def doesn't encode a forest of trees.
+    pub fn decompile(def: &hvm::Def, fids: &BTreeMap<hvm::Val, String>) -> Option<Net> {
+        let mut vars = BTreeMap::new();
+        let mut seen = vec![false; def.node.len()];
+        let root = Tree::decompile(def, def.root, fids, &mut vars, &mut seen)?;
+        let mut rbag = Vec::new();
+        for pair in &def.rbag {
+            // Clears the par flag left on the fst ref by `set_par_flag`
+            let par = pair.get_par_flag();
+            let fst = if par { hvm::Port::new(hvm::REF, pair.get_fst().get_val() & 0x0FFFFFFF) } else { pair.get_fst() };
+            let fst = Tree::decompile(def, fst, fids, &mut vars, &mut seen)?;
+            let snd = Tree::decompile(def, pair.get_snd(), fids, &mut vars, &mut seen)?;
+            rbag.push((par, fst, snd));
+        }
+        return Some(Net { root, rbag });
+    }
+}
+
+impl Book {
+    pub fn decompile(book: &hvm::Book) -> Option<Book> {
+        let mut fids = BTreeMap::new();
+        for (fid, def) in book.defs.iter().enumerate() {
+            fids.insert(fid as hvm::Val, def.name.clone());
+        }
+        let mut defs = BTreeMap::new();
+        for def in &book.defs {
+            defs.insert(def.name.clone(), Net::decompile(def, &fids)?);
+        }
+        return Some(Book { defs });
+    }
+}
+
+// Names the nth var: a, b, ..., z, aa, ab, ...
+fn var_name(mut index: usize) -> String {
+    let mut nam = Vec::new();
+    loop {
+        nam.push(b'a' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    nam.reverse();
+    String::from_utf8(nam).unwrap_or_default()
+}
+
+#[test]
+fn test_parse_errors() {
+    let error = Book::parse("@main = (a").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::PARSE);
+    assert_eq!(error.span, Some((10, 10)));
+    assert_eq!(error.message, "Expected name at line 1, column 11, but found the end of the input");
+
+    let error = Book::parse("@main = (a a)\n@x = %").unwrap_err();
+    assert_eq!(error.span, Some((19, 20)));
+    assert_eq!(error.message, "Expected name at line 2, column 6, but found `%`");
+
+    let error = Book::parse("@main = 0xZZ").unwrap_err();
+    assert_eq!(error.message, "Invalid number literal `0xZZ`: invalid digit found in string");
+
+    assert!(Book::parse("@main = (a a)").is_ok());
+}
+
+#[test]
+fn test_decompile() {
+    let code = "
+@main = a
+  & @sum ~ (3 a)
+@sum = (?((0 @sum__C0) a) a)
+@sum__C0 = ({a b} c)
+  &! @sum ~ (a $([+] $(b c)))
+@nums = (1.5 (-3 ([*2] *)))
+";
+    let book = Book::parse(code).unwrap().build().unwrap();
+    let decompiled = Book::decompile(&book).unwrap();
+    assert_eq!(decompiled.defs["sum__C0"].show(), "({a b} c) &!@sum ~ (a $([+] $(b c)))");
+
+    // Shows as source that builds the same book
+    let mut expected = Vec::new();
+    book.to_buffer(&mut expected);
+    let mut got = Vec::new();
+    Book::parse(&decompiled.show()).unwrap().build().unwrap().to_buffer(&mut got);
+    assert_eq!(got, expected);
+}
@@ -0,0 +1,206 @@
+use crate::ast::{Book, Net, Numb, Tree};
+use crate::codec::Value;
+use crate::error::{Error, ErrorKind};
+use crate::hvm;
+use std::collections::BTreeMap;
+
+// Book Builder
+// ------------
+
+// Builds a book from trees instead of source text. Trees and nets are kept
+// in slots and referred to by index; building a node or net out of them moves
+// them out of their slots, so each can only be used once.
+#[derive(Default)]
+pub struct BookBuilder {
+    trees: Vec<Option<Tree>>,
+    nets: Vec<Option<Net>>,
+    defs: BTreeMap<String, Net>,
+    fresh: u32, // next var number for encoded values
+}
+
+pub type TreeId = u32;
+pub type NetId = u32;
+
+// Returned by the FFI wrappers in place of an id when an operation fails
+pub const INVALID_ID : u32 = u32::MAX;
+
+impl BookBuilder {
+    pub fn new() -> Self {
+        BookBuilder::default()
+    }
+
+    fn push_tree(&mut self, tree: Tree) -> TreeId {
+        self.trees.push(Some(tree));
+        (self.trees.len() - 1) as TreeId
+    }
+
+    fn take_tree(&mut self, id: TreeId) -> Result<Tree, Error> {
+        match self.trees.get_mut(id as usize).and_then(Option::take) {
+            Some(tree) => Ok(tree),
+            None => Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Tree {} doesn't exist or was already used", id))),
+        }
+    }
+
+    pub fn var(&mut self, nam: &str) -> Result<TreeId, Error> {
+        // Names starting like a number would be read back as one, and those
+        // starting with `$` are kept for the vars of encoded values
+        if nam.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-' || c == '$') {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid variable name: {}", nam)));
+        }
+        check_name(nam)?;
+        Ok(self.push_tree(Tree::Var { nam: nam.to_string() }))
+    }
+
+    pub fn reference(&mut self, nam: &str) -> Result<TreeId, Error> {
+        check_name(nam)?;
+        Ok(self.push_tree(Tree::Ref { nam: nam.to_string() }))
+    }
+
+    pub fn era(&mut self) -> TreeId {
+        self.push_tree(Tree::Era)
+    }
+
+    pub fn num(&mut self, val: hvm::Numb) -> TreeId {
+        self.push_tree(Tree::Num { val: Numb(val.0) })
+    }
+
+    // Adds an encoded host value. Its vars are named `$v0`, `$v1`, ..., which
+    // `var` refuses, so they can't be wired to the caller's vars.
+    pub fn value(&mut self, value: &Value) -> TreeId {
+        let tree = value.encode(&mut self.fresh);
+        self.push_tree(tree)
+    }
+
+    // Applies an operator to a number tree, like `[+1]`.
+    pub fn partial(&mut self, op: hvm::Tag, operand: TreeId) -> Result<TreeId, Error> {
+        match self.take_tree(operand)? {
+            Tree::Num { val } => Ok(self.num(hvm::Numb::partial(hvm::Numb::new_sym(op), hvm::Numb(val.0)))),
+            tree => {
+                self.trees[operand as usize] = Some(tree);
+                Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Tree {} isn't a number", operand)))
+            }
+        }
+    }
+
+    pub fn con(&mut self, fst: TreeId, snd: TreeId) -> Result<TreeId, Error> {
+        let (fst, snd) = self.take_pair(fst, snd)?;
+        Ok(self.push_tree(Tree::Con { fst, snd }))
+    }
+
+    pub fn dup(&mut self, fst: TreeId, snd: TreeId) -> Result<TreeId, Error> {
+        let (fst, snd) = self.take_pair(fst, snd)?;
+        Ok(self.push_tree(Tree::Dup { fst, snd }))
+    }
+
+    pub fn opr(&mut self, fst: TreeId, snd: TreeId) -> Result<TreeId, Error> {
+        let (fst, snd) = self.take_pair(fst, snd)?;
+        Ok(self.push_tree(Tree::Opr { fst, snd }))
+    }
+
+    pub fn swi(&mut self, fst: TreeId, snd: TreeId) -> Result<TreeId, Error> {
+        let (fst, snd) = self.take_pair(fst, snd)?;
+        Ok(self.push_tree(Tree::Swi { fst, snd }))
+    }
+
+    // Takes both children of a node, leaving them in place if either is missing.
+    fn take_pair(&mut self, fst: TreeId, snd: TreeId) -> Result<(Box<Tree>, Box<Tree>), Error> {
+        if fst == snd {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Tree {} can't be used twice", fst)));
+        }
+        let fst_tree = self.take_tree(fst)?;
+        match self.take_tree(snd) {
+            Ok(snd_tree) => Ok((Box::new(fst_tree), Box::new(snd_tree))),
+            Err(e) => {
+                self.trees[fst as usize] = Some(fst_tree);
+                Err(e)
+            }
+        }
+    }
+
+    // Starts a net with the given root.
+    pub fn net(&mut self, root: TreeId) -> Result<NetId, Error> {
+        let root = self.take_tree(root)?;
+        self.nets.push(Some(Net { root, rbag: Vec::new() }));
+        Ok((self.nets.len() - 1) as NetId)
+    }
+
+    // Adds a redex to a net that wasn't defined yet.
+    pub fn redex(&mut self, net: NetId, par: bool, fst: TreeId, snd: TreeId) -> Result<(), Error> {
+        if !matches!(self.nets.get(net as usize), Some(Some(_))) {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Net {} doesn't exist or was already used", net)));
+        }
+        let (fst, snd) = self.take_pair(fst, snd)?;
+        if let Some(Some(net)) = self.nets.get_mut(net as usize) {
+            net.rbag.push((par, *fst, *snd));
+        }
+        Ok(())
+    }
+
+    // Adds a definition, moving the net into it.
+    pub fn define(&mut self, nam: &str, net: NetId) -> Result<(), Error> {
+        check_name(nam)?;
+        if self.defs.contains_key(nam) {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Duplicate definition: {}", nam)));
+        }
+        match self.nets.get_mut(net as usize).and_then(Option::take) {
+            Some(net) => {
+                self.defs.insert(nam.to_string(), net);
+                Ok(())
+            }
+            None => Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Net {} doesn't exist or was already used", net))),
+        }
+    }
+
+    // Returns the definitions added so far as a source book. The builder can
+    // keep being used afterwards.
+    pub fn book(&self) -> Book {
+        Book { defs: self.defs.clone() }
+    }
+}
+
+// Checks a name can be written in HVM core syntax.
+fn check_name(nam: &str) -> Result<(), Error> {
+    if nam.is_empty() || !nam.chars().all(|c| c.is_ascii_alphanumeric() || "_.-/$".contains(c)) {
+        return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid name: {:?}", nam)));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_builder() {
+    // Builds `@id = (a a)` and `@main = x & @id ~ (7 x)`
+    let mut builder = BookBuilder::new();
+    let fst = builder.var("a").unwrap();
+    let snd = builder.var("a").unwrap();
+    let root = builder.con(fst, snd).unwrap();
+    let net = builder.net(root).unwrap();
+    builder.define("id", net).unwrap();
+    let root = builder.var("x").unwrap();
+    let net = builder.net(root).unwrap();
+    let fun = builder.reference("id").unwrap();
+    let arg = builder.num(hvm::Numb::new_u24(7));
+    let ret = builder.var("x").unwrap();
+    let arg = builder.con(arg, ret).unwrap();
+    builder.redex(net, false, fun, arg).unwrap();
+    builder.define("main", net).unwrap();
+    assert_eq!(builder.book(), Book::parse("@id = (a a)\n@main = x & @id ~ (7 x)").unwrap());
+
+    // Trees and nets are used up once built into something else
+    assert_eq!(builder.con(fst, snd).unwrap_err().message, format!("Tree {} doesn't exist or was already used", fst));
+    assert_eq!(builder.define("other", net).unwrap_err().message, format!("Net {} doesn't exist or was already used", net));
+    let era = builder.era();
+    assert_eq!(builder.con(era, era).unwrap_err().message, format!("Tree {} can't be used twice", era));
+    assert_eq!(builder.var("1x").unwrap_err().kind, ErrorKind::INVALID_ARGUMENT);
+    assert_eq!(builder.var("$v0").unwrap_err().message, "Invalid variable name: $v0");
+    let net = builder.net(era).unwrap();
+    assert_eq!(builder.define("main", net).unwrap_err().message, "Duplicate definition: main");
+
+    // Partial operations apply to numbers only
+    let two = builder.num(hvm::Numb::new_u24(2));
+    let add = builder.partial(hvm::OP_ADD, two).unwrap();
+    let net = builder.net(add).unwrap();
+    builder.define("add", net).unwrap();
+    assert_eq!(builder.book().defs["add"].show(), "[+0x0000002]");
+    let var = builder.var("y").unwrap();
+    assert_eq!(builder.partial(hvm::OP_ADD, var).unwrap_err().message, format!("Tree {} isn't a number", var));
+}
@@ -0,0 +1,500 @@
+use crate::ast::{Net, Numb, Tree};
+use crate::error::{Error, ErrorKind};
+use crate::hvm;
+use std::borrow::Cow;
+
+// Data Codec
+// ----------
+
+// Converts between host values and λ-encoded data, using the same encodings
+// as `readback_ctr`, `readback_str` and `inject_str` in hvm.c:
+// - a constructor is `λt (((t TAG) field0) field1 ...)`, in core syntax
+//   `((TAG (field0 (field1 (... r)))) r)`, with a u24 tag
+// - a list is built from `Nil` (tag 0) and `Cons head tail` (tag 1)
+// - a string is a list of u24 code points
+// - a boolean is `False` (tag 0) or `True` (tag 1), and decodes from 0 or 1 too
+// - a tuple nests to the right: `(a, b, c)` is `(a (b c))`
+// Values are decoded from the read back root of a normalized net, so both
+// runtimes go through the same path.
+
+// Most nodes or vars a value may take when encoded: as many as a port can
+// address, which is also the most a net can hold
+pub const MAX_VALUE_LEN: usize = 1 << 29;
+
+// Kept in sync with `ValueKinds` on the host
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+pub enum ValueKind {
+    U24 = 0,
+    I24 = 1,
+    F24 = 2,
+    BOOL = 3,
+    STRING = 4,
+    LIST = 5,
+    TUPLE = 6,
+    CTR = 7,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    U24(u32),
+    I24(i32),
+    F24(f32),
+    Bool(bool),
+    Str(String),
+    List(Vec<Value>),
+    Tuple(Vec<Value>),
+    Ctr { tag: u32, fields: Vec<Value> },
+}
+
+// What a decoded value is expected to look like. Written as `u24`, `i24`,
+// `f24`, `bool`, `str`, `ctr` (any tag, fields decoded as `any`), `any`,
+// `[shape]` for lists and `(shape, shape, ...)` for tuples.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Any,
+    U24,
+    I24,
+    F24,
+    Bool,
+    Str,
+    Ctr,
+    List(Box<Shape>),
+    Tuple(Vec<Shape>),
+}
+
+// Refs are expanded this many times in a row before giving up
+const MAX_EXPANSIONS : usize = 64;
+
+impl Value {
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            Value::U24(_) => ValueKind::U24,
+            Value::I24(_) => ValueKind::I24,
+            Value::F24(_) => ValueKind::F24,
+            Value::Bool(_) => ValueKind::BOOL,
+            Value::Str(_) => ValueKind::STRING,
+            Value::List(_) => ValueKind::LIST,
+            Value::Tuple(_) => ValueKind::TUPLE,
+            Value::Ctr { .. } => ValueKind::CTR,
+        }
+    }
+
+    // The items of a list or tuple, or the fields of a constructor.
+    pub fn items(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::List(items) | Value::Tuple(items) | Value::Ctr { fields: items, .. } => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn items_mut(&mut self) -> Option<&mut Vec<Value>> {
+        match self {
+            Value::List(items) | Value::Tuple(items) | Value::Ctr { fields: items, .. } => Some(items),
+            _ => None,
+        }
+    }
+
+    // Encodes the value as a tree. Its vars are named `$v{n}`, counting up
+    // from `fresh`, so trees encoded with the same counter never share one.
+    // Empty tuples are encoded as `*`, and 1-tuples as their only item.
+    pub fn encode(&self, fresh: &mut u32) -> Tree {
+        match self {
+            Value::U24(val) => Tree::Num { val: Numb(hvm::Numb::new_u24(*val & 0xFFFFFF).0) },
+            Value::I24(val) => Tree::Num { val: Numb(hvm::Numb::new_i24(*val).0) },
+            Value::F24(val) => Tree::Num { val: Numb(hvm::Numb::new_f24(*val).0) },
+            Value::Bool(val) => encode_ctr(*val as u32, Vec::new(), fresh),
+            Value::Str(text) => encode_list(text.chars().map(|c| Tree::Num { val: Numb(hvm::Numb::new_u24(c as u32).0) }).collect(), fresh),
+            Value::List(items) => {
+                let items = items.iter().map(|item| item.encode(fresh)).collect();
+                encode_list(items, fresh)
+            }
+            Value::Tuple(items) => {
+                let mut items: Vec<Tree> = items.iter().map(|item| item.encode(fresh)).collect();
+                let mut tree = items.pop().unwrap_or(Tree::Era);
+                while let Some(item) = items.pop() {
+                    tree = Tree::Con { fst: Box::new(item), snd: Box::new(tree) };
+                }
+                tree
+            }
+            Value::Ctr { tag, fields } => {
+                let fields = fields.iter().map(|field| field.encode(fresh)).collect();
+                encode_ctr(*tag & 0xFFFFFF, fields, fresh)
+            }
+        }
+    }
+
+    // Counts the nodes and vars the value's encoding takes, without building
+    // it, since long lists make for deep trees.
+    pub fn len(&self) -> (usize, usize) {
+        match self {
+            Value::U24(_) | Value::I24(_) | Value::F24(_) => (0, 0),
+            Value::Bool(_) => (2, 1),
+            // `Nil`, then a `Cons` with 4 nodes and a var per item
+            Value::Str(text) => {
+                let len = text.chars().count();
+                (2 + 4 * len, 1 + len)
+            }
+            Value::List(items) => items.iter().map(Value::len).fold((2, 1), |(node, vars), (n, v)| (node + 4 + n, vars + 1 + v)),
+            Value::Tuple(items) => items.iter().map(Value::len).fold((items.len().saturating_sub(1), 0), |(node, vars), (n, v)| (node + n, vars + v)),
+            Value::Ctr { fields, .. } => fields.iter().map(Value::len).fold((2 + fields.len(), 1), |(node, vars), (n, v)| (node + n, vars + v)),
+        }
+    }
+
+    // Builds the value as a def, so either runtime can copy it into a net.
+    // Values have no refs, so no fids are needed. Nodes are added directly
+    // rather than through `encode`, since long lists make for trees too deep
+    // to build on a host's stack.
+    pub fn build(&self, name: &str) -> Result<hvm::Def, Error> {
+        let (node, vars) = self.len();
+        if node > MAX_VALUE_LEN || vars > MAX_VALUE_LEN {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Value is too large: {} nodes and {} vars (expected at most {} of each)", node, vars, MAX_VALUE_LEN)));
+        }
+        let mut def = hvm::Def { name: name.to_string(), safe: true, root: hvm::Port(0), rbag: vec![], node: Vec::with_capacity(node), vars: 0 };
+        def.root = self.build_port(&mut def);
+        Ok(def)
+    }
+
+    // Adds the value's nodes and vars to a def, returning its root. Only
+    // nested values recurse, so the depth doesn't grow with a list's length.
+    fn build_port(&self, def: &mut hvm::Def) -> hvm::Port {
+        match self {
+            Value::U24(val) => build_numb(hvm::Numb::new_u24(*val & 0xFFFFFF)),
+            Value::I24(val) => build_numb(hvm::Numb::new_i24(*val)),
+            Value::F24(val) => build_numb(hvm::Numb::new_f24(*val)),
+            Value::Bool(val) => build_ctr(def, *val as u32, &[]),
+            Value::Str(text) => {
+                let items: Vec<hvm::Port> = text.chars().map(|c| build_numb(hvm::Numb::new_u24(c as u32))).collect();
+                build_list(def, &items)
+            }
+            Value::List(items) => {
+                let items: Vec<hvm::Port> = items.iter().map(|item| item.build_port(def)).collect();
+                build_list(def, &items)
+            }
+            Value::Tuple(items) => {
+                let mut items: Vec<hvm::Port> = items.iter().map(|item| item.build_port(def)).collect();
+                let mut port = items.pop().unwrap_or(hvm::Port::new(hvm::ERA, 0));
+                while let Some(item) = items.pop() {
+                    port = build_con(def, item, port);
+                }
+                port
+            }
+            Value::Ctr { tag, fields } => {
+                let fields: Vec<hvm::Port> = fields.iter().map(|field| field.build_port(def)).collect();
+                build_ctr(def, *tag & 0xFFFFFF, &fields)
+            }
+        }
+    }
+
+    // Decodes a tree into the given shape. Refs are expanded with `defs`,
+    // which looks up a definition's net, or evaluates it when it can; only
+    // nets without redexes can be expanded, since anything else would have to
+    // be evaluated first.
+    pub fn decode(tree: &Tree, shape: &Shape, defs: &mut dyn FnMut(&str) -> Option<Net>) -> Result<Value, Error> {
+        let tree = expand(tree, defs)?;
+        match shape {
+            Shape::U24 => match decode_numb(&tree)? {
+                numb if numb.get_typ() == hvm::TY_U24 => Ok(Value::U24(numb.get_u24())),
+                _ => Err(mismatch("a u24", &tree)),
+            },
+            Shape::I24 => match decode_numb(&tree)? {
+                numb if numb.get_typ() == hvm::TY_I24 => Ok(Value::I24(numb.get_i24())),
+                _ => Err(mismatch("an i24", &tree)),
+            },
+            Shape::F24 => match decode_numb(&tree)? {
+                numb if numb.get_typ() == hvm::TY_F24 => Ok(Value::F24(numb.get_f24())),
+                _ => Err(mismatch("an f24", &tree)),
+            },
+            Shape::Bool => {
+                let val = match &*tree {
+                    Tree::Num { val } if hvm::Numb(val.0).get_typ() == hvm::TY_U24 => hvm::Numb(val.0).get_u24(),
+                    _ => match decode_ctr(&tree, defs)? {
+                        Some((tag, fields)) if fields.is_empty() => tag,
+                        _ => u32::MAX,
+                    },
+                };
+                match val {
+                    0 | 1 => Ok(Value::Bool(val == 1)),
+                    _ => Err(mismatch("a boolean", &tree)),
+                }
+            }
+            Shape::Str => {
+                let mut text = String::new();
+                for item in decode_list(&tree, defs)? {
+                    let item = expand(&item, defs)?;
+                    let code = match decode_numb(&item)? {
+                        numb if numb.get_typ() == hvm::TY_U24 => numb.get_u24(),
+                        _ => return Err(mismatch("a character", &item)),
+                    };
+                    match char::from_u32(code) {
+                        Some(chr) => text.push(chr),
+                        None => return Err(Error::new(ErrorKind::DECODE, format!("Invalid code point in string: {}", code))),
+                    }
+                }
+                Ok(Value::Str(text))
+            }
+            Shape::List(item_shape) => {
+                let items = decode_list(&tree, defs)?;
+                items.iter().map(|item| Value::decode(item, item_shape, defs)).collect::<Result<_, _>>().map(Value::List)
+            }
+            Shape::Tuple(shapes) => {
+                let mut items = Vec::new();
+                let mut rest = tree;
+                for (i, item_shape) in shapes.iter().enumerate() {
+                    if i == shapes.len() - 1 {
+                        items.push(Value::decode(&rest, item_shape, defs)?);
+                        break;
+                    }
+                    let (fst, snd) = match &*rest {
+                        Tree::Con { fst, snd } => ((**fst).clone(), (**snd).clone()),
+                        _ => return Err(mismatch(&format!("a {}-tuple", shapes.len()), &rest)),
+                    };
+                    items.push(Value::decode(&fst, item_shape, defs)?);
+                    rest = Cow::Owned(expand(&snd, defs)?.into_owned());
+                }
+                Ok(Value::Tuple(items))
+            }
+            Shape::Ctr => match decode_ctr(&tree, defs)? {
+                Some((tag, fields)) => {
+                    let fields = fields.iter().map(|field| Value::decode(field, &Shape::Any, defs)).collect::<Result<_, _>>()?;
+                    Ok(Value::Ctr { tag, fields })
+                }
+                None => Err(mismatch("a constructor", &tree)),
+            },
+            Shape::Any => match &*tree {
+                Tree::Num { val } => match hvm::Numb(val.0).get_typ() {
+                    hvm::TY_U24 => Value::decode(&tree, &Shape::U24, defs),
+                    hvm::TY_I24 => Value::decode(&tree, &Shape::I24, defs),
+                    hvm::TY_F24 => Value::decode(&tree, &Shape::F24, defs),
+                    _ => Err(mismatch("a value", &tree)),
+                },
+                Tree::Con { .. } if decode_ctr(&tree, defs)?.is_some() => Value::decode(&tree, &Shape::Ctr, defs),
+                Tree::Con { .. } => Value::decode(&tree, &Shape::Tuple(vec![Shape::Any, Shape::Any]), defs),
+                _ => Err(mismatch("a value", &tree)),
+            },
+        }
+    }
+}
+
+impl Shape {
+    pub fn parse(code: &str) -> Result<Shape, Error> {
+        let mut index = 0;
+        let shape = parse_shape(code, &mut index)?;
+        skip_spaces(code, &mut index);
+        if index < code.len() {
+            return Err(shape_error(code, index));
+        }
+        Ok(shape)
+    }
+}
+
+fn skip_spaces(code: &str, index: &mut usize) {
+    *index += code[*index..].len() - code[*index..].trim_start().len();
+}
+
+fn shape_error(code: &str, index: usize) -> Error {
+    Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid shape: {:?} (at byte {})", code, index))
+}
+
+fn parse_shape(code: &str, index: &mut usize) -> Result<Shape, Error> {
+    skip_spaces(code, index);
+    let rest = &code[*index..];
+    if rest.starts_with('[') {
+        *index += 1;
+        let item = parse_shape(code, index)?;
+        skip_spaces(code, index);
+        if !code[*index..].starts_with(']') {
+            return Err(shape_error(code, *index));
+        }
+        *index += 1;
+        return Ok(Shape::List(Box::new(item)));
+    }
+    if rest.starts_with('(') {
+        *index += 1;
+        let mut items = vec![parse_shape(code, index)?];
+        loop {
+            skip_spaces(code, index);
+            match code[*index..].chars().next() {
+                Some(',') => {
+                    *index += 1;
+                    items.push(parse_shape(code, index)?);
+                }
+                Some(')') if items.len() >= 2 => {
+                    *index += 1;
+                    return Ok(Shape::Tuple(items));
+                }
+                _ => return Err(shape_error(code, *index)),
+            }
+        }
+    }
+    let len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+    let shape = match &rest[..len] {
+        "any" => Shape::Any,
+        "u24" => Shape::U24,
+        "i24" => Shape::I24,
+        "f24" => Shape::F24,
+        "bool" => Shape::Bool,
+        "str" => Shape::Str,
+        "ctr" => Shape::Ctr,
+        _ => return Err(shape_error(code, *index)),
+    };
+    *index += len;
+    Ok(shape)
+}
+
+// Replaces refs by the root of their definition, until the tree isn't one.
+fn expand<'a>(tree: &'a Tree, defs: &mut dyn FnMut(&str) -> Option<Net>) -> Result<Cow<'a, Tree>, Error> {
+    let mut tree = Cow::Borrowed(tree);
+    for _ in 0..MAX_EXPANSIONS {
+        let nam = match &*tree {
+            Tree::Ref { nam } => nam.clone(),
+            _ => return Ok(tree),
+        };
+        match defs(&nam) {
+            Some(net) if net.rbag.is_empty() => tree = Cow::Owned(net.root),
+            Some(_) => return Err(Error::new(ErrorKind::DECODE, format!("`@{}` must be evaluated before it can be decoded", nam))),
+            None => return Err(Error::new(ErrorKind::DECODE, format!("Unbound definition: {}", nam))),
+        }
+    }
+    Err(Error::new(ErrorKind::DECODE, format!("Too many nested refs in `{}`", tree.show())))
+}
+
+fn mismatch(expected: &str, tree: &Tree) -> Error {
+    Error::new(ErrorKind::DECODE, format!("Expected {}, found `{}`", expected, tree.show()))
+}
+
+fn decode_numb(tree: &Tree) -> Result<hvm::Numb, Error> {
+    match tree {
+        Tree::Num { val } => Ok(hvm::Numb(val.0)),
+        _ => Err(mismatch("a number", tree)),
+    }
+}
+
+// Reads `((TAG (field0 (... r))) r)` into its tag and fields, or returns None
+// if the tree isn't a constructor.
+fn decode_ctr(tree: &Tree, defs: &mut dyn FnMut(&str) -> Option<Net>) -> Result<Option<(u32, Vec<Tree>)>, Error> {
+    let (app, ret) = match tree {
+        Tree::Con { fst, snd } => (expand(fst, defs)?, &**snd),
+        _ => return Ok(None),
+    };
+    let (tag, mut rest) = match &*app {
+        Tree::Con { fst, snd } => match &**fst {
+            Tree::Num { val } if hvm::Numb(val.0).get_typ() == hvm::TY_U24 => (hvm::Numb(val.0).get_u24(), expand(snd, defs)?.into_owned()),
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    let mut fields = Vec::new();
+    loop {
+        match rest {
+            Tree::Con { fst, snd } => {
+                fields.push(*fst);
+                rest = expand(&snd, defs)?.into_owned();
+            }
+            Tree::Var { .. } if &rest == ret => return Ok(Some((tag, fields))),
+            _ => return Ok(None),
+        }
+    }
+}
+
+// Reads a cons/nil list into its items.
+fn decode_list(tree: &Tree, defs: &mut dyn FnMut(&str) -> Option<Net>) -> Result<Vec<Tree>, Error> {
+    let mut items = Vec::new();
+    let mut rest = tree.clone();
+    loop {
+        match decode_ctr(&rest, defs)? {
+            Some((hvm::LIST_NIL, fields)) if fields.is_empty() => return Ok(items),
+            Some((hvm::LIST_CONS, mut fields)) if fields.len() == 2 => {
+                let tail = fields.pop().unwrap();
+                items.push(fields.pop().unwrap());
+                rest = expand(&tail, defs)?.into_owned();
+            }
+            _ => return Err(mismatch("a list", &rest)),
+        }
+    }
+}
+
+// Builds `((TAG (field0 (... r))) r)`.
+fn encode_ctr(tag: u32, fields: Vec<Tree>, fresh: &mut u32) -> Tree {
+    let ret = Tree::Var { nam: format!("$v{}", fresh) };
+    *fresh += 1;
+    let mut app = ret.clone();
+    for field in fields.into_iter().rev() {
+        app = Tree::Con { fst: Box::new(field), snd: Box::new(app) };
+    }
+    let app = Tree::Con { fst: Box::new(Tree::Num { val: Numb(hvm::Numb::new_u24(tag).0) }), snd: Box::new(app) };
+    Tree::Con { fst: Box::new(app), snd: Box::new(ret) }
+}
+
+fn encode_list(items: Vec<Tree>, fresh: &mut u32) -> Tree {
+    let mut list = encode_ctr(hvm::LIST_NIL, Vec::new(), fresh);
+    for item in items.into_iter().rev() {
+        list = encode_ctr(hvm::LIST_CONS, vec![item, list], fresh);
+    }
+    list
+}
+
+fn build_numb(numb: hvm::Numb) -> hvm::Port {
+    hvm::Port::new(hvm::NUM, numb.0)
+}
+
+fn build_con(def: &mut hvm::Def, fst: hvm::Port, snd: hvm::Port) -> hvm::Port {
+    def.node.push(hvm::Pair::new(fst, snd));
+    hvm::Port::new(hvm::CON, (def.node.len() - 1) as hvm::Val)
+}
+
+// Adds `((TAG (field0 (... r))) r)` to a def, like `encode_ctr`.
+fn build_ctr(def: &mut hvm::Def, tag: u32, fields: &[hvm::Port]) -> hvm::Port {
+    let ret = hvm::Port::new(hvm::VAR, def.vars as hvm::Val);
+    def.vars += 1;
+    let mut app = ret;
+    for &field in fields.iter().rev() {
+        app = build_con(def, field, app);
+    }
+    let app = build_con(def, build_numb(hvm::Numb::new_u24(tag)), app);
+    build_con(def, app, ret)
+}
+
+fn build_list(def: &mut hvm::Def, items: &[hvm::Port]) -> hvm::Port {
+    let mut list = build_ctr(def, hvm::LIST_NIL, &[]);
+    for &item in items.iter().rev() {
+        list = build_ctr(def, hvm::LIST_CONS, &[item, list]);
+    }
+    list
+}
+
+#[test]
+fn test_codec() {
+    let ctr = Value::Ctr { tag: 9, fields: vec![Value::I24(-4), Value::Bool(false)] };
+    let value = Value::Tuple(vec![Value::Str("héllo".to_string()), Value::List(vec![Value::F24(2.5), Value::Bool(true), ctr.clone()])]);
+    let tree = value.encode(&mut 0);
+    let decode = |shape: &str| Shape::parse(shape).and_then(|shape| Value::decode(&tree, &shape, &mut |_| None));
+
+    // Decodes into the given shape, or as constructors where it's `any`
+    let nil = Value::Ctr { tag: 0, fields: vec![] };
+    let decoded = Value::Tuple(vec![Value::Str("héllo".to_string()), Value::List(vec![Value::F24(2.5), Value::Ctr { tag: 1, fields: vec![] }, Value::Ctr { tag: 9, fields: vec![Value::I24(-4), nil.clone()] }])]);
+    assert_eq!(decode("(str, [any])").unwrap(), decoded);
+    let Value::Tuple(items) = decode("any").unwrap() else { panic!() };
+    assert_eq!(items[0].kind(), ValueKind::CTR);
+    assert_eq!(decode("(str, [u24])").unwrap_err().message, "Expected a u24, found `2.5`");
+    assert_eq!(decode("(str, [u24])").unwrap_err().kind, ErrorKind::DECODE);
+    assert_eq!(decode("(str").unwrap_err().message, "Invalid shape: \"(str\" (at byte 4)");
+
+    // Expands refs to nets without redexes
+    let tree = Tree::Ref { nam: "nil".to_string() };
+    let mut defs = |nam: &str| (nam == "nil").then(|| Net { root: nil.encode(&mut 0), rbag: vec![] });
+    assert_eq!(Value::decode(&tree, &Shape::List(Box::new(Shape::U24)), &mut defs).unwrap(), Value::List(vec![]));
+
+    // Builds what it encodes to, and counts what that takes
+    for value in [value, ctr, Value::Str(String::new()), Value::Tuple(vec![]), Value::Tuple(vec![Value::U24(1)])] {
+        let def = value.build("value").unwrap();
+        assert_eq!(value.len(), (def.node.len(), def.vars));
+        let built = Net::decompile(&def, &Default::default()).unwrap().root;
+        assert_eq!(Value::decode(&built, &Shape::Any, &mut |_| None).ok(), Value::decode(&value.encode(&mut 0), &Shape::Any, &mut |_| None).ok());
+    }
+
+    // However long it is
+    let long = Value::Str("x".repeat(100_000));
+    assert_eq!(long.build("value").unwrap().node.len(), long.len().0);
+}
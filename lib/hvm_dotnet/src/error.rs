@@ -0,0 +1,82 @@
+use std::ffi::CString;
+use std::os::raw::c_char;
+use crate::to_cstring;
+
+// Errors
+// ======
+
+// Kept in sync with `ErrorKind` in hvm.h. Part of the host ABI, so some
+// kinds may not be produced by every build.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms, dead_code)]
+pub enum ErrorKind {
+    PARSE = 0, // the source isn't valid HVM
+    BUILD = 1, // the book can't be built or encoded
+    MISSING_ENTRY = 2, // the entry definition doesn't exist
+    OOM = 3, // the net can't be allocated
+    RUNTIME_UNAVAILABLE = 4, // the runtime wasn't compiled in
+    CANCELLED = 5, // the operation was cancelled before it ran
+    IO = 6, // an IO operation failed
+    INVALID_ARGUMENT = 7, // the host passed a bad argument
+    RUNTIME = 8, // the evaluation raised a runtime error
+    PANIC = 9, // the library hit an internal error
+    FORMAT = 10, // a serialized book is malformed
+    DECODE = 11, // a value doesn't have the expected shape
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Option<(usize, usize)>, // byte range in the source
+}
+
+// Handed to the host through `err_out`; freed with `free_error`
+#[repr(C)]
+pub struct ErrorRaw {
+    kind: ErrorKind,
+    has_span: u32, // whether `span_start`/`span_end` are set
+    span_start: u64,
+    span_end: u64,
+    message: *mut c_char,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Error { kind, message: message.into(), span: None }
+    }
+
+    pub fn with_span(mut self, start: usize, end: usize) -> Self {
+        self.span = Some((start, end));
+        self
+    }
+
+    pub fn into_raw(self) -> *mut ErrorRaw {
+        let (start, end) = self.span.unwrap_or((0, 0));
+        Box::into_raw(Box::new(ErrorRaw {
+            kind: self.kind,
+            has_span: self.span.is_some() as u32,
+            span_start: start as u64,
+            span_end: end as u64,
+            message: to_cstring(self.message).into_raw(),
+        }))
+    }
+}
+
+impl ErrorRaw {
+    // Frees an error created by `Error::into_raw`.
+    pub unsafe fn free(this_ptr: *mut ErrorRaw) {
+        let this = Box::from_raw(this_ptr);
+        _ = CString::from_raw(this.message);
+    }
+
+    // Takes back an error handed to the host, so tests can inspect it.
+    #[cfg(test)]
+    pub unsafe fn take(this_ptr: *mut ErrorRaw) -> Error {
+        let this = Box::from_raw(this_ptr);
+        let message = CString::from_raw(this.message).into_string().unwrap_or_default();
+        let span = if this.has_span != 0 { Some((this.span_start as usize, this.span_end as usize)) } else { None };
+        Error { kind: this.kind, message, span }
+    }
+}
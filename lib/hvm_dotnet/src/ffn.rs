@@ -0,0 +1,172 @@
+use crate::ast::{Net, Tree};
+use crate::codec::{Shape, Value};
+use crate::error::{Error, ErrorKind};
+use crate::hvm;
+use crate::BookHandle;
+use std::collections::BTreeMap;
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+// Host Functions
+// --------------
+
+// A host callback for `IO_CALL`. It gets the decoded argument, which it must
+// not free, and returns a value made with the `value_*` exports, which is then
+// owned by the library; null returns `*` to the program.
+pub type HostFn = unsafe extern "C" fn(data: *mut c_void, argm: *const Value) -> *mut Value;
+
+// A function registered with `book_register_ffn`
+pub struct HostFFn {
+    pub name: String,
+    pub shape: Shape, // how the argument is decoded
+    pub func: HostFn,
+    pub data: *mut c_void, // passed back to `func` as is
+}
+
+impl HostFFn {
+    // Calls the function, building its result as a def so either runtime can
+    // copy it into a net. Errors are for the runtime to report like other IO
+    // errors.
+    fn invoke(&self, argm: &Value) -> Result<Option<hvm::Def>, String> {
+        let ret = unsafe { (self.func)(self.data, argm) };
+        if ret.is_null() {
+            return Ok(None);
+        }
+        let ret = unsafe { Box::from_raw(ret) };
+        match ret.build(&self.name) {
+            Ok(def) => Ok(Some(def)),
+            Err(e) => Err(format!("{}: {}", self.name, e.message)),
+        }
+    }
+}
+
+impl BookHandle {
+    fn ffn(&self, name: &str) -> Option<&HostFFn> {
+        self.ffns.iter().find(|ffn| ffn.name == name)
+    }
+}
+
+// Rust runtime: the argument is read back from the net, and refs in it are
+// evaluated as they are decoded.
+impl hvm::HostFFns for BookHandle {
+    fn call(&self, io: &mut hvm::Io, name: &str, argm: hvm::Port) -> Option<hvm::Port> {
+        let ffn = self.ffn(name)?;
+        let fids: BTreeMap<hvm::Val, String> = io.book.defs.iter().enumerate().map(|(fid, def)| (fid as hvm::Val, def.name.clone())).collect();
+        let era = hvm::Port::new(hvm::ERA, 0);
+        let tree = match Tree::readback(io.net, io.net.peek(argm), &fids) {
+            Some(tree) => tree,
+            None => return Some(era),
+        };
+        let mut defs = |nam: &str| {
+            let fid = io.book.defs.iter().position(|def| def.name == nam)?;
+            let root = io.expand(hvm::Port::new(hvm::REF, fid as hvm::Val)).ok()?;
+            Some(Net { root: Tree::readback(io.net, root, &fids)?, rbag: Vec::new() })
+        };
+        let argm = match Value::decode(&tree, &ffn.shape, &mut defs) {
+            Ok(argm) => argm,
+            Err(e) => {
+                io.report(&format!("{}: {}", name, e.message));
+                return Some(era);
+            }
+        };
+        match ffn.invoke(&argm) {
+            Ok(Some(def)) => match io.tms[0].inject_def(io.net, &def) {
+                Some(ret) => Some(ret),
+                None => {
+                    io.report(&format!("{}: failed to get resources", name));
+                    Some(era)
+                }
+            },
+            Ok(None) => Some(era),
+            Err(e) => {
+                io.report(&e);
+                Some(era)
+            }
+        }
+    }
+}
+
+// C runtime: `HostFFnsRaw` and `HostValue` are mirrored in hvm.h. The argument
+// is shown as text, and refs in it are expanded to their definitions.
+
+#[repr(C)]
+pub struct HostValue {
+    root: u32,
+    node_len: u32,
+    node_buf: *mut u64,
+    vars_len: u32,
+}
+
+impl HostValue {
+    // Points at a def, whose nodes are given as raw pairs in `node`, which
+    // must outlive the value.
+    pub fn new(def: &hvm::Def, node: &[u64]) -> Self {
+        HostValue { root: def.root.0, node_len: node.len() as u32, node_buf: node.as_ptr() as *mut u64, vars_len: def.vars as u32 }
+    }
+}
+
+#[repr(C)]
+pub struct HostFFnsRaw {
+    ctx: *const c_void,
+    // Returns 0 if there's no function named `name`. Errors are written to
+    // `err_buf`, for the runtime to report.
+    call: unsafe extern "C" fn(ctx: *const c_void, name: *const c_char, argm: *const c_char, ret: *mut *mut HostValue, err_buf: *mut c_char, err_len: u32) -> u32,
+    free: unsafe extern "C" fn(value: *mut HostValue),
+}
+
+impl HostFFnsRaw {
+    pub fn new(handle: &BookHandle) -> Self {
+        HostFFnsRaw { ctx: handle as *const BookHandle as *const c_void, call: host_call, free: host_free }
+    }
+}
+
+// Writes `message` to a buffer of `len` bytes as a C string, cut short if it
+// doesn't fit, like `write_error` in hvm.c.
+unsafe fn write_error(buf: *mut c_char, len: u32, message: &str) {
+    if buf.is_null() || len == 0 {
+        return;
+    }
+    let size = message.len().min(len as usize - 1);
+    std::ptr::copy_nonoverlapping(message.as_ptr() as *const c_char, buf, size);
+    *buf.add(size) = 0;
+}
+
+unsafe extern "C" fn host_call(ctx: *const c_void, name: *const c_char, argm: *const c_char, ret: *mut *mut HostValue, err_buf: *mut c_char, err_len: u32) -> u32 {
+    let handle = &*(ctx as *const BookHandle);
+    let name = CStr::from_ptr(name).to_string_lossy();
+    let ffn = match handle.ffn(&name) {
+        Some(ffn) => ffn,
+        None => return 0,
+    };
+    *ret = std::ptr::null_mut();
+    // Unwinding into C is undefined, so a panic returns `*` instead
+    _ = catch_unwind(AssertUnwindSafe(|| {
+        let argm = match Tree::parse(&CStr::from_ptr(argm).to_string_lossy()) {
+            Ok(tree) => handle.decode_tree(&tree, &ffn.shape),
+            Err(e) => Err(Error::new(ErrorKind::PARSE, e)),
+        };
+        let argm = match argm {
+            Ok(argm) => argm,
+            Err(e) => {
+                write_error(err_buf, err_len, &format!("{}: {}", name, e.message));
+                return;
+            }
+        };
+        match ffn.invoke(&argm) {
+            Ok(Some(def)) => {
+                let node = def.node.iter().map(|pair| pair.0).collect::<Vec<u64>>().into_boxed_slice();
+                *ret = Box::into_raw(Box::new(HostValue::new(&def, &node)));
+                _ = Box::into_raw(node); // freed by `host_free`
+            }
+            Ok(None) => {}
+            Err(e) => write_error(err_buf, err_len, &e),
+        }
+    }));
+    1
+}
+
+unsafe extern "C" fn host_free(value: *mut HostValue) {
+    let value = Box::from_raw(value);
+    _ = Box::from_raw(std::ptr::slice_from_raw_parts_mut(value.node_buf, value.node_len as usize));
+}
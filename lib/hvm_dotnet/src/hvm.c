@@ -17,9 +17,18 @@
 //      https://github.com/HigherOrderCO/HVM.git
 
 //#include <dlfcn.h>
+#include <limits.h>
 #include <math.h>
+#include <stdarg.h>
 #include <pthread.h>
+#include <sys/mman.h>
 #include <stdatomic.h>
+#include <fcntl.h>
+// unistd.h declares a `link` that would clash with ours, so it is renamed
+// while included. We only need `sysconf` and `close` from it.
+#define link unistd_link
+#include <unistd.h>
+#undef link
 #include "hvm.h"
 
 #define INTERPRETED
@@ -112,19 +121,58 @@ static const f32 I24_MIN = (f32) (i32) ((-1u) << 23);
 
 // Global Net
 #define HLEN (1ul << 16) // max 16k high-priority redexes
-#define RLEN (1ul << 24) // max 16m low-priority redexes
+#define RLEN (1ul << 24) // max 16m low-priority redexes (per thread)
 #define G_NODE_LEN (1ul << 29) // max 536m nodes
 #define G_VARS_LEN (1ul << 29) // max 536m vars
-#define G_RBAG_LEN (TPC * RLEN)
+
+typedef struct TM TM;
 
 typedef struct Net {
-    APair node_buf[G_NODE_LEN]; // global node buffer
-    APort vars_buf[G_VARS_LEN]; // global vars buffer
-    APair rbag_buf[G_RBAG_LEN]; // global rbag buffer
+    APair *node_buf; // global node buffer
+    APort *vars_buf; // global vars buffer
+    APair *rbag_buf; // global rbag buffer (RLEN per thread)
+    u32 node_len; // length of the node buffer
+    u32 vars_len; // length of the vars buffer
+    u32 tpc; // thread count
+    APort root; // root var (kept out of the vars buffer)
+    TM **tm; // thread memories
     a64 itrs; // interaction count
     a32 idle; // idle thread counter
+    a64 reached; // number of threads that reached the current barrier
+    a64 barrier; // number of barriers passed during this evaluation
+    u64 fuel; // interaction limit (UINT64_MAX = unlimited)
+    u64 deadline; // wall-clock limit, in time64() nanoseconds (0 = unlimited)
+    const CancellationToken *cancel; // set by the host to cancel (NULL = none)
+    a32 halt; // why the evaluation stopped early (STATUS_COMPLETED otherwise)
+    u32 fail_fid; // def that raised a runtime error (when halted with HALT_FAIL)
+    const IoPolicy *policy; // IO allow-list (NULL = no IO)
+    FILE *files[256]; // open files, indexed by file descriptor (see `readback_file`)
+    u32 stdio; // one of StdioModes
+    const u8 *stdin_buf; // STDIO_CAPTURE: what programs read from stdin
+    u64 stdin_len;
+    u64 stdin_at; // how much of it was read
+    void (*output)(void *ctx, u32 fd, const u8 *buf, u64 len); // STDIO_CAPTURE: streams output
+    void *output_ctx;
+    StringBuffer captured[2]; // STDIO_CAPTURE: stdout and stderr, unless streamed
+    u32 (*progress)(void *ctx, const Progress *progress); // reports progress (NULL = never)
+    void *progress_ctx;
+    u64 progress_itrs; // report every this many interactions (0 = never)
+    u64 progress_ns; // report every this many nanoseconds (0 = never)
+    u64 start; // when the evaluation started, in time64() nanoseconds
+    u64 next_itrs; // when the next reports are due, only touched by thread 0
+    u64 next_time;
+    // Most redexes, nodes and vars in use at once. Threads add their own peaks
+    // since their last halt check to the others' loads, so this is exact on one
+    // thread, and on more it may miss peaks shorter than a halt check interval.
+    a64 peak_rbag;
+    a64 peak_node;
+    a64 peak_vars;
 } Net;
 
+// Halt reasons for runtime errors, next to the EvaluationStatus values
+#define HALT_FAIL 4
+#define HALT_OOM 5 // a thread went through its whole slice of a buffer without finding room
+
 // Top-Level Definition
 typedef struct Def {
     char name[256];
@@ -155,7 +203,7 @@ typedef struct Book {
 } Book;
 
 // Local Thread Memory
-typedef struct TM {
+struct TM {
     u32 tid; // thread id
     u32 itrs; // interaction count
     u32 nput; // next node allocation attempt index
@@ -163,10 +211,20 @@ typedef struct TM {
     u32 hput; // next hbag push index
     u32 rput; // next rbag push index
     u32 sidx; // steal index
+    i64 live; // nodes made minus nodes taken by this thread's interactions
+    i64 live_vars; // vars made minus vars taken by them
+    u64 rules[8]; // interactions performed, indexed by rule
+    u64 peak_rbag; // most redexes this thread's bags held since its last halt check
+    i64 peak_node; // highest `live` since then
+    i64 peak_vars; // highest `live_vars` since then
+    a64 load_lo; // rbag and hbag lengths and live nodes and vars, as of the last halt check
+    a64 load_hi;
+    _Atomic (i64) load_live;
+    _Atomic (i64) load_vars;
     u32 nloc[0xFFF]; // node allocation indices
     u32 vloc[0xFFF]; // vars allocation indices
     Pair hbag_buf[HLEN]; // high-priority redexes
-} TM;
+};
 
 // Readback: λ-Encoded Ctr
 typedef struct Ctr {
@@ -299,34 +357,19 @@ inline f32 clamp(f32 x, f32 min, f32 max) {
 }
 
 // A simple spin-wait barrier using atomic operations
-a64 a_reached = 0; // number of threads that reached the current barrier
-a64 a_barrier = 0; // number of barriers passed during this program
-void sync_threads() {
-    u64 barrier_old = atomic_load_explicit(&a_barrier, memory_order_relaxed);
-    if (atomic_fetch_add_explicit(&a_reached, 1, memory_order_relaxed) == (TPC - 1)) {
+void sync_threads(Net *net) {
+    u64 barrier_old = atomic_load_explicit(&net->barrier, memory_order_relaxed);
+    if (atomic_fetch_add_explicit(&net->reached, 1, memory_order_relaxed) == (net->tpc - 1)) {
         // Last thread to reach the barrier resets the counter and advances the barrier
-        atomic_store_explicit(&a_reached, 0, memory_order_relaxed);
-        atomic_store_explicit(&a_barrier, barrier_old + 1, memory_order_release);
+        atomic_store_explicit(&net->reached, 0, memory_order_relaxed);
+        atomic_store_explicit(&net->barrier, barrier_old + 1, memory_order_release);
     } else {
-        u32 tries = 0;
-        while (atomic_load_explicit(&a_barrier, memory_order_acquire) == barrier_old) {
+        while (atomic_load_explicit(&net->barrier, memory_order_acquire) == barrier_old) {
             sched_yield();
         }
     }
 }
 
-// Global sum function
-static a32 GLOBAL_SUM = 0;
-
-u32 global_sum(u32 x) {
-    atomic_fetch_add_explicit(&GLOBAL_SUM, x, memory_order_relaxed);
-    sync_threads();
-    u32 sum = atomic_load_explicit(&GLOBAL_SUM, memory_order_relaxed);
-    sync_threads();
-    atomic_store_explicit(&GLOBAL_SUM, 0, memory_order_relaxed);
-    return sum;
-}
-
 // TODO: write a time64() function that returns the time as fast as possible as a u64
 static inline u64 time64() {
     struct timespec ts;
@@ -540,6 +583,7 @@ static inline Numb operate(Numb a, Numb b) {
         a = b;
         b = swp;
     }
+    // Integer division and remainder by zero give 0, like upstream HVM
     switch (ty) {
         case TY_U24: {
             u32 av = get_u24(a);
@@ -554,13 +598,13 @@ static inline Numb operate(Numb a, Numb b) {
                 case OP_MUL:
                     return new_u24(av * bv);
                 case OP_DIV:
-                    return new_u24(av / bv);
+                    return new_u24(bv ? av / bv : 0);
                 case FP_DIV:
-                    return new_u24(bv / av);
+                    return new_u24(av ? bv / av : 0);
                 case OP_REM:
-                    return new_u24(av % bv);
+                    return new_u24(bv ? av % bv : 0);
                 case FP_REM:
-                    return new_u24(bv % av);
+                    return new_u24(av ? bv % av : 0);
                 case OP_EQ:
                     return new_u24(av == bv);
                 case OP_NEQ:
@@ -600,13 +644,13 @@ static inline Numb operate(Numb a, Numb b) {
                 case OP_MUL:
                     return new_i24(av * bv);
                 case OP_DIV:
-                    return new_i24(av / bv);
+                    return new_i24(bv ? av / bv : 0);
                 case FP_DIV:
-                    return new_i24(bv / av);
+                    return new_i24(av ? bv / av : 0);
                 case OP_REM:
-                    return new_i24(av % bv);
+                    return new_i24(bv ? av % bv : 0);
                 case FP_REM:
-                    return new_i24(bv % av);
+                    return new_i24(av ? bv % av : 0);
                 case OP_EQ:
                     return new_u24(av == bv);
                 case OP_NEQ:
@@ -677,7 +721,7 @@ static inline void push_redex(Net *net, TM *tm, Pair redex) {
     if (is_high_priority(get_pair_rule(redex))) {
         tm->hbag_buf[tm->hput++] = redex;
     } else {
-        atomic_store_explicit(&net->rbag_buf[tm->tid * (G_RBAG_LEN / TPC) + (tm->rput++)], redex, memory_order_relaxed);
+        atomic_store_explicit(&net->rbag_buf[tm->tid * RLEN + (tm->rput++)], redex, memory_order_relaxed);
     }
 }
 
@@ -685,7 +729,7 @@ static inline Pair pop_redex(Net *net, TM *tm) {
     if (tm->hput > 0) {
         return tm->hbag_buf[--tm->hput];
     } else if (tm->rput > 0) {
-        return atomic_exchange_explicit(&net->rbag_buf[tm->tid * (G_RBAG_LEN / TPC) + (--tm->rput)], 0,
+        return atomic_exchange_explicit(&net->rbag_buf[tm->tid * RLEN + (--tm->rput)], 0,
                                         memory_order_relaxed);
     } else {
         return 0;
@@ -699,10 +743,9 @@ static inline u32 rbag_len(Net *net, TM *tm) {
 // TM
 // --
 
-static TM *tm[TPC];
-
 TM *tm_new(u32 tid) {
     TM *tm = malloc(sizeof(TM));
+    if (tm == NULL) return NULL;
     tm->tid = tid;
     tm->itrs = 0;
     tm->nput = 1;
@@ -710,24 +753,46 @@ TM *tm_new(u32 tid) {
     tm->rput = 0;
     tm->hput = 0;
     tm->sidx = 0;
+    tm->live = 0;
+    tm->live_vars = 0;
+    memset(tm->rules, 0, sizeof(tm->rules));
+    tm->peak_rbag = 0;
+    tm->peak_node = 0;
+    tm->peak_vars = 0;
+    tm->load_lo = 0;
+    tm->load_hi = 0;
+    tm->load_live = 0;
+    tm->load_vars = 0;
     return tm;
 }
 
-void alloc_static_tms() {
-    for (u32 t = 0; t < TPC; ++t) {
-        tm[t] = tm_new(t);
+bool alloc_tms(Net *net) {
+    net->tm = calloc(net->tpc, sizeof(TM *));
+    if (net->tm == NULL) return FALSE;
+    for (u32 t = 0; t < net->tpc; ++t) {
+        net->tm[t] = tm_new(t);
+        if (net->tm[t] == NULL) return FALSE;
     }
+    return TRUE;
 }
 
-void free_static_tms() {
-    for (u32 t = 0; t < TPC; ++t) {
-        free(tm[t]);
+void free_tms(Net *net) {
+    if (net->tm == NULL) return;
+    for (u32 t = 0; t < net->tpc; ++t) {
+        free(net->tm[t]);
     }
+    free(net->tm);
+    net->tm = NULL;
 }
 
 // Net
 // ----
 
+// Gets a var slot, routing the ROOT var to its dedicated slot.
+static inline APort *vars_slot(Net *net, u32 var) {
+    return var == get_val(ROOT) ? &net->root : &net->vars_buf[var];
+}
+
 // Stores a new node on global.
 static inline void node_create(Net *net, u32 loc, Pair val) {
     atomic_store_explicit(&net->node_buf[loc], val, memory_order_relaxed);
@@ -735,7 +800,7 @@ static inline void node_create(Net *net, u32 loc, Pair val) {
 
 // Stores a var on global.
 static inline void vars_create(Net *net, u32 var, Port val) {
-    atomic_store_explicit(&net->vars_buf[var], val, memory_order_relaxed);
+    atomic_store_explicit(vars_slot(net, var), val, memory_order_relaxed);
 }
 
 // Reads a node from global.
@@ -745,7 +810,7 @@ static inline Pair node_load(Net *net, u32 loc) {
 
 // Reads a var from global.
 static inline Port vars_load(Net *net, u32 var) {
-    return atomic_load_explicit(&net->vars_buf[var], memory_order_relaxed);
+    return atomic_load_explicit(vars_slot(net, var), memory_order_relaxed);
 }
 
 // Stores a node on global.
@@ -760,7 +825,7 @@ static inline Pair node_exchange(Net *net, u32 loc, Pair val) {
 
 // Exchanges a var on global by a value. Returns old.
 static inline Port vars_exchange(Net *net, u32 var, Port val) {
-    return atomic_exchange_explicit(&net->vars_buf[var], val, memory_order_relaxed);
+    return atomic_exchange_explicit(vars_slot(net, var), val, memory_order_relaxed);
 }
 
 // Takes a node.
@@ -774,79 +839,256 @@ static inline Port vars_take(Net *net, u32 var) {
 }
 
 
+// Net Memory
+// ----------
+
+// Maps `size` zeroed bytes. Pages are only committed once they're written,
+// and aren't counted against the commit limit, so a net takes as much RAM as
+// its evaluation uses rather than its full size. Returns NULL on failure.
+void *mem_map(u64 size) {
+    void *ptr = mmap(NULL, size, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE, -1, 0);
+    return ptr == MAP_FAILED ? NULL : ptr;
+}
+
+void mem_unmap(void *ptr, u64 size) {
+    if (ptr != NULL) munmap(ptr, size);
+}
+
+// Zeroes mapped bytes. The whole pages among them are handed back to the OS,
+// which maps them to zeroes again when they're next touched. `madvise` only
+// takes whole pages, so the page size is asked for rather than assumed: it is
+// 16 KiB on Apple Silicon and can be 64 KiB on ARM64 Linux.
+void mem_zero(void *ptr, u64 size) {
+    long page_size = sysconf(_SC_PAGESIZE);
+    u64 page = page_size > 0 ? (u64) page_size : 4096;
+    u64 ini = (u64) ptr;
+    u64 end = ini + size;
+    u64 lo = (ini + page - 1) / page * page;
+    u64 hi = end / page * page;
+    if (lo < hi && madvise((void *) lo, hi - lo, MADV_DONTNEED) == 0) {
+        memset((void *) ini, 0, lo - ini);
+        memset((void *) hi, 0, end - hi);
+    } else {
+        memset(ptr, 0, size);
+    }
+}
+
 // Net
 // ---
 
-// Initializes a net.
-static inline void net_init(Net *net) {
-    // is that needed?
-    atomic_store(&net->itrs, 0);
-    atomic_store(&net->idle, 0);
+void net_free(Net *net);
+
+// Sets what may change between evaluations on the same net from `options`.
+void net_configure(Net *net, const EvaluationOptions *options) {
+    net->fuel = options->max_interactions ? options->max_interactions : UINT64_MAX;
+    net->cancel = options->cancellation;
+    net->policy = options->io_policy;
+    net->stdio = options->stdio;
+    net->stdin_buf = options->stdin_buf;
+    net->stdin_len = options->stdin_len;
+    net->stdin_at = 0;
+    net->output = options->output;
+    net->output_ctx = options->output_ctx;
+    net->progress = options->progress;
+    net->progress_ctx = options->progress_ctx;
+    net->progress_itrs = options->progress_interactions;
+    net->progress_ns = options->progress_interval_ms * 1000000;
+}
+
+// Allocates a net with the buffer sizes and thread count from `options`.
+// Returns NULL if any of the buffers can't be allocated.
+Net *net_new(const EvaluationOptions *options) {
+    Net *net = calloc(1, sizeof(Net));
+    if (net == NULL) return NULL;
+    net->node_len = options->node_len ? options->node_len : G_NODE_LEN;
+    // The last var index is the ROOT port, which is stored separately
+    net->vars_len = min(options->vars_len ? options->vars_len : G_VARS_LEN, get_val(ROOT));
+    net->tpc = options->threads ? options->threads : TPC;
+    net_configure(net, options);
+    // Each thread allocates from its own slice of the buffers
+    if (net->node_len / net->tpc < 2) net->tpc = net->node_len / 2;
+    if (net->vars_len / net->tpc < 2) net->tpc = net->vars_len / 2;
+    net->node_buf = mem_map((u64) net->node_len * sizeof(APair));
+    net->vars_buf = mem_map((u64) net->vars_len * sizeof(APort));
+    net->rbag_buf = mem_map((u64) net->tpc * RLEN * sizeof(APair));
+    if (net->node_buf == NULL || net->vars_buf == NULL || net->rbag_buf == NULL || !alloc_tms(net)) {
+        net_free(net);
+        return NULL;
+    }
+    return net;
+}
+
+// Closes the files an evaluation left open, and drops its captured output.
+void net_close_io(Net *net) {
+    for (u32 fd = 3; fd < 256; ++fd) {
+        if (net->files[fd] != NULL) fclose(net->files[fd]);
+        net->files[fd] = NULL;
+    }
+    string_buffer_free(&net->captured[0]);
+    string_buffer_free(&net->captured[1]);
+}
+
+// Clears what an evaluation left in a net, so it can run another. Threads
+// hand out slots in order, so only the ones they reached are zeroed.
+void net_reset(Net *net) {
+    u32 node_len = net->node_len / net->tpc;
+    u32 vars_len = net->vars_len / net->tpc;
+    for (u32 t = 0; t < net->tpc; ++t) {
+        TM *tm = net->tm[t];
+        mem_zero(&net->node_buf[t * node_len], min(tm->nput, node_len) * sizeof(APair));
+        mem_zero(&net->vars_buf[t * vars_len], min(tm->vput, vars_len) * sizeof(APort));
+        mem_zero(&net->rbag_buf[(u64) t * RLEN], tm->rput * sizeof(APair));
+        tm->itrs = 0;
+        tm->nput = 1;
+        tm->vput = 1;
+        tm->hput = 0;
+        tm->rput = 0;
+        tm->sidx = 0;
+        tm->live = 0;
+        tm->live_vars = 0;
+        memset(tm->rules, 0, sizeof(tm->rules));
+        tm->peak_rbag = 0;
+        tm->peak_node = 0;
+        tm->peak_vars = 0;
+        tm->load_lo = 0;
+        tm->load_hi = 0;
+        tm->load_live = 0;
+        tm->load_vars = 0;
+    }
+    net->root = 0;
+    net->itrs = 0;
+    net->idle = 0;
+    net->reached = 0;
+    net->barrier = 0;
+    net->peak_rbag = 0;
+    net->peak_node = 0;
+    net->peak_vars = 0;
+    net->deadline = 0;
+    net->halt = STATUS_COMPLETED;
+    net->fail_fid = 0;
+}
+
+// Frees a net and its thread memories.
+void net_free(Net *net) {
+    net_close_io(net);
+    free_tms(net);
+    mem_unmap(net->node_buf, (u64) net->node_len * sizeof(APair));
+    mem_unmap(net->vars_buf, (u64) net->vars_len * sizeof(APort));
+    mem_unmap(net->rbag_buf, (u64) net->tpc * RLEN * sizeof(APair));
+    free(net);
 }
 
 // Allocator
 // ---------
 
+// Halts the net, keeping the first reason given.
+static inline void halt(Net *net, u32 reason) {
+    u32 expected = STATUS_COMPLETED;
+    atomic_compare_exchange_strong_explicit(&net->halt, &expected, reason, memory_order_relaxed, memory_order_relaxed);
+}
+
+static inline bool is_halted(Net *net) {
+    return atomic_load_explicit(&net->halt, memory_order_relaxed) != STATUS_COMPLETED;
+}
+
+// Halts the net if it was cancelled or its deadline passed. Returns whether
+// it's halted, for any reason.
+static inline bool check_limits(Net *net) {
+    if (net->cancel && atomic_load_explicit(&net->cancel->cancelled, memory_order_relaxed)) {
+        halt(net, STATUS_CANCELLED);
+    } else if (net->deadline && time64() >= net->deadline) {
+        halt(net, STATUS_TIMED_OUT);
+    }
+    return is_halted(net);
+}
+
+// How many full slots allocators go through between checks of the limits
+#define ALLOC_CHECK_SLOTS 0x10000
+
+// Allocators go through the thread's slice of a buffer at most once. If it's
+// full, they halt the net with HALT_OOM, and return 0 (or fewer slots than
+// asked for). They do the same if the net is halted while they search.
+
 u32 node_alloc_1(Net *net, TM *tm, u32 *lps) {
+    u32 len = net->node_len / net->tpc;
     while (TRUE) {
-        u32 lc = tm->tid * (G_NODE_LEN / TPC) + (tm->nput % (G_NODE_LEN / TPC));
+        u32 lc = tm->tid * len + (tm->nput % len);
         Pair elem = net->node_buf[lc];
         tm->nput += 1;
         if (lc > 0 && elem == 0) {
             return lc;
         }
-        // FIXME: check this decently
-        if (++(*lps) >= G_NODE_LEN / TPC) printf("OOM\n");
+        if (++(*lps) >= len) {
+            halt(net, HALT_OOM);
+            return 0;
+        }
+        if (*lps % ALLOC_CHECK_SLOTS == 0 && check_limits(net)) {
+            return 0;
+        }
     }
 }
 
 u32 vars_alloc_1(Net *net, TM *tm, u32 *lps) {
+    u32 len = net->vars_len / net->tpc;
     while (TRUE) {
-        u32 lc = tm->tid * (G_NODE_LEN / TPC) + (tm->vput % (G_NODE_LEN / TPC));
+        u32 lc = tm->tid * len + (tm->vput % len);
         Port elem = net->vars_buf[lc];
         tm->vput += 1;
         if (lc > 0 && elem == 0) {
             return lc;
         }
-        // FIXME: check this decently
-        if (++(*lps) >= G_NODE_LEN / TPC) printf("OOM\n");
+        if (++(*lps) >= len) {
+            halt(net, HALT_OOM);
+            return 0;
+        }
+        if (*lps % ALLOC_CHECK_SLOTS == 0 && check_limits(net)) {
+            return 0;
+        }
     }
 }
 
 u32 node_alloc(Net *net, TM *tm, u32 num) {
+    u32 len = net->node_len / net->tpc;
     u32 got = 0;
     u32 lps = 0;
     while (got < num) {
-        u32 lc = tm->tid * (G_NODE_LEN / TPC) + (tm->nput % (G_NODE_LEN / TPC));
+        u32 lc = tm->tid * len + (tm->nput % len);
         Pair elem = net->node_buf[lc];
         tm->nput += 1;
         if (lc > 0 && elem == 0) {
             tm->nloc[got++] = lc;
+        } else if (++lps >= len) {
+            halt(net, HALT_OOM);
+            break;
+        } else if (lps % ALLOC_CHECK_SLOTS == 0 && check_limits(net)) {
+            break;
         }
-        // FIXME: check this decently
-        if (++lps >= G_NODE_LEN / TPC) printf("OOM\n");
     }
     return got;
 }
 
 u32 vars_alloc(Net *net, TM *tm, u32 num) {
+    u32 len = net->vars_len / net->tpc;
     u32 got = 0;
     u32 lps = 0;
     while (got < num) {
-        u32 lc = tm->tid * (G_NODE_LEN / TPC) + (tm->vput % (G_NODE_LEN / TPC));
+        u32 lc = tm->tid * len + (tm->vput % len);
         Port elem = net->vars_buf[lc];
         tm->vput += 1;
         if (lc > 0 && elem == 0) {
             tm->vloc[got++] = lc;
+        } else if (++lps >= len) {
+            halt(net, HALT_OOM);
+            break;
+        } else if (lps % ALLOC_CHECK_SLOTS == 0 && check_limits(net)) {
+            break;
         }
-        // FIXME: check this decently
-        if (++lps >= G_NODE_LEN / TPC) printf("OOM\n");
     }
     return got;
 }
 
 // Gets the necessary resources for an interaction. Returns success.
-static inline bool get_resources(Net *net, TM *tm, u8 need_rbag, u8 need_node, u8 need_vars) {
+static inline bool get_resources(Net *net, TM *tm, u32 need_rbag, u32 need_node, u32 need_vars) {
     u32 got_rbag = 0xFF; // FIXME: implement
     u32 got_node = node_alloc(net, tm, need_node);
     u32 got_vars = vars_alloc(net, tm, need_vars);
@@ -868,7 +1110,8 @@ static inline Port peek(Net *net, Port var) {
 }
 
 // Finds a variable's value.
-static inline Port enter(Net *net, Port var) {
+// Like `enter`, also subtracting the vars it takes from `*live_vars`.
+static inline Port enter_taking(Net *net, Port var, i64 *live_vars) {
     // While `B` is VAR: extend it (as an optimization)
     while (get_tag(var) == VAR) {
         // Takes the current `var` substitution as `val`
@@ -879,11 +1122,17 @@ static inline Port enter(Net *net, Port var) {
         }
         // Otherwise, delete `B` (we own both) and continue
         vars_take(net, get_val(var));
+        *live_vars -= 1;
         var = val;
     }
     return var;
 }
 
+static inline Port enter(Net *net, Port var) {
+    i64 live_vars = 0;
+    return enter_taking(net, var, &live_vars);
+}
+
 // Atomically Links `A ~ B`.
 static inline void link(Net *net, TM *tm, Port A, Port B) {
     //printf("LINK %s ~> %s\n", show_port(A).x, show_port(B).x);
@@ -904,7 +1153,7 @@ static inline void link(Net *net, TM *tm, Port A, Port B) {
         }
 
         // Extends B (as an optimization)
-        B = enter(net, B);
+        B = enter_taking(net, B, &tm->live_vars);
 
         // Since `A` is VAR: point `A ~> B`.
         if (TRUE) {
@@ -917,6 +1166,7 @@ static inline void link(Net *net, TM *tm, Port A, Port B) {
             //if (A_ == 0) { ? } // FIXME: must handle on the move-to-global algo
             // Otherwise, delete `A` (we own both) and link `A' ~ B`
             vars_take(net, get_val(A));
+            tm->live_vars -= 1;
             A = A_;
         }
     }
@@ -958,8 +1208,16 @@ static inline bool interact_call(Net *net, TM *tm, Port a, Port b, Book *book) {
     Def *def = &book->defs_buf[fid];
 
     // Copy Optimization.
-    if (def->safe && get_tag(b) == DUP) {
-        return interact_eras(net, tm, a, b);
+    if (get_tag(b) == DUP) {
+        if (def->safe) {
+            return interact_eras(net, tm, a, b);
+        }
+        // Cloning a non-affine REF can lead to unsound reductions: halt with an error
+        u32 expected = STATUS_COMPLETED;
+        if (atomic_compare_exchange_strong_explicit(&net->halt, &expected, HALT_FAIL, memory_order_relaxed, memory_order_relaxed)) {
+            net->fail_fid = fid;
+        }
+        return TRUE;
     }
 
     // Allocates needed nodes and vars.
@@ -978,6 +1236,8 @@ static inline bool interact_call(Net *net, TM *tm, Port a, Port b, Book *book) {
         node_create(net, tm->nloc[i], adjust_pair(net, tm, def->node_buf[i]));
         //printf("node_create nloc[%04x] %08llx\n", i-1, def->node_buf[i]);
     }
+    tm->live += def->node_len;
+    tm->live_vars += def->vars_len;
 
     // Links.
     for (u32 i = 0; i < def->rbag_len; ++i) {
@@ -1012,6 +1272,7 @@ static inline bool interact_eras(Net *net, TM *tm, Port a, Port b) {
     Pair B = node_exchange(net, get_val(b), 0);
     Port B1 = get_fst(B);
     Port B2 = get_snd(B);
+    tm->live -= 1;
 
     //if (B == 0) printf("[%04x] ERROR2: %s\n", tid, show_port(b).x);
 
@@ -1043,6 +1304,7 @@ static inline bool interact_anni(Net *net, TM *tm, Port a, Port b) {
     Pair B = node_take(net, get_val(b));
     Port B1 = get_fst(B);
     Port B2 = get_snd(B);
+    tm->live -= 2;
 
     //if (A == 0) printf("[%04x] ERROR3: %s\n", tid, show_port(a).x);
     //if (B == 0) printf("[%04x] ERROR4: %s\n", tid, show_port(b).x);
@@ -1089,6 +1351,8 @@ static inline bool interact_comm(Net *net, TM *tm, Port a, Port b) {
     node_create(net, tm->nloc[1], new_pair(new_port(VAR, tm->vloc[2]), new_port(VAR, tm->vloc[3])));
     node_create(net, tm->nloc[2], new_pair(new_port(VAR, tm->vloc[0]), new_port(VAR, tm->vloc[2])));
     node_create(net, tm->nloc[3], new_pair(new_port(VAR, tm->vloc[1]), new_port(VAR, tm->vloc[3])));
+    tm->live += 2;
+    tm->live_vars += 4;
 
     // Links.
     link_pair(net, tm, new_pair(new_port(get_tag(b), tm->nloc[0]), A1));
@@ -1117,13 +1381,14 @@ static inline bool interact_oper(Net *net, TM *tm, Port a, Port b) {
     Val av = get_val(a);
     Pair B = node_take(net, get_val(b));
     Port B1 = get_fst(B);
-    Port B2 = enter(net, get_snd(B));
+    Port B2 = enter_taking(net, get_snd(B), &tm->live_vars);
 
     // Performs operation.
     if (get_tag(B1) == NUM) {
         Val bv = get_val(B1);
         Numb cv = operate(av, bv);
         link_pair(net, tm, new_pair(new_port(NUM, cv), B2));
+        tm->live -= 1;
     } else {
         node_create(net, tm->nloc[0], new_pair(a, B2));
         link_pair(net, tm, new_pair(B1, new_port(OPR, tm->nloc[0])));
@@ -1158,6 +1423,7 @@ static inline bool interact_swit(Net *net, TM *tm, Port a, Port b) {
         node_create(net, tm->nloc[0], new_pair(new_port(ERA, 0), new_port(CON, tm->nloc[1])));
         node_create(net, tm->nloc[1], new_pair(new_port(NUM, new_u24(av - 1)), B2));
         link_pair(net, tm, new_pair(new_port(CON, tm->nloc[0]), B1));
+        tm->live += 1;
     }
 
     return TRUE;
@@ -1224,10 +1490,15 @@ static inline bool interact(Net *net, TM *tm, Book *book) {
         if (!success) {
             push_redex(net, tm, redex);
             return FALSE;
-            // Else, increments the interaction count.
-        } else if (rule != LINK) {
+            // Else, increments the interaction counts.
+        }
+        tm->rules[rule] += 1;
+        if (rule != LINK) {
             tm->itrs += 1;
         }
+        if (rbag_len(net, tm) > tm->peak_rbag) tm->peak_rbag = rbag_len(net, tm);
+        if (tm->live > tm->peak_node) tm->peak_node = tm->live;
+        if (tm->live_vars > tm->peak_vars) tm->peak_vars = tm->live_vars;
     }
 
     return TRUE;
@@ -1248,10 +1519,89 @@ static inline bool interact(Net *net, TM *tm, Book *book) {
 //tm->itrs = 0;
 //}
 
+// Checks if the interaction limit was reached. Other threads' counts are
+// only seen when they flush them, so with many threads the limit may be
+// overshot by a few thousand interactions.
+static inline bool out_of_fuel(Net *net, TM *tm) {
+    if (net->fuel == UINT64_MAX || atomic_load_explicit(&net->itrs, memory_order_relaxed) + tm->itrs < net->fuel) {
+        return FALSE;
+    }
+    halt(net, STATUS_FUEL_EXHAUSTED);
+    return TRUE;
+}
+
+// Reports progress if it's due. Returns FALSE if the host asked to halt.
+static bool report_progress(Net *net) {
+    u64 itrs = atomic_load_explicit(&net->itrs, memory_order_relaxed);
+    u64 now = time64();
+    bool due_itrs = net->progress_itrs && itrs >= net->next_itrs;
+    bool due_time = net->progress_ns && now >= net->next_time;
+    if (!due_itrs && !due_time) {
+        return TRUE;
+    }
+    net->next_itrs = itrs + net->progress_itrs;
+    net->next_time = now + net->progress_ns;
+    Progress progress = {itrs, 0, 0, 0, (now - net->start) / 1000000000.0};
+    i64 live = 0;
+    for (u32 t = 0; t < net->tpc; ++t) {
+        progress.rbag_lo += atomic_load_explicit(&net->tm[t]->load_lo, memory_order_relaxed);
+        progress.rbag_hi += atomic_load_explicit(&net->tm[t]->load_hi, memory_order_relaxed);
+        live += atomic_load_explicit(&net->tm[t]->load_live, memory_order_relaxed);
+    }
+    progress.live_nodes = live > 0 ? live : 0;
+    return net->progress(net->progress_ctx, &progress) != 0;
+}
+
+static inline void atomic_max(a64 *peak, i64 value) {
+    u64 seen = atomic_load_explicit(peak, memory_order_relaxed);
+    while (value > 0 && (u64) value > seen) {
+        if (atomic_compare_exchange_weak_explicit(peak, &seen, value, memory_order_relaxed, memory_order_relaxed)) {
+            break;
+        }
+    }
+}
+
+// Publishes the thread's load, and counts its peaks since the last call, along
+// with the other threads' loads, towards the net's.
+void publish_load(Net *net, TM *tm) {
+    atomic_store_explicit(&tm->load_lo, tm->rput, memory_order_relaxed);
+    atomic_store_explicit(&tm->load_hi, tm->hput, memory_order_relaxed);
+    atomic_store_explicit(&tm->load_live, tm->live, memory_order_relaxed);
+    atomic_store_explicit(&tm->load_vars, tm->live_vars, memory_order_relaxed);
+    i64 rbag = tm->peak_rbag;
+    i64 node = tm->peak_node;
+    i64 vars = tm->peak_vars;
+    for (u32 t = 0; t < net->tpc; ++t) {
+        if (t == tm->tid) continue;
+        rbag += atomic_load_explicit(&net->tm[t]->load_lo, memory_order_relaxed);
+        rbag += atomic_load_explicit(&net->tm[t]->load_hi, memory_order_relaxed);
+        node += atomic_load_explicit(&net->tm[t]->load_live, memory_order_relaxed);
+        vars += atomic_load_explicit(&net->tm[t]->load_vars, memory_order_relaxed);
+    }
+    atomic_max(&net->peak_rbag, rbag);
+    atomic_max(&net->peak_node, node);
+    atomic_max(&net->peak_vars, vars);
+    tm->peak_rbag = rbag_len(net, tm);
+    tm->peak_node = tm->live;
+    tm->peak_vars = tm->live_vars;
+}
+
+// Flushes the interaction count and load, reports progress, and checks the
+// deadline and cancellation.
+bool check_halt(Net *net, TM *tm) {
+    atomic_fetch_add_explicit(&net->itrs, tm->itrs, memory_order_relaxed);
+    tm->itrs = 0;
+    publish_load(net, tm);
+    if (net->progress && tm->tid == 0 && !report_progress(net)) {
+        halt(net, STATUS_CANCELLED);
+    }
+    return check_limits(net);
+}
+
 void evaluator(Net *net, TM *tm, Book *book) {
     // Initializes the global idle counter
-    atomic_store_explicit(&net->idle, TPC - 1, memory_order_relaxed);
-    sync_threads();
+    atomic_store_explicit(&net->idle, net->tpc - 1, memory_order_relaxed);
+    sync_threads(net);
 
     // Performs some interactions
     u32 tick = 0;
@@ -1266,6 +1616,10 @@ void evaluator(Net *net, TM *tm, Book *book) {
             // Update global idle counter
             if (!busy) atomic_fetch_sub_explicit(&net->idle, 1, memory_order_relaxed);
             busy = TRUE;
+            // Stop if the net was halted
+            if (is_halted(net) || (tick % 1024 == 0 && check_halt(net, tm)) || out_of_fuel(net, tm)) {
+                break;
+            }
             // Perform an interaction
             interact(net, tm, book);
             // If we have no redexes...
@@ -1275,8 +1629,8 @@ void evaluator(Net *net, TM *tm, Book *book) {
             busy = FALSE;
 
             //// Peeks a redex from target
-            u32 sid = (tm->tid - 1) % TPC;
-            u32 idx = sid * (G_RBAG_LEN / TPC) + (tm->sidx++);
+            u32 sid = (tm->tid + net->tpc - 1) % net->tpc;
+            u32 idx = sid * RLEN + (tm->sidx++);
 
             // Steal Parallel: this will only steal parallel redexes
 
@@ -1311,19 +1665,20 @@ void evaluator(Net *net, TM *tm, Book *book) {
 
             // Chill...
             sched_yield();
-            // Halt if all threads are idle
+            // Halt if all threads are idle, or if the net was halted
             if (tick % 256 == 0) {
-                if (atomic_load_explicit(&net->idle, memory_order_relaxed) == TPC) {
+                if (atomic_load_explicit(&net->idle, memory_order_relaxed) == net->tpc || check_halt(net, tm)) {
                     break;
                 }
             }
         }
     }
 
-    sync_threads();
+    sync_threads(net);
 
     atomic_fetch_add(&net->itrs, tm->itrs);
     tm->itrs = 0;
+    publish_load(net, tm);
 }
 
 // Normalizer
@@ -1344,7 +1699,7 @@ void *thread_func(void *arg) {
 
 // Sets the initial redex.
 void boot_redex(Net *net, Pair redex) {
-    net->vars_buf[get_val(ROOT)] = NONE;
+    vars_create(net, get_val(ROOT), NONE);
     net->rbag_buf[0] = redex;
 }
 
@@ -1352,21 +1707,21 @@ void boot_redex(Net *net, Pair redex) {
 // TODO: cache threads to avoid spawning overhead
 void normalize(Net *net, Book *book) {
     // Inits thread_arg objects
-    ThreadArg thread_arg[TPC];
-    for (u32 t = 0; t < TPC; ++t) {
+    ThreadArg thread_arg[net->tpc];
+    for (u32 t = 0; t < net->tpc; ++t) {
         thread_arg[t].net = net;
-        thread_arg[t].tm = tm[t];
+        thread_arg[t].tm = net->tm[t];
         thread_arg[t].book = book;
     }
 
     // Spawns the evaluation threads
-    pthread_t threads[TPC];
-    for (u32 t = 0; t < TPC; ++t) {
+    pthread_t threads[net->tpc];
+    for (u32 t = 0; t < net->tpc; ++t) {
         pthread_create(&threads[t], NULL, thread_func, &thread_arg[t]);
     }
 
     // Wait for the threads to finish
-    for (u32 t = 0; t < TPC; ++t) {
+    for (u32 t = 0; t < net->tpc; ++t) {
         pthread_join(threads[t], NULL);
     }
 }
@@ -1375,7 +1730,7 @@ void normalize(Net *net, Book *book) {
 Port expand(Net *net, Book *book, Port port) {
     Port old = vars_load(net, get_val(ROOT));
     Port got = peek(net, port);
-    while (get_tag(got) == REF) {
+    while (get_tag(got) == REF && !check_halt(net, net->tm[0])) {
         boot_redex(net, new_pair(new_port(REF, get_val(got)), ROOT));
         normalize(net, book);
         got = peek(net, vars_load(net, get_val(ROOT)));
@@ -1384,6 +1739,46 @@ Port expand(Net *net, Book *book, Port port) {
     return got;
 }
 
+// Program Stdio
+// -------------
+
+// With STDIO_CAPTURE, a program's stdio is its own: `stdin` reads from the
+// host's buffer, and `stdout` and `stderr` are captured or streamed to the
+// host. Otherwise, they're the process' own.
+
+// Reads a byte like `fgetc`.
+int net_getc(Net *net, FILE *fp) {
+    if (fp == stdin && net->stdio == STDIO_CAPTURE) {
+        return net->stdin_at < net->stdin_len ? net->stdin_buf[net->stdin_at++] : EOF;
+    }
+    return fgetc(fp);
+}
+
+// Writes bytes like `fwrite`, returning whether all were written.
+bool net_write(Net *net, FILE *fp, const char *buf, u64 len) {
+    if ((fp == stdout || fp == stderr) && net->stdio == STDIO_CAPTURE) {
+        u32 fd = fp == stdout ? 1 : 2;
+        if (net->output != NULL) {
+            net->output(net->output_ctx, fd, (const u8 *) buf, len);
+        } else {
+            string_buffer_write_bytes(&net->captured[fd - 1], buf, len);
+        }
+        return TRUE;
+    }
+    return fwrite(buf, 1, len, fp) == len;
+}
+
+// Reports a failed IO call on the program's stderr.
+void io_error(Net *net, const char *format, ...) {
+    char message[512];
+    va_list args;
+    va_start(args, format);
+    i32 len = vsnprintf(message, sizeof(message), format, args);
+    va_end(args);
+    if (len < 0) return;
+    net_write(net, stderr, message, min(len, sizeof(message) - 1));
+}
+
 // Readback
 // --------
 
@@ -1421,9 +1816,7 @@ Ctr readback_ctr(Net *net, Book *book, Port port) {
     return ctr;
 }
 
-// Converts a UTF-32 (truncated to 24 bits) string to a Port.
-// Since unicode scalars can fit in 21 bits, HVM's u24
-// integers can contain any unicode scalar value.
+// Reads back a λ-Encoded string as bytes, keeping the low byte of each cell.
 // Encoding:
 // - λt (t NIL)
 // - λt (((t CONS) head) tail)
@@ -1453,7 +1846,7 @@ Str readback_str(Net *net, Book *book, Port port) {
                 if (ctr.args_len != 2) break;
                 if (get_tag(ctr.args_buf[0]) != NUM) break;
                 if (str.text_len >= 256) {
-                    printf("ERROR: for now, HVM can only readback strings of length <256.");
+                    io_error(net, "ERROR: for now, HVM can only readback strings of length <256.\n");
                     break;
                 }
                 //printf("reading chr %d\n", get_u24(get_val(ctr.args_buf[0])));
@@ -1475,10 +1868,10 @@ Str readback_str(Net *net, Book *book, Port port) {
 /// Should only be called within `inject_str`, as a previous call
 /// to `get_resources` is expected.
 Port inject_nil(Net *net) {
-    u32 v1 = tm[0]->vloc[0];
+    u32 v1 = net->tm[0]->vloc[0];
 
-    u32 n1 = tm[0]->nloc[0];
-    u32 n2 = tm[0]->nloc[1];
+    u32 n1 = net->tm[0]->nloc[0];
+    u32 n2 = net->tm[0]->nloc[1];
 
     vars_create(net, v1, NONE);
     Port var = new_port(VAR, v1);
@@ -1492,16 +1885,13 @@ Port inject_nil(Net *net) {
 /// Returns a λ-Encoded Ctr for a CONS: λt (((t CONS) head) tail)
 /// Should only be called within `inject_str`, as a previous call
 /// to `get_resources` is expected.
-/// The `char_idx` parameter is used to offset the vloc and nloc
-/// allocations, otherwise they would conflict with each other on
-/// subsequent calls.
-Port inject_cons(Net *net, Port head, Port tail, u32 char_idx) {
-    u32 v1 = tm[0]->vloc[1 + char_idx];
+Port inject_cons(Net *net, Port head, Port tail) {
+    u32 v1 = net->tm[0]->vloc[0];
 
-    u32 n1 = tm[0]->nloc[2 + char_idx * 4 + 0];
-    u32 n2 = tm[0]->nloc[2 + char_idx * 4 + 1];
-    u32 n3 = tm[0]->nloc[2 + char_idx * 4 + 2];
-    u32 n4 = tm[0]->nloc[2 + char_idx * 4 + 3];
+    u32 n1 = net->tm[0]->nloc[0];
+    u32 n2 = net->tm[0]->nloc[1];
+    u32 n3 = net->tm[0]->nloc[2];
+    u32 n4 = net->tm[0]->nloc[3];
 
     vars_create(net, v1, NONE);
     Port var = new_port(VAR, v1);
@@ -1514,27 +1904,30 @@ Port inject_cons(Net *net, Port head, Port tail, u32 char_idx) {
     return new_port(CON, n4);
 }
 
-// Converts a UTF-32 (truncated to 24 bits) string to a Port.
-// Since unicode scalars can fit in 21 bits, HVM's u24
-// integers can contain any unicode scalar value.
+// Converts a byte string to a Port, with a cell per byte.
 // Encoding:
 // - λt (t NIL)
 // - λt (((t CONS) head) tail)
 Port inject_str(Net *net, Str *str) {
-    // Allocate all resources up front:
+    // Allocates resources as it goes, as the thread can't hold locations for
+    // more than 0xFFF nodes at once:
     // - NIL needs  2 nodes & 1 var
     // - CONS needs 4 nodes & 1 var
     u32 len = str->text_len;
-    if (!get_resources(net, tm[0], 0, 2 + 4 * len, 1 + len)) {
-        printf("inject_str: failed to get resources\n");
+    if (!get_resources(net, net->tm[0], 0, 2, 1)) {
+        io_error(net, "inject_str: failed to get resources\n");
         return new_port(ERA, 0);
     }
 
     Port port = inject_nil(net);
 
     for (u32 i = 0; i < len; i++) {
-        Port chr = new_port(NUM, new_u24(str->text_buf[len - i - 1]));
-        port = inject_cons(net, chr, port, i);
+        if (!get_resources(net, net->tm[0], 0, 4, 1)) {
+            io_error(net, "inject_str: failed to get resources\n");
+            return new_port(ERA, 0);
+        }
+        Port chr = new_port(NUM, new_u24((u8) str->text_buf[len - i - 1]));
+        port = inject_cons(net, chr, port);
     }
 
     return port;
@@ -1593,21 +1986,108 @@ void read_img(Net *net, Port port, u32 width, u32 height, u32 *buffer) {
     }
 }
 
+// IO Policy
+// ---------
+
+// Whether the policy lets programs call `name`.
+bool policy_allows_ffn(const IoPolicy *policy, const char *name) {
+    if (policy == NULL) return FALSE;
+    if (policy->unrestricted) return TRUE;
+    for (u64 i = 0; i < policy->ffns_len; ++i) {
+        if (strcmp(policy->ffns[i], name) == 0) return TRUE;
+    }
+    return FALSE;
+}
+
+// Whether `path` is `dir` or inside it. Both must be canonical.
+bool path_is_inside(const char *path, const char *dir) {
+    size_t len = strlen(dir);
+    if (len > 0 && dir[len - 1] == '/') len--;
+    return strncmp(path, dir, len) == 0 && (path[len] == '\0' || path[len] == '/');
+}
+
+// Whether the policy lets programs open `path`, for writing or not. Like
+// `IoPolicy::allows_path` in hvm.rs, files that don't exist yet are resolved
+// through their parent directory, so links can't lead out of the allowed ones.
+// The path to open is written to `resolved`, which holds PATH_MAX bytes: the
+// resolved one, so a link swapped in afterwards isn't followed, or `path` as
+// is when the policy is unrestricted.
+bool policy_allows_path(const IoPolicy *policy, const char *path, bool write, char *resolved) {
+    if (policy == NULL) return FALSE;
+    if (policy->unrestricted) {
+        if (strlen(path) >= PATH_MAX) return FALSE;
+        strcpy(resolved, path);
+        return TRUE;
+    }
+    if (realpath(path, resolved) == NULL) {
+        // Splits the path into its parent and file name
+        const char *slash = strrchr(path, '/');
+        const char *name = slash ? slash + 1 : path;
+        if (name[0] == '\0' || strcmp(name, ".") == 0 || strcmp(name, "..") == 0) return FALSE;
+        char parent[PATH_MAX];
+        size_t parent_len = slash == NULL ? 1 : slash == path ? 1 : (size_t) (slash - path);
+        if (parent_len >= sizeof(parent)) return FALSE;
+        memcpy(parent, slash == NULL ? "." : path, parent_len);
+        parent[parent_len] = '\0';
+        if (realpath(parent, resolved) == NULL) return FALSE;
+        size_t len = strlen(resolved);
+        if (len + 1 + strlen(name) >= PATH_MAX) return FALSE;
+        snprintf(resolved + len, PATH_MAX - len, "%s%s", resolved[len - 1] == '/' ? "" : "/", name);
+    }
+    // The innermost dir decides, so a read-only dir can be nested in a writable one
+    const IoDirectory *dir = NULL;
+    for (u64 i = 0; i < policy->dirs_len; ++i) {
+        if (path_is_inside(resolved, policy->dirs[i].path) && (dir == NULL || strlen(policy->dirs[i].path) > strlen(dir->path))) {
+            dir = &policy->dirs[i];
+        }
+    }
+    return dir != NULL && (!write || dir->writable);
+}
+
+// Converts an fopen mode to open(2) flags. Like `open_options` in hvm.rs, it
+// takes "r", "w" or "a", then any of "+", "b" and, after "w", "x", each at
+// most once. Returns -1 for any other mode.
+int open_mode_flags(const char *mode) {
+    int flags;
+    switch (mode[0]) {
+        case 'r': flags = O_RDONLY; break;
+        case 'w': flags = O_WRONLY | O_CREAT | O_TRUNC; break;
+        case 'a': flags = O_WRONLY | O_CREAT | O_APPEND; break;
+        default: return -1;
+    }
+    bool plus = FALSE;
+    bool binary = FALSE;
+    bool excl = FALSE;
+    for (const char *c = mode + 1; *c != '\0'; ++c) {
+        if (*c == '+' && !plus) {
+            plus = TRUE;
+            flags = (flags & ~O_ACCMODE) | O_RDWR;
+        } else if (*c == 'b' && !binary) {
+            binary = TRUE;
+        } else if (*c == 'x' && !excl && mode[0] == 'w') {
+            excl = TRUE;
+            flags |= O_EXCL;
+        } else {
+            return -1;
+        }
+    }
+    return flags;
+}
+
 // Primitive IO Fns
 // -----------------
 
-// Open file pointers. Indices into this array
-// are used as "file descriptors".
+// Open files are kept per net, in `Net.files`. Indices into
+// that array are used as "file descriptors".
 // Indices 0 1 and 2 are reserved.
 // - 0 -> stdin
 // - 1 -> stdout
 // - 2 -> stderr
-static FILE *FILE_POINTERS[256];
 
 // Converts a NUM port (file descriptor) to file pointer.
-FILE *readback_file(Port port) {
+FILE *readback_file(Net *net, Port port) {
     if (get_tag(port) != NUM) {
-        fprintf(stderr, "non-num where file descriptor was expected: %i\n", get_tag(port));
+        io_error(net, "non-num where file descriptor was expected: %i\n", get_tag(port));
         return NULL;
     }
 
@@ -1617,9 +2097,9 @@ FILE *readback_file(Port port) {
     if (idx == 1) return stdout;
     if (idx == 2) return stderr;
 
-    FILE *fp = FILE_POINTERS[idx];
+    FILE *fp = idx < 256 ? net->files[idx] : NULL;
     if (fp == NULL) {
-        fprintf(stderr, "invalid file descriptor\n");
+        io_error(net, "invalid file descriptor\n");
         return NULL;
     }
 
@@ -1628,42 +2108,44 @@ FILE *readback_file(Port port) {
 
 // Reads a single char from `argm`.
 Port io_read_char(Net *net, Book *book, Port argm) {
-    FILE *fp = readback_file(peek(net, argm));
+    FILE *fp = readback_file(net, peek(net, argm));
     if (fp == NULL) {
         return new_port(ERA, 0);
     }
 
-    /// Read a string.
+    /// Read a string, which is empty at the end of the input
     Str str;
 
-    str.text_buf[0] = fgetc(fp);
-    str.text_buf[1] = 0;
-    str.text_len = 1;
+    int chr = net_getc(net, fp);
+    str.text_buf[0] = (char) chr;
+    str.text_len = chr == EOF ? 0 : 1;
+    str.text_buf[str.text_len] = 0;
 
     return inject_str(net, &str);
 }
 
 // Reads from `argm` at most 255 characters or until a newline is seen.
 Port io_read_line(Net *net, Book *book, Port argm) {
-    FILE *fp = readback_file(peek(net, argm));
+    FILE *fp = readback_file(net, peek(net, argm));
     if (fp == NULL) {
-        fprintf(stderr, "io_read_line: invalid file descriptor\n");
+        io_error(net, "io_read_line: invalid file descriptor\n");
         return new_port(ERA, 0);
     }
 
     /// Read a string.
     Str str;
 
-    if (fgets(str.text_buf, sizeof(str.text_buf), fp) == NULL) {
-        fprintf(stderr, "io_read_line: failed to read\n");
+    // Reads up to a newline, which is dropped
+    u32 len = 0;
+    int chr = 0;
+    while (len < sizeof(str.text_buf) - 1 && (chr = net_getc(net, fp)) != EOF && chr != '\n') {
+        str.text_buf[len++] = (char) chr;
     }
-    str.text_len = strlen(str.text_buf);
-
-    // Strip any trailing newline.
-    if (str.text_len > 0 && str.text_buf[str.text_len - 1] == '\n') {
-        str.text_buf[str.text_len] = 0;
-        str.text_len--;
+    if (len == 0 && chr == EOF) {
+        io_error(net, "io_read_line: failed to read\n");
     }
+    str.text_buf[len] = 0;
+    str.text_len = len;
 
     // Convert it to a port.
     return inject_str(net, &str);
@@ -1674,42 +2156,74 @@ Port io_read_line(Net *net, Book *book, Port argm) {
 // file name and mode as strings.
 Port io_open_file(Net *net, Book *book, Port argm) {
     if (get_tag(peek(net, argm)) != CON) {
-        fprintf(stderr, "io_open_file: expected tuple\n");
+        io_error(net, "io_open_file: expected tuple\n");
         return new_port(ERA, 0);
     }
 
-    Pair args = node_load(net, get_val(argm));
+    Pair args = node_load(net, get_val(peek(net, argm)));
     Str name = readback_str(net, book, get_fst(args));
     Str mode = readback_str(net, book, get_snd(args));
 
-    for (u32 fd = 3; fd < sizeof(FILE_POINTERS); fd++) {
-        if (FILE_POINTERS[fd] == NULL) {
-            FILE_POINTERS[fd] = fopen(name.text_buf, mode.text_buf);
+    // Any mode but "r" may write or create the file
+    bool write = strpbrk(mode.text_buf, "wa+") != NULL;
+    char path[PATH_MAX];
+    if (!policy_allows_path(net->policy, name.text_buf, write, path)) {
+        io_error(net, "io_open_file: permission denied: %s\n", name.text_buf);
+        return new_port(ERA, 0);
+    }
+
+    u32 open_files = 0;
+    for (u32 fd = 3; fd < 256; fd++) {
+        open_files += net->files[fd] != NULL;
+    }
+    if (!net->policy->unrestricted && open_files >= net->policy->max_files) {
+        io_error(net, "io_open_file: too many open files\n");
+        return new_port(ERA, 0);
+    }
+
+    for (u32 fd = 3; fd < 256; fd++) {
+        if (net->files[fd] == NULL) {
+            int flags = open_mode_flags(mode.text_buf);
+            if (flags < 0) {
+                io_error(net, "io_open_file: invalid mode: %s\n", mode.text_buf);
+                return new_port(ERA, 0);
+            }
+            // The checked path is opened, without following a link put there since
+            if (!net->policy->unrestricted) {
+                flags |= O_NOFOLLOW;
+            }
+            int os_fd = open(path, flags, 0666);
+            FILE *fp = os_fd < 0 ? NULL : fdopen(os_fd, mode.text_buf);
+            if (fp == NULL) {
+                if (os_fd >= 0) close(os_fd);
+                io_error(net, "io_open_file: failed to open %s\n", name.text_buf);
+                return new_port(ERA, 0);
+            }
+            net->files[fd] = fp;
             return new_port(NUM, new_u24(fd));
         }
     }
 
-    fprintf(stderr, "io_open_file: too many open files\n");
+    io_error(net, "io_open_file: too many open files\n");
 
     return new_port(ERA, 0);
 }
 
 // Closes a file, reclaiming the file descriptor.
 Port io_close_file(Net *net, Book *book, Port argm) {
-    FILE *fp = readback_file(peek(net, argm));
-    if (fp == NULL) {
-        fprintf(stderr, "io_close_file: failed to close\n");
+    FILE *fp = readback_file(net, peek(net, argm));
+    if (fp == NULL || fp == stdin || fp == stdout || fp == stderr) {
+        io_error(net, "io_close_file: failed to close\n");
         return new_port(ERA, 0);
     }
 
+    net->files[get_u24(get_val(peek(net, argm)))] = NULL;
     int err = fclose(fp) != 0;
     if (err != 0) {
-        fprintf(stderr, "io_close_file: failed to close: %i\n", err);
+        io_error(net, "io_close_file: failed to close: %i\n", err);
         return new_port(ERA, 0);
     }
 
-    FILE_POINTERS[get_u24(get_val(argm))] = NULL;
-
     return new_port(ERA, 0);
 }
 
@@ -1718,21 +2232,21 @@ Port io_close_file(Net *net, Book *book, Port argm) {
 // file descriptor and string to write.
 Port io_write(Net *net, Book *book, Port argm) {
     if (get_tag(peek(net, argm)) != CON) {
-        fprintf(stderr, "io_write: expected tuple, but got %u\n", get_tag(peek(net, argm)));
+        io_error(net, "io_write: expected tuple, but got %u\n", get_tag(peek(net, argm)));
         return new_port(ERA, 0);
     }
 
     Pair args = node_load(net, get_val(argm));
-    FILE *fp = readback_file(peek(net, get_fst(args)));
+    FILE *fp = readback_file(net, peek(net, get_fst(args)));
     Str str = readback_str(net, book, get_snd(args));
 
     if (fp == NULL) {
-        fprintf(stderr, "io_write: invalid file descriptor\n");
+        io_error(net, "io_write: invalid file descriptor\n");
         return new_port(ERA, 0);
     }
 
-    if (fputs(str.text_buf, fp) == EOF) {
-        fprintf(stderr, "io_write: failed to write\n");
+    if (!net_write(net, fp, str.text_buf, str.text_len)) {
+        io_error(net, "io_write: failed to write\n");
     }
 
     return new_port(ERA, 0);
@@ -1748,12 +2262,18 @@ Port io_get_time(Net *net, Book *book, Port argm) {
     u32 time_lo = (u32) (time_ns & 0xFFFFFFF);
     // Allocate a node to store the time
     u32 lps = 0;
-    u32 loc = node_alloc_1(net, tm[0], &lps);
+    u32 loc = node_alloc_1(net, net->tm[0], &lps);
+    if (loc == 0) {
+        return new_port(ERA, 0);
+    }
     node_create(net, loc, new_pair(new_port(NUM, new_u24(time_hi)), new_port(NUM, new_u24(time_lo))));
     // Return the encoded time
     return new_port(CON, loc);
 }
 
+// How long SLEEP sleeps between checks of the deadline and cancellation
+#define SLEEP_SLICE_NS 10000000
+
 // Sleeps.
 // `argm` is a tuple (CON node) of the high and low
 // 24 bits for a 48-bit duration in nanoseconds.
@@ -1765,11 +2285,19 @@ Port io_sleep(Net *net, Book *book, Port argm) {
     u32 dur_lo = get_u24(get_val(get_snd(dur_node)));
     // Combine into a 48-bit duration in nanoseconds
     u64 dur_ns = (((u64) dur_hi) << 24) | dur_lo;
-    // Sleep for the specified duration
-    struct timespec ts;
-    ts.tv_sec = dur_ns / 1000000000;
-    ts.tv_nsec = dur_ns % 1000000000;
-    nanosleep(&ts, NULL);
+    // Sleep for the specified duration, in slices of SLEEP_SLICE_NS, stopping
+    // early if the net halts so the deadline and cancellation still apply
+    u64 end = time64() + dur_ns;
+    while (!check_limits(net)) {
+        u64 now = time64();
+        if (now >= end) break;
+        u64 left = end - now;
+        if (left > SLEEP_SLICE_NS) left = SLEEP_SLICE_NS;
+        struct timespec ts;
+        ts.tv_sec = left / 1000000000;
+        ts.tv_nsec = left % 1000000000;
+        nanosleep(&ts, NULL);
+    }
     // Return an eraser
     return new_port(ERA, 0);
 }
@@ -1800,7 +2328,7 @@ Port io_sleep(Net *net, Book *book, Port argm) {
 //// Initialize SDL if it hasn't been initialized
 //if (SDL_WasInit(SDL_INIT_VIDEO) == 0) {
 //if (SDL_Init(SDL_INIT_VIDEO) < 0) {
-//fprintf(stderr, "SDL could not initialize! SDL Error: %s\n", SDL_GetError());
+//io_error(net, "SDL could not initialize! SDL Error: %s\n", SDL_GetError());
 //return;
 //}
 //}
@@ -1808,14 +2336,14 @@ Port io_sleep(Net *net, Book *book, Port argm) {
 //if (window == NULL) {
 //window = SDL_CreateWindow("SDL Window", SDL_WINDOWPOS_UNDEFINED, SDL_WINDOWPOS_UNDEFINED, width, height, SDL_WINDOW_SHOWN);
 //if (window == NULL) {
-//fprintf(stderr, "Window could not be created! SDL Error: %s\n", SDL_GetError());
+//io_error(net, "Window could not be created! SDL Error: %s\n", SDL_GetError());
 //return;
 //}
 //renderer = SDL_CreateRenderer(window, -1, SDL_RENDERER_ACCELERATED | SDL_RENDERER_PRESENTVSYNC);
 //if (renderer == NULL) {
 //SDL_DestroyWindow(window);
 //window = NULL;
-//fprintf(stderr, "Renderer could not be created! SDL Error: %s\n", SDL_GetError());
+//io_error(net, "Renderer could not be created! SDL Error: %s\n", SDL_GetError());
 //return;
 //}
 //}
@@ -1823,13 +2351,13 @@ Port io_sleep(Net *net, Book *book, Port argm) {
 //if (texture == NULL) {
 //texture = SDL_CreateTexture(renderer, SDL_PIXELFORMAT_ARGB8888, SDL_TEXTUREACCESS_STREAMING, width, height);
 //if (texture == NULL) {
-//fprintf(stderr, "Texture could not be created! SDL Error: %s\n", SDL_GetError());
+//io_error(net, "Texture could not be created! SDL Error: %s\n", SDL_GetError());
 //return;
 //}
 //}
 //// Update the texture with the new buffer
 //if (SDL_UpdateTexture(texture, NULL, buffer, width * sizeof(uint32_t)) < 0) {
-//fprintf(stderr, "Texture could not be updated! SDL Error: %s\n", SDL_GetError());
+//io_error(net, "Texture could not be updated! SDL Error: %s\n", SDL_GetError());
 //return;
 //}
 //// Clear the renderer
@@ -1854,7 +2382,7 @@ Port io_sleep(Net *net, Book *book, Port argm) {
 //// Create a buffer
 //uint32_t *buffer = (uint32_t *)malloc(width * height * sizeof(uint32_t));
 //if (buffer == NULL) {
-//fprintf(stderr, "Failed to allocate memory for buffer\n");
+//io_error(net, "Failed to allocate memory for buffer\n");
 //return 1;
 //}
 //// Initialize buffer to a dark blue background
@@ -1883,15 +2411,97 @@ Port io_sleep(Net *net, Book *book, Port argm) {
 //}
 //#endif
 
+// Host Functions
+// --------------
+
+// Declared here for use in `call_host`.
+char *show_tree(Net *net, Book *book, Port port);
+
+// Adjusts a port of a host value to the locations it was copied to.
+static inline Port adjust_host_port(const u32 *nloc, const u32 *vloc, Port port) {
+    if (is_nod(port)) return new_port(get_tag(port), nloc[get_val(port)]);
+    if (is_var(port)) return new_port(get_tag(port), vloc[get_val(port)]);
+    return port;
+}
+
+// Copies a host value into the net, like a call interaction. Locations are
+// taken 0xFFF at a time, as that's all a TM holds, so values of any size fit
+// as long as the net has room.
+Port inject_host_value(Net *net, const HostValue *value) {
+    TM *tm = net->tm[0];
+    u32 *nloc = (u32 *) malloc(sizeof(u32) * (value->node_len + 1));
+    u32 *vloc = (u32 *) malloc(sizeof(u32) * (value->vars_len + 1));
+    u32 got_node = 0;
+    u32 got_vars = 0;
+    bool ok = nloc != NULL && vloc != NULL;
+    while (ok && (got_node < value->node_len || got_vars < value->vars_len)) {
+        u32 need_node = value->node_len - got_node < 0xFFF ? value->node_len - got_node : 0xFFF;
+        u32 need_vars = value->vars_len - got_vars < 0xFFF ? value->vars_len - got_vars : 0xFFF;
+        ok = get_resources(net, tm, 0, need_node, need_vars);
+        if (!ok) {
+            break;
+        }
+        // Claims them right away, so later chunks can't take them again
+        for (u32 i = 0; i < need_node; ++i) {
+            node_create(net, tm->nloc[i], new_pair(new_port(ERA, 0), new_port(ERA, 0)));
+            nloc[got_node++] = tm->nloc[i];
+        }
+        for (u32 i = 0; i < need_vars; ++i) {
+            vars_create(net, tm->vloc[i], NONE);
+            vloc[got_vars++] = tm->vloc[i];
+        }
+    }
+    Port root = new_port(ERA, 0);
+    if (ok) {
+        for (u32 i = 0; i < value->node_len; ++i) {
+            Pair pair = value->node_buf[i];
+            node_create(net, nloc[i], new_pair(adjust_host_port(nloc, vloc, get_fst(pair)), adjust_host_port(nloc, vloc, get_snd(pair))));
+        }
+        root = adjust_host_port(nloc, vloc, value->root);
+    } else {
+        io_error(net, "inject_host_value: failed to get resources\n");
+    }
+    free(nloc);
+    free(vloc);
+    return root;
+}
+
+// Calls a host function, returning FALSE if there's none named `name`.
+bool call_host(Net *net, Book *book, const HostFFns *host, const char *name, Port argm, Port *ret) {
+    if (host == NULL) {
+        return FALSE;
+    }
+    char *text = show_tree(net, book, argm);
+    HostValue *value = NULL;
+    char error[256] = {0};
+    u32 found = host->call(host->ctx, name, text, &value, error, sizeof(error));
+    free(text);
+    if (!found) {
+        return FALSE;
+    }
+    if (error[0] != '\0') {
+        io_error(net, "%s\n", error);
+    }
+    *ret = new_port(ERA, 0);
+    if (value != NULL) {
+        *ret = inject_host_value(net, value);
+        host->free(value);
+    }
+    return TRUE;
+}
+
 // Monadic IO Evaluator
 // ---------------------
 
 // Runs an IO computation.
-void do_run_io(Net *net, Book *book, Port port) {
+void do_run_io(Net *net, Book *book, const HostFFns *host, Port port) {
     // IO loop
     while (TRUE) {
         // Normalizes the net
         normalize(net, book);
+        if (is_halted(net)) {
+            break;
+        }
 
         // Reads the λ-Encoded Ctr
         Ctr ctr = readback_ctr(net, book, peek(net, port));
@@ -1914,7 +2524,10 @@ void do_run_io(Net *net, Book *book, Port port) {
                 Port argm = ctr.args_buf[2];
                 Port cont = ctr.args_buf[3];
                 u32 lps = 0;
-                u32 loc = node_alloc_1(net, tm[0], &lps);
+                u32 loc = node_alloc_1(net, net->tm[0], &lps);
+                if (loc == 0) {
+                    break;
+                }
                 Port ret = new_port(ERA, 0);
                 FFn *ffn = NULL;
                 // FIXME: optimize this linear search
@@ -1924,17 +2537,19 @@ void do_run_io(Net *net, Book *book, Port port) {
                         break;
                     }
                 }
-                if (ffn == NULL) {
+                if (!policy_allows_ffn(net->policy, func.text_buf)) {
+                    io_error(net, "io_call: permission denied: %s\n", func.text_buf);
+                } else if (ffn != NULL) {
+                    ret = ffn->func(net, book, argm);
+                } else if (!call_host(net, book, host, func.text_buf, argm, &ret)) {
                     break;
                 }
-                ret = ffn->func(net, book, argm);
                 node_create(net, loc, new_pair(ret, ROOT));
                 boot_redex(net, new_pair(new_port(CON, loc), cont));
                 port = ROOT;
                 continue;
             }
             case IO_DONE: {
-                printf("DONE\n");
                 break;
             }
         }
@@ -1957,41 +2572,172 @@ void book_init(Book *book) {
     book->ffns_buf[6] = (FFn) {"SLEEP", io_sleep};
 }
 
-void book_load(Book *book, u32 *buf) {
+// Reads a book written by `Book::to_buffer`, in either format (see hvm.rs).
+// Returns NULL on success, or why the buffer can't be loaded.
+
+typedef struct {
+    const u8 *buf;
+    u64 len;
+    u64 at;
+    bool ok; // cleared on reading past the end
+} BookReader;
+
+static void read_raw(BookReader *r, void *out, u64 size) {
+    if (!r->ok || r->len - r->at < size) {
+        r->ok = FALSE;
+        memset(out, 0, size);
+        return;
+    }
+    memcpy(out, r->buf + r->at, size);
+    r->at += size;
+}
+
+static u32 read_u32_ne(BookReader *r) {
+    u32 val;
+    read_raw(r, &val, 4);
+    return val;
+}
+
+static u64 read_u64_ne(BookReader *r) {
+    u64 val;
+    read_raw(r, &val, 8);
+    return val;
+}
+
+static u64 read_le(BookReader *r, u32 size) {
+    u8 bytes[8];
+    read_raw(r, bytes, size);
+    u64 val = 0;
+    for (u32 i = 0; i < size; ++i) {
+        val |= (u64) bytes[i] << (8 * i);
+    }
+    return val;
+}
+
+static u64 read_varint(BookReader *r) {
+    u64 val = 0;
+    for (u32 shift = 0; shift < 64; shift += 7) {
+        u8 byte = (u8) read_le(r, 1);
+        val |= (u64) (byte & 0x7F) << shift;
+        if ((byte & 0x80) == 0) return val;
+    }
+    r->ok = FALSE;
+    return 0;
+}
+
+// 32-bit FNV-1a, as computed by `checksum` in hvm.rs
+u32 book_checksum(const u8 *buf, u64 len) {
+    u32 hash = 0x811C9DC5;
+    for (u64 i = 0; i < len; ++i) {
+        hash ^= buf[i];
+        hash *= 0x01000193;
+    }
+    return hash;
+}
+
+static const char *book_load_v1(Book *book, BookReader *r) {
     // Reads defs_len
-    book->defs_len = *buf++;
+    book->defs_len = read_u32_ne(r);
+    if (book->defs_len > 0x4000) return "Too many definitions for the C runtime";
 
     // Parses each def
-    for (u32 i = 0; i < book->defs_len; ++i) {
+    for (u32 i = 0; i < book->defs_len && r->ok; ++i) {
         // Reads fid
-        u32 fid = *buf++;
+        u32 fid = read_u32_ne(r);
+        if (fid != i) return "Definition stored out of order";
 
         // Gets def
         Def *def = &book->defs_buf[fid];
 
         // Reads name
-        memcpy(def->name, buf, 256);
-        buf += 64;
+        read_raw(r, def->name, 256);
+        def->name[255] = '\0';
 
         // Reads safe flag
-        def->safe = *buf++;
+        def->safe = read_u32_ne(r);
 
         // Reads lengths
-        def->rbag_len = *buf++;
-        def->node_len = *buf++;
-        def->vars_len = *buf++;
+        def->rbag_len = read_u32_ne(r);
+        def->node_len = read_u32_ne(r);
+        def->vars_len = read_u32_ne(r);
+        if (def->rbag_len > 0xFFF || def->node_len > 0xFFF) return "Definition too large for the C runtime";
 
         // Reads root
-        def->root = *buf++;
+        def->root = read_u32_ne(r);
 
         // Reads rbag_buf
-        memcpy(def->rbag_buf, buf, 8 * def->rbag_len);
-        buf += def->rbag_len * 2;
+        for (u32 j = 0; j < def->rbag_len; ++j) {
+            def->rbag_buf[j] = read_u64_ne(r);
+        }
 
         // Reads node_buf
-        memcpy(def->node_buf, buf, 8 * def->node_len);
-        buf += def->node_len * 2;
+        for (u32 j = 0; j < def->node_len; ++j) {
+            def->node_buf[j] = read_u64_ne(r);
+        }
+    }
+    return NULL;
+}
+
+static const char *book_load_v2(Book *book, BookReader *r) {
+    // Reads and checks the header
+    r->at = 4;
+    u32 version = (u32) read_le(r, 4);
+    u32 checksum = (u32) read_le(r, 4);
+    u64 body_len = read_le(r, 8);
+    if (!r->ok) return "Unexpected end of buffer";
+    if (version != 2) return "Unsupported book format version";
+    if (body_len != r->len - r->at) return "Book length mismatch";
+    if (book_checksum(r->buf + r->at, body_len) != checksum) return "Checksum mismatch";
+
+    // Reads defs_len
+    u64 defs_len = read_varint(r);
+    if (defs_len > 0x4000) return "Too many definitions for the C runtime";
+    book->defs_len = (u32) defs_len;
+
+    // Parses each def
+    for (u32 fid = 0; fid < book->defs_len && r->ok; ++fid) {
+        Def *def = &book->defs_buf[fid];
+
+        // Reads name
+        u64 name_len = read_varint(r);
+        if (name_len > 255) return "Name too long for the C runtime";
+        read_raw(r, def->name, name_len);
+        def->name[name_len] = '\0';
+
+        // Reads safe flag
+        def->safe = (bool) read_le(r, 1);
+
+        // Reads lengths
+        u64 rbag_len = read_varint(r);
+        u64 node_len = read_varint(r);
+        u64 vars_len = read_varint(r);
+        if (rbag_len > 0xFFF || node_len > 0xFFF || vars_len > 0xFFFFFFFF) {
+            return "Definition too large for the C runtime";
+        }
+        def->rbag_len = (u32) rbag_len;
+        def->node_len = (u32) node_len;
+        def->vars_len = (u32) vars_len;
+
+        // Reads root
+        def->root = (Port) read_le(r, 4);
+
+        // Reads rbag_buf and node_buf
+        for (u32 j = 0; j < def->rbag_len; ++j) {
+            def->rbag_buf[j] = read_le(r, 8);
+        }
+        for (u32 j = 0; j < def->node_len; ++j) {
+            def->node_buf[j] = read_le(r, 8);
+        }
     }
+    return NULL;
+}
+
+const char *book_load(Book *book, const u8 *buf, u64 len) {
+    BookReader r = {buf, len, 0, TRUE};
+    const char *error = len >= 4 && memcmp(buf, "HVMB", 4) == 0 ? book_load_v2(book, &r) : book_load_v1(book, &r);
+    if (error == NULL && !r.ok) return "Unexpected end of buffer";
+    if (error == NULL && r.at != len) return "Unexpected trailing bytes";
+    return error;
 }
 
 // Debug Printing
@@ -2095,7 +2841,7 @@ Show show_rule(Rule rule) {
 void print_net(Net *net) {
     printf("NODE | PORT-1       | PORT-2      \n");
     printf("---- | ------------ | ------------\n");
-    for (u32 i = 0; i < G_NODE_LEN; ++i) {
+    for (u32 i = 0; i < net->node_len; ++i) {
         Pair node = node_load(net, i);
         if (node != 0) {
             printf("%04X | %s | %s\n", i, show_port(get_fst(node)).x, show_port(get_snd(node)).x);
@@ -2104,7 +2850,7 @@ void print_net(Net *net) {
     printf("==== | ============ |\n");
     printf("VARS | VALUE        |\n");
     printf("---- | ------------ |\n");
-    for (u32 i = 0; i < G_VARS_LEN; ++i) {
+    for (u32 i = 0; i < net->vars_len; ++i) {
         Port var = vars_load(net, i);
         if (var != 0) {
             printf("%04X | %s |\n", i, show_port(vars_load(net, i)).x);
@@ -2570,15 +3316,55 @@ void string_buffer_pretty_print_numb(StringBuffer *buffer, Numb word) {
 
 }
 
-void extract_result_and_mem_dump(Net *net, Book *book, Port port, u32 enable_mem_dump, char **out_result,
-                                 char **out_mem_dump) {
-    Port stack[256];
+void string_buffer_write_pair_row(StringBuffer *buffer, u32 idx, Port fst, Port snd) {
+    string_buffer_write_u32_custom(buffer, "%04X | ", idx);
+    string_buffer_write_string(buffer, show_port(fst).x);
+    string_buffer_write_string(buffer, " | ");
+    string_buffer_write_string(buffer, show_port(snd).x);
+    string_buffer_write_char(buffer, '\n');
+}
+
+void string_buffer_write_mem_dump(StringBuffer *buffer, Net *net, u32 mode) {
+    string_buffer_write_string(buffer, "NODE | FST-PORT     | SND-PORT     \n");
+    string_buffer_write_string(buffer, "---- | ------------ | ------------\n");
+    for (u32 i = 0; i < net->node_len; ++i) {
+        Pair node = node_load(net, i);
+        if (node != 0) {
+            string_buffer_write_pair_row(buffer, i, get_fst(node), get_snd(node));
+        }
+    }
+    string_buffer_write_string(buffer, "==== | ============ | ============\n");
+    if (mode != MEM_DUMP_FULL) return;
+    string_buffer_write_string(buffer, "VARS | VALUE        |\n");
+    string_buffer_write_string(buffer, "---- | ------------ |\n");
+    for (u32 i = 0; i < net->vars_len; ++i) {
+        Port var = vars_load(net, i);
+        if (var != 0) {
+            string_buffer_write_u32_custom(buffer, "%04X | ", i);
+            string_buffer_write_string(buffer, show_port(var).x);
+            string_buffer_write_string(buffer, " |\n");
+        }
+    }
+    string_buffer_write_string(buffer, "ROOT | ");
+    string_buffer_write_string(buffer, show_port(vars_load(net, get_val(ROOT))).x);
+    string_buffer_write_string(buffer, " |\n");
+    string_buffer_write_string(buffer, "==== | ============ |\n");
+}
+
+// Shows a tree without consuming it, following substituted vars.
+char *show_tree(Net *net, Book *book, Port port) {
+    // Each node pushes 4 entries, so the stack grows as needed
+    u32 cap = 256;
+    Port *stack = (Port *) malloc(sizeof(Port) * cap);
     stack[0] = port;
     u32 len = 1;
-    u32 num = 0;
     StringBuffer buffer;
     string_buffer_init(&buffer, 0);
     while (len > 0) {
+        if (len + 4 > cap) {
+            cap *= 2;
+            stack = (Port *) realloc(stack, sizeof(Port) * cap);
+        }
         Port cur = stack[--len];
         switch (get_tag(cur)) {
             case CON: {
@@ -2658,46 +3444,167 @@ void extract_result_and_mem_dump(Net *net, Book *book, Port port, u32 enable_mem
         }
     }
 
-    *out_result = string_buffer_consume(&buffer);
-    *out_mem_dump = (char*)NULL;
+    free(stack);
+    return string_buffer_consume(&buffer);
+}
+
+void extract_result_and_mem_dump(Net *net, Book *book, Port port, u32 mem_dump_mode, char **out_result,
+                                 char **out_mem_dump) {
+    *out_result = show_tree(net, book, port);
+
+    StringBuffer dump;
+    string_buffer_init(&dump, 0);
+    if (mem_dump_mode != MEM_DUMP_NONE) {
+        string_buffer_write_mem_dump(&dump, net, mem_dump_mode);
+    }
+    *out_mem_dump = string_buffer_consume(&dump);
 }
 
 
 // Main
 // ----
 
-EvaluationResultRaw *hvm_c(u32 *book_buffer, u32 enable_mem_dump) {
-    // Creates static TMs
-    alloc_static_tms();
+// Writes an error message into the host's buffer.
+void write_error(u32 *err_kind, char *err_buf, u32 err_len, ErrorKind kind, const char *message) {
+    if (err_kind != NULL) *err_kind = kind;
+    if (err_buf == NULL || err_len == 0) return;
+    snprintf(err_buf, err_len, "%s", message);
+}
+
+// Sessions
+// --------
+
+// A session keeps a net alive between evaluations, with the buffer sizes and
+// thread count from `options`. Returns NULL if it can't be allocated.
+Net *hvm_c_session_new(const EvaluationOptions *options) {
+    return net_new(options);
+}
+
+void hvm_c_session_free(Net *session) {
+    net_free(session);
+}
+
+// Sets an initial redex that calls a def with `args` as its λ-arguments:
+// `@def ~ (arg0 (arg1 ... ROOT))`.
+void boot_call(Net *net, u32 fid, const HostValue *args, u32 args_len) {
+    TM *tm = net->tm[0];
+    Port ret = ROOT;
+    for (u32 i = args_len; i-- > 0;) {
+        Port arg = inject_host_value(net, &args[i]);
+        if (node_alloc(net, tm, 1) < 1) {
+            return; // halted with HALT_OOM
+        }
+        node_create(net, tm->nloc[0], new_pair(arg, ret));
+        ret = new_port(CON, tm->nloc[0]);
+    }
+    boot_redex(net, new_pair(new_port(REF, fid), ret));
+}
+
+// Evaluates a book on `session`, or on a net of its own if it's NULL. A
+// session's buffer sizes and thread count are used instead of the options'.
+// The entry definition is applied to `args`.
+EvaluationResultRaw *hvm_c(const u8 *book_buffer, u64 book_len, const EvaluationOptions *options,
+                           const HostFFns *host, Net *session, const HostValue *args, u32 args_len,
+                           u32 *err_kind, char *err_buf, u32 err_len) {
+    EvaluationOptions defaults = {0, 0, 0, MEM_DUMP_NONE, NULL, 0, 0, NULL, NULL, STDIO_INHERIT, NULL, 0, NULL, NULL, NULL, 0, NULL, NULL, 0, 0};
+    if (options == NULL) options = &defaults;
+    const char *entry = options->entry ? options->entry : "main";
 
     // Loads the Book
     Book *book = NULL;
     if (book_buffer) {
         book = (Book *) malloc(sizeof(Book));
+        if (book == NULL) {
+            write_error(err_kind, err_buf, err_len, ERROR_OOM, "Failed to allocate the book");
+            return NULL;
+        }
         book_init(book);
-        book_load(book, book_buffer);
+        const char *error = book_load(book, book_buffer, book_len);
+        if (error != NULL) {
+            write_error(err_kind, err_buf, err_len, ERROR_FORMAT, error);
+            free(book);
+            return NULL;
+        }
     }
 
-    // Starts the timer
-    u64 start = time64();
+    // Finds the entry definition
+    u32 entry_fid = book ? book->defs_len : 0;
+    for (u32 fid = 0; book && fid < book->defs_len; ++fid) {
+        if (strcmp(book->defs_buf[fid].name, entry) == 0) {
+            entry_fid = fid;
+            break;
+        }
+    }
+    if (book == NULL || entry_fid == book->defs_len) {
+        char message[320];
+        snprintf(message, sizeof(message), "No `@%s` definition found", entry);
+        write_error(err_kind, err_buf, err_len, ERROR_MISSING_ENTRY, message);
+        free(book);
+        return NULL;
+    }
 
     // GMem
-    Net *net = malloc(sizeof(Net));
-    net_init(net);
+    Net *net = session;
+    if (session != NULL) {
+        net_reset(session);
+        net_configure(session, options);
+    } else {
+        net = net_new(options);
+    }
+    if (net == NULL) {
+        char message[128];
+        snprintf(message, sizeof(message), "Failed to allocate a net of %" PRIu64 " nodes and %" PRIu64 " vars",
+                 options->node_len ? options->node_len : G_NODE_LEN,
+                 options->vars_len ? options->vars_len : G_VARS_LEN);
+        write_error(err_kind, err_buf, err_len, ERROR_OOM, message);
+        free(book);
+        return NULL;
+    }
 
-    // Creates an initial redex that calls main
-    boot_redex(net, new_pair(new_port(REF, 0), ROOT));
+    // Starts the timer
+    u64 start = time64();
+    net->start = start;
+    net->next_itrs = net->progress_itrs;
+    net->next_time = start + net->progress_ns;
+    if (options->timeout_ms) {
+        net->deadline = start + options->timeout_ms * 1000000;
+    }
+
+    // Creates an initial redex that calls the entry definition with the arguments
+    boot_call(net, entry_fid, args, args_len);
 
     // Normalizes and runs IO
-    do_run_io(net, book, ROOT);
+    do_run_io(net, book, host, ROOT);
+
+    // Stops the timer
+    double duration = (time64() - start) / 1000000000.0; // seconds
 
     char *result = (char *) NULL;
     char *mem_dump = (char *) NULL;
 
-    extract_result_and_mem_dump(net, book, enter(net, ROOT), enable_mem_dump, &result, &mem_dump);
+    // Reports runtime errors
+    u32 status = atomic_load(&net->halt);
+    if (status == HALT_FAIL || status == HALT_OOM) {
+        char message[320];
+        if (status == HALT_FAIL) {
+            snprintf(message, sizeof(message), "attempt to clone a non-affine global reference `@%s`",
+                     book->defs_buf[net->fail_fid].name);
+        } else {
+            snprintf(message, sizeof(message), "The net ran out of space (%" PRIu32 " nodes and %" PRIu32 " vars)",
+                     net->node_len, net->vars_len);
+        }
+        write_error(err_kind, err_buf, err_len, status == HALT_FAIL ? ERROR_RUNTIME : ERROR_OOM, message);
+        if (session == NULL) net_free(net); else net_close_io(net);
+        free(book);
+        return NULL;
+    }
 
-    // Stops the timer
-    double duration = (time64() - start) / 1000000000.0; // seconds
+    // Only a completed net can be read back
+    Port root = status == STATUS_COMPLETED ? enter(net, ROOT) : new_port(ERA, 0);
+    extract_result_and_mem_dump(net, book, root, options->mem_dump, &result, &mem_dump);
+    if (status != STATUS_COMPLETED) {
+        result[0] = '\0';
+    }
 
     // Prints interactions and time
     u64 itrs = atomic_load(&net->itrs);
@@ -2708,20 +3615,42 @@ EvaluationResultRaw *hvm_c(u32 *book_buffer, u32 enable_mem_dump) {
     EvaluationResultRaw *ret = malloc(sizeof(EvaluationResultRaw));
     ret->iterations = itrs;
     ret->time = duration;
+    ret->status = status;
     ret->result = result;
     ret->mem_dump = mem_dump;
     ret->deallocator = c_free_evaluation_result;
+    ret->tree = NULL;
+    for (u32 r = 0; r < 8; ++r) {
+        ret->rules[r] = 0;
+        for (u32 t = 0; t < net->tpc; ++t) {
+            ret->rules[r] += net->tm[t]->rules[r];
+        }
+    }
+    ret->peak_rbag = atomic_load(&net->peak_rbag);
+    ret->peak_node = atomic_load(&net->peak_node);
+    ret->peak_vars = atomic_load(&net->peak_vars);
 
-    // Frees everything
-    free_static_tms();
-    free(net);
+    // Hands over what the program wrote, unless it was streamed
+    ret->stdout_buf = NULL;
+    ret->stdout_len = 0;
+    ret->stderr_buf = NULL;
+    ret->stderr_len = 0;
+    if (net->stdio == STDIO_CAPTURE && net->output == NULL) {
+        ret->stdout_len = net->captured[0].count;
+        ret->stdout_buf = (u8 *) string_buffer_consume(&net->captured[0]);
+        ret->stderr_len = net->captured[1].count;
+        ret->stderr_buf = (u8 *) string_buffer_consume(&net->captured[1]);
+    }
+
+    // Frees everything, but a session's net
+    if (session == NULL) net_free(net); else net_close_io(net);
     free(book);
     return ret;
 }
 
 #ifdef WITH_MAIN
 int main() {
-  hvm_c((u32*)BOOK_BUF);
+  hvm_c(BOOK_BUF, sizeof(BOOK_BUF), NULL, NULL, NULL, NULL, 0, NULL, NULL, 0);
   return 0;
 }
 #endif
@@ -15,6 +15,7 @@ typedef uint8_t bool;
 typedef uint8_t u8;
 typedef uint16_t u16;
 typedef int32_t i32;
+typedef int64_t i64;
 typedef uint32_t u32;
 typedef uint64_t u64;
 typedef float f32;
@@ -25,13 +26,137 @@ typedef _Atomic (u16) a16;
 typedef _Atomic (u32) a32;
 typedef _Atomic (u64) a64;
 
+// What to dump from the net after evaluation
+typedef enum {
+    MEM_DUMP_NONE = 0,
+    MEM_DUMP_NODES = 1,
+    MEM_DUMP_FULL = 2,
+} MemDumpModes;
+
+// Where a program's stdin, stdout and stderr go
+typedef enum {
+    STDIO_INHERIT = 0, // the process' own
+    STDIO_CAPTURE = 1, // stdin from `stdin_buf`, output captured or streamed to `output`
+} StdioModes;
+
+// Kept in sync with `ErrorKind` in error.rs
+typedef enum {
+    ERROR_PARSE = 0,
+    ERROR_BUILD = 1,
+    ERROR_MISSING_ENTRY = 2,
+    ERROR_OOM = 3,
+    ERROR_RUNTIME_UNAVAILABLE = 4,
+    ERROR_CANCELLED = 5,
+    ERROR_IO = 6,
+    ERROR_INVALID_ARGUMENT = 7,
+    ERROR_RUNTIME = 8,
+    ERROR_PANIC = 9,
+    ERROR_FORMAT = 10,
+    ERROR_DECODE = 11,
+} ErrorKind;
+
+// Why an evaluation stopped
+typedef enum {
+    STATUS_COMPLETED = 0,
+    STATUS_FUEL_EXHAUSTED = 1,
+    STATUS_TIMED_OUT = 2,
+    STATUS_CANCELLED = 3,
+} EvaluationStatus;
+
+// Shared with the host, which may cancel an evaluation from another thread
+typedef struct {
+    a32 cancelled;
+} CancellationToken;
+
+// A directory programs may open files in, along with its subdirectories
+typedef struct {
+    const char *path;
+    u32 writable; // whether files may be written or created (0 = read-only)
+} IoDirectory;
+
+// What IO a program may do. Anything not listed is denied, unless the policy
+// is unrestricted, and denied calls return `*` like any other failed IO call.
+typedef struct {
+    const IoDirectory *dirs; // canonical paths
+    u64 dirs_len;
+    const char *const *ffns; // names of the functions programs may call
+    u64 ffns_len;
+    u32 max_files; // how many files may be open at once
+    u32 unrestricted; // allows all IO, ignoring the lists above (0 = only what's listed)
+} IoPolicy;
+
+// What progress reports tell the host. Mirrored in hvm.rs.
+typedef struct {
+    u64 interactions; // so far
+    u64 rbag_lo; // redexes waiting in all threads' bags
+    u64 rbag_hi;
+    u64 live_nodes; // estimated from the nodes interactions made and took
+    f64 time; // seconds since the evaluation started
+} Progress;
+
+typedef struct {
+    u64 node_len; // node buffer length (0 = default)
+    u64 vars_len; // vars buffer length (0 = default)
+    u32 threads; // evaluator threads (0 = runtime default)
+    u32 mem_dump; // one of MemDumpModes
+    const char *entry; // entry definition name (null = "main")
+    u64 max_interactions; // interaction limit (0 = unlimited)
+    u64 timeout_ms; // wall-clock limit in milliseconds (0 = unlimited)
+    const CancellationToken *cancellation; // cancellation handle (null = none)
+    const IoPolicy *io_policy; // IO allow-list (null = no IO)
+    u32 stdio; // one of StdioModes
+    const u8 *stdin_buf; // STDIO_CAPTURE: what programs read from stdin
+    u64 stdin_len;
+    // STDIO_CAPTURE: streams output on fd 1 or 2 instead of capturing it (null = capture)
+    void (*output)(void *ctx, u32 fd, const u8 *buf, u64 len);
+    void *output_ctx; // passed back to `output` as is
+    const void *const *args; // λ-arguments for the entry, read by the Rust side (`hvm_c` takes them built)
+    u64 args_len;
+    // Called every `progress_interactions` interactions and every `progress_interval_ms`
+    // milliseconds (0 = never); returning 0 cancels the evaluation (null = no reports)
+    u32 (*progress)(void *ctx, const Progress *progress);
+    void *progress_ctx; // passed back to `progress` as is
+    u64 progress_interactions;
+    u64 progress_interval_ms;
+} EvaluationOptions;
+
+// A value returned by a host function, laid out like a def without redexes
+typedef struct {
+    u32 root;
+    u32 node_len;
+    const u64 *node_buf;
+    u32 vars_len;
+} HostValue;
+
+// Functions registered by the host, called by `IO_CALL`s no builtin handles.
+// `call` gets the argument as text, and returns 0 if there's no function
+// named `name`; otherwise `*ret` is the result (NULL = `*`), freed with `free`.
+// Errors are written to `err_buf`, and reported like other IO errors.
+typedef struct {
+    const void *ctx;
+    u32 (*call)(const void *ctx, const char *name, const char *argm, HostValue **ret, char *err_buf, u32 err_len);
+    void (*free)(HostValue *value);
+} HostFFns;
+
 typedef struct {
     u64 iterations;
     f64 time;
+    u32 status; // one of EvaluationStatus
     char *result;
     char *mem_dump;
 
     void (*deallocator)(void *);
+    void *tree; // structured result, set and freed by the Rust side
+    u8 *stdout_buf; // captured output (null unless captured)
+    u64 stdout_len;
+    u8 *stderr_buf;
+    u64 stderr_len;
+    u64 rules[8]; // interactions performed, indexed by rule (`iterations` counts all but LINK)
+    // Most redexes, nodes and vars in use at once: exact on one thread, and may
+    // miss short peaks on more
+    u64 peak_rbag;
+    u64 peak_node;
+    u64 peak_vars;
 } EvaluationResultRaw;
 
 void c_free_evaluation_result(void *result_ptr) {
@@ -40,6 +165,8 @@ void c_free_evaluation_result(void *result_ptr) {
         free(casted->result);
     if (casted->mem_dump)
         free(casted->mem_dump);
+    free(casted->stdout_buf);
+    free(casted->stderr_buf);
     free(result_ptr);
 }
 
@@ -67,9 +194,11 @@ void string_buffer_free(StringBuffer *buffer) {
 }
 
 void string_buffer_ensure_amount(StringBuffer *buffer, u32 amount) {
-    u32 target_cap = buffer->count + amount;
+    // Leaves room for the terminator sprintf writes
+    u32 target_cap = buffer->count + amount + 1;
     if (target_cap <= buffer->capacity) return;
-    u32 new_cap = buffer->capacity * 2;
+    u32 new_cap = buffer->capacity ? buffer->capacity * 2 : 64;
+    while (new_cap < target_cap) new_cap *= 2;
     char *new_buffer = (char *) malloc(sizeof(char) * new_cap);
     memcpy(new_buffer, buffer->buffer, sizeof(char) * buffer->count);
     free(buffer->buffer);
@@ -87,8 +216,8 @@ char *string_buffer_consume(StringBuffer *buffer) {
 }
 
 void string_buffer_write_u32_custom(StringBuffer *buffer, const char* format, u32 value) {
-    // 4294967295.length() == 10
-    string_buffer_ensure_amount(buffer, 10);
+    // 4294967295.length() == 10, plus room for the format's own text
+    string_buffer_ensure_amount(buffer, 10 + strlen(format));
     char* loc = &buffer->buffer[buffer->count];
     i32 written = sprintf(loc, format, value);
     buffer->count += written;
@@ -111,8 +240,8 @@ void string_buffer_write_u64(StringBuffer *buffer, u64 value) {
 }
 
 void string_buffer_write_f32_custom(StringBuffer *buffer, const char* format, f32 value) {
-    // (-1.7976931348623157e+308).length() == 24
-    string_buffer_ensure_amount(buffer, 24);
+    // (-1.7976931348623157e+308).length() == 24, plus room for the format's own text
+    string_buffer_ensure_amount(buffer, 24 + strlen(format));
     char *loc = &buffer->buffer[buffer->count];
     i32 written = sprintf(loc, format, value);
     buffer->count += written;
@@ -138,7 +267,13 @@ void string_buffer_write_string(StringBuffer *buffer, char *str) {
     while (str[count++] != '\0') {}
     count--;
     string_buffer_ensure_amount(buffer, count);
-    memcpy(&buffer->buffer[count], str, sizeof(char) * count);
+    memcpy(&buffer->buffer[buffer->count], str, sizeof(char) * count);
+    buffer->count += count;
+}
+
+void string_buffer_write_bytes(StringBuffer *buffer, const char *bytes, u32 count) {
+    string_buffer_ensure_amount(buffer, count);
+    memcpy(&buffer->buffer[buffer->count], bytes, sizeof(char) * count);
     buffer->count += count;
 }
 
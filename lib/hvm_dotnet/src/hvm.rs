@@ -16,9 +16,15 @@
 //   Copied and adapted from Higher-order Virtual Machine 2: 
 //      https://github.com/HigherOrderCO/HVM.git
 
-use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
-use std::alloc::{alloc, dealloc, Layout};
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::io::{Read, Write};
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
 use std::mem;
+use std::collections::VecDeque;
 
 // Runtime
 // =======
@@ -98,9 +104,23 @@ pub const FREE : Port = Port(0x0);
 pub const ROOT : Port = Port(0xFFFFFFF8);
 pub const NONE : Port = Port(0xFFFFFFFF);
 
+// Halt Reasons
+pub const HALT_NONE : u32 = 0x0; // ran to completion
+pub const HALT_FUEL : u32 = 0x1; // interaction limit reached
+pub const HALT_TIME : u32 = 0x2; // deadline passed
+pub const HALT_STOP : u32 = 0x3; // cancelled by the host
+pub const HALT_FAIL : u32 = 0x4; // a thread raised a RuntimeError or panicked
+pub const HALT_OOM  : u32 = 0x5; // a thread's slice of the node or vars buffer is full
+
+// Interactions between checks of the halting conditions
+const HALT_CHECK_TICKS : u32 = 1 << 10;
+
+// Most nodes or vars a thread holds locations for at once, as in hvm.c
+pub const MAX_ALLOC : usize = 0xFFF;
+
 // RBag
 pub struct RBag {
-    pub lo: Vec<Pair>,
+    pub lo: VecDeque<Pair>, // shared from the front, so the oldest redexes go to idle threads
     pub hi: Vec<Pair>,
 }
 
@@ -110,7 +130,16 @@ pub struct GNet<'a> {
     pub vlen: usize, // length of the vars buffer
     pub node: &'a mut [APair], // node buffer
     pub vars: &'a mut [APort], // vars buffer
+    pub root: APort, // root var (kept out of the vars buffer)
     pub itrs: AtomicU64, // interaction count
+    pub idle: AtomicU32, // idle thread counter
+    pub share: Vec<APair>, // per-thread slot with a redex up for stealing
+    pub load: Vec<Load>, // per-thread load, for progress reports
+    pub peak: Peak, // most resources in use at once, across threads
+    pub fuel: u64, // interaction limit (u64::MAX = unlimited)
+    pub deadline: Option<Instant>, // wall-clock limit
+    pub cancel: Option<&'a AtomicU32>, // set to non-zero by the host to cancel
+    pub halt: AtomicU32, // why the evaluation stopped early (HALT_NONE otherwise)
 }
 
 // Thread Memory
@@ -119,6 +148,12 @@ pub struct TMem {
     pub tids: u32, // thread count
     pub tick: u32, // tick counter
     pub itrs: u32, // interaction count
+    pub live: i64, // nodes created minus nodes taken by this thread
+    pub live_vars: i64, // vars created minus vars taken by this thread
+    pub rules: [u64; 8], // interactions performed, indexed by rule
+    pub peak_rbag: usize, // most redexes this thread's bag held since its last halt check
+    pub peak_node: i64, // highest `live` since then
+    pub peak_vars: i64, // highest `live_vars` since then
     pub nput: usize, // next node allocation index
     pub vput: usize, // next vars allocation index
     pub nloc: Vec<usize>, // allocated node locations
@@ -126,6 +161,47 @@ pub struct TMem {
     pub rbag: RBag, // local redex bag
 }
 
+// A thread's load as of its last halt check
+#[derive(Default)]
+pub struct Load {
+    pub rbag_lo: AtomicU64,
+    pub rbag_hi: AtomicU64,
+    pub live: AtomicI64,
+    pub live_vars: AtomicI64,
+}
+
+// Most redexes, nodes and vars in use at once. Threads add their own peaks
+// since their last halt check to the others' loads, so this is exact on one
+// thread, and on more it may miss peaks shorter than a halt check interval.
+#[derive(Default)]
+pub struct Peak {
+    pub rbag: AtomicU64,
+    pub node: AtomicU64,
+    pub vars: AtomicU64,
+}
+
+// What progress reports tell the host. Mirrored in hvm.h.
+#[repr(C)]
+pub struct Progress {
+    pub interactions: u64, // so far
+    pub rbag_lo: u64, // redexes waiting in all threads' bags
+    pub rbag_hi: u64,
+    pub live_nodes: u64, // estimated from the nodes interactions made and took
+    pub time: f64, // seconds since the evaluation started
+}
+
+// Reports progress every `every_itrs` interactions and every `every_ms`
+// milliseconds (0 = never), from thread 0's halt checks. Reports are only as
+// frequent as those checks.
+pub struct Reporter<'n> {
+    pub report: &'n (dyn Fn(&Progress) -> bool + Sync), // false halts the net
+    pub every_itrs: u64,
+    pub every_ms: u64,
+    pub start: Instant,
+    pub next_itrs: AtomicU64, // only touched by thread 0
+    pub next_ms: AtomicU64,
+}
+
 // Top-Level Definition
 pub struct Def {
     pub name: String, // def name
@@ -141,6 +217,12 @@ pub struct Book {
     pub defs: Vec<Def>,
 }
 
+// Runtime Error
+#[derive(Debug)]
+pub enum RuntimeError {
+    NonAffineRef(String), // attempt to clone the named non-affine definition
+}
+
 impl Port {
     pub fn new(tag: Tag, val: Val) -> Self {
         Port((val << 3) | tag as Val)
@@ -357,6 +439,7 @@ impl Numb {
             return Numb::new_u24(0);
         }
         let (op, a, ty, b) = if at >= OP_ADD { (at, a, bt, b) } else { (bt, b, at, a) };
+        // Integer division and remainder by zero give 0, like upstream HVM
         match ty {
             TY_U24 => {
                 let av = a.get_u24();
@@ -366,10 +449,10 @@ impl Numb {
                     OP_SUB => Numb::new_u24(av.wrapping_sub(bv)),
                     FP_SUB => Numb::new_u24(bv.wrapping_sub(av)),
                     OP_MUL => Numb::new_u24(av.wrapping_mul(bv)),
-                    OP_DIV => Numb::new_u24(av.wrapping_div(bv)),
-                    FP_DIV => Numb::new_u24(bv.wrapping_div(av)),
-                    OP_REM => Numb::new_u24(av.wrapping_rem(bv)),
-                    FP_REM => Numb::new_u24(bv.wrapping_rem(av)),
+                    OP_DIV => Numb::new_u24(av.checked_div(bv).unwrap_or(0)),
+                    FP_DIV => Numb::new_u24(bv.checked_div(av).unwrap_or(0)),
+                    OP_REM => Numb::new_u24(av.checked_rem(bv).unwrap_or(0)),
+                    FP_REM => Numb::new_u24(bv.checked_rem(av).unwrap_or(0)),
                     OP_EQ  => Numb::new_u24((av == bv) as u32),
                     OP_NEQ => Numb::new_u24((av != bv) as u32),
                     OP_LT  => Numb::new_u24((av <  bv) as u32),
@@ -392,10 +475,10 @@ impl Numb {
                     OP_SUB => Numb::new_i24(av.wrapping_sub(bv)),
                     FP_SUB => Numb::new_i24(bv.wrapping_sub(av)),
                     OP_MUL => Numb::new_i24(av.wrapping_mul(bv)),
-                    OP_DIV => Numb::new_i24(av.wrapping_div(bv)),
-                    FP_DIV => Numb::new_i24(bv.wrapping_div(av)),
-                    OP_REM => Numb::new_i24(av.wrapping_rem(bv)),
-                    FP_REM => Numb::new_i24(bv.wrapping_rem(av)),
+                    OP_DIV => Numb::new_i24(av.checked_div(bv).unwrap_or(0)),
+                    FP_DIV => Numb::new_i24(bv.checked_div(av).unwrap_or(0)),
+                    OP_REM => Numb::new_i24(av.checked_rem(bv).unwrap_or(0)),
+                    FP_REM => Numb::new_i24(bv.checked_rem(av).unwrap_or(0)),
                     OP_EQ  => Numb::new_u24((av == bv) as u32),
                     OP_NEQ => Numb::new_u24((av != bv) as u32),
                     OP_LT  => Numb::new_u24((av <  bv) as u32),
@@ -436,7 +519,7 @@ impl Numb {
 impl RBag {
     pub fn new() -> Self {
         RBag {
-            lo: Vec::new(),
+            lo: VecDeque::new(),
             hi: Vec::new(),
         }
     }
@@ -446,7 +529,7 @@ impl RBag {
         if Port::is_high_priority(rule) {
             self.hi.push(redex);
         } else {
-            self.lo.push(redex);
+            self.lo.push_back(redex);
         }
     }
 
@@ -454,7 +537,7 @@ impl RBag {
         if !self.hi.is_empty() {
             self.hi.pop()
         } else {
-            self.lo.pop()
+            self.lo.pop_back()
         }
     }
 
@@ -467,15 +550,122 @@ impl RBag {
     }
 }
 
+// Net Memory
+// ----------
+
+// Maps `len` zeroed items. Pages are only committed once they're written, and
+// aren't counted against the commit limit, so a net takes as much RAM as its
+// evaluation uses rather than its full size.
+fn map_zeroed<'a, T>(len: usize) -> Option<&'a mut [T]> {
+    let size = len.checked_mul(mem::size_of::<T>())?;
+    let prot = libc::PROT_READ | libc::PROT_WRITE;
+    let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE;
+    let ptr = unsafe { libc::mmap(std::ptr::null_mut(), size, prot, flags, -1, 0) };
+    if ptr == libc::MAP_FAILED {
+        return None;
+    }
+    Some(unsafe { std::slice::from_raw_parts_mut(ptr as *mut T, len) })
+}
+
+fn unmap<T>(buf: &mut [T]) {
+    unsafe { libc::munmap(buf.as_mut_ptr() as *mut libc::c_void, mem::size_of_val(buf)) };
+}
+
+// Zeroes mapped items. The whole pages among them are handed back to the OS,
+// which maps them to zeroes again when they're next touched.
+fn zero_mapped<T>(buf: &mut [T]) {
+    let ini = buf.as_mut_ptr() as usize;
+    let end = ini + mem::size_of_val(buf);
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let lo = ini.div_ceil(page) * page;
+    let hi = end / page * page;
+    unsafe {
+        if lo < hi && libc::madvise(lo as *mut libc::c_void, hi - lo, libc::MADV_DONTNEED) == 0 {
+            std::ptr::write_bytes(ini as *mut u8, 0, lo - ini);
+            std::ptr::write_bytes(hi as *mut u8, 0, end - hi);
+        } else {
+            std::ptr::write_bytes(ini as *mut u8, 0, end - ini);
+        }
+    }
+}
+
 impl<'a> GNet<'a> {
-    pub fn new(nlen: usize, vlen: usize) -> Self {
-        let nlay = Layout::array::<APair>(nlen).unwrap();
-        let vlay = Layout::array::<APort>(vlen).unwrap();
-        let nptr = unsafe { alloc(nlay) as *mut APair };
-        let vptr = unsafe { alloc(vlay) as *mut APort };
-        let node = unsafe { std::slice::from_raw_parts_mut(nptr, nlen) };
-        let vars = unsafe { std::slice::from_raw_parts_mut(vptr, vlen) };
-        GNet { nlen, vlen, node, vars, itrs: AtomicU64::new(0) }
+    // Allocates a net with `nlen` nodes and `vlen` vars, to be evaluated by
+    // `tids` threads. Returns `None` if the buffers can't be mapped.
+    pub fn new(nlen: usize, vlen: usize, tids: u32) -> Option<Self> {
+        // The last var index is the ROOT port, which is stored separately
+        let vlen = vlen.min(ROOT.get_val() as usize);
+        let node = map_zeroed::<APair>(nlen)?;
+        let vars = match map_zeroed::<APort>(vlen) {
+            Some(vars) => vars,
+            None => {
+                unmap(node);
+                return None;
+            }
+        };
+        let share = (0..tids).map(|_| APair(AtomicU64::new(0))).collect();
+        let load = (0..tids).map(|_| Load::default()).collect();
+        Some(GNet {
+            nlen,
+            vlen,
+            node,
+            vars,
+            root: APort(AtomicU32::new(0)),
+            itrs: AtomicU64::new(0),
+            idle: AtomicU32::new(0),
+            share,
+            load,
+            peak: Peak::default(),
+            fuel: u64::MAX,
+            deadline: None,
+            cancel: None,
+            halt: AtomicU32::new(HALT_NONE),
+        })
+    }
+
+    // Clears what an evaluation left in the net, so it can run another with
+    // the same `tms`. Threads hand out slots in order, so only the ones they
+    // reached are zeroed.
+    pub fn reset(&mut self, tms: &mut [TMem]) {
+        let nlen = self.nlen / tms.len();
+        let vlen = self.vlen / tms.len();
+        for tm in tms.iter_mut() {
+            let ini = tm.tid as usize * nlen;
+            zero_mapped(&mut self.node[ini..ini + nlen.min(tm.nput + 1)]);
+            let ini = tm.tid as usize * vlen;
+            zero_mapped(&mut self.vars[ini..ini + vlen.min(tm.vput + 1)]);
+            tm.tick = 0;
+            tm.itrs = 0;
+            tm.live = 0;
+            tm.live_vars = 0;
+            tm.rules = [0; 8];
+            tm.peak_rbag = 0;
+            tm.peak_node = 0;
+            tm.peak_vars = 0;
+            tm.nput = 0;
+            tm.vput = 0;
+            tm.rbag.lo.clear();
+            tm.rbag.hi.clear();
+        }
+        self.root.0.store(0, Ordering::Relaxed);
+        self.itrs.store(0, Ordering::Relaxed);
+        self.idle.store(0, Ordering::Relaxed);
+        self.share.iter().for_each(|slot| slot.0.store(0, Ordering::Relaxed));
+        self.load.iter_mut().for_each(|load| *load = Load::default());
+        self.peak = Peak::default();
+        self.fuel = u64::MAX;
+        self.deadline = None;
+        self.cancel = None;
+        self.halt.store(HALT_NONE, Ordering::Relaxed);
+    }
+
+    // Gets a var slot, routing the ROOT var to its dedicated slot.
+    fn vars_slot(&self, var: usize) -> &APort {
+        if var == ROOT.get_val() as usize {
+            &self.root
+        } else {
+            &self.vars[var]
+        }
     }
 
     pub fn node_create(&self, loc: usize, val: Pair) {
@@ -483,7 +673,7 @@ impl<'a> GNet<'a> {
     }
 
     pub fn vars_create(&self, var: usize, val: Port) {
-        self.vars[var].0.store(val.0, Ordering::Relaxed);
+        self.vars_slot(var).0.store(val.0, Ordering::Relaxed);
     }
 
     pub fn node_load(&self, loc: usize) -> Pair {
@@ -491,7 +681,7 @@ impl<'a> GNet<'a> {
     }
 
     pub fn vars_load(&self, var: usize) -> Port {
-        Port(self.vars[var].0.load(Ordering::Relaxed) as u32)
+        Port(self.vars_slot(var).0.load(Ordering::Relaxed))
     }
 
     pub fn node_store(&self, loc: usize, val: Pair) {
@@ -499,7 +689,7 @@ impl<'a> GNet<'a> {
     }
 
     pub fn vars_store(&self, var: usize, val: Port) {
-        self.vars[var].0.store(val.0, Ordering::Relaxed);
+        self.vars_slot(var).0.store(val.0, Ordering::Relaxed);
     }
 
     pub fn node_exchange(&self, loc: usize, val: Pair) -> Pair {
@@ -507,7 +697,7 @@ impl<'a> GNet<'a> {
     }
 
     pub fn vars_exchange(&self, var: usize, val: Port) -> Port {
-        Port(self.vars[var].0.swap(val.0, Ordering::Relaxed) as u32)
+        Port(self.vars_slot(var).0.swap(val.0, Ordering::Relaxed))
     }
 
     pub fn node_take(&self, loc: usize) -> Pair {
@@ -526,7 +716,13 @@ impl<'a> GNet<'a> {
         self.vars_load(var).0 == 0
     }
 
-    pub fn enter(&self, mut var: Port) -> Port {
+    pub fn enter(&self, var: Port) -> Port {
+        self.enter_taking(var).0
+    }
+
+    // Like `enter`, also returning how many vars it took.
+    pub fn enter_taking(&self, mut var: Port) -> (Port, i64) {
+        let mut taken = 0;
         // While `B` is VAR: extend it (as an optimization)
         while var.get_tag() == VAR {
             // Takes the current `B` substitution as `B'`
@@ -537,25 +733,49 @@ impl<'a> GNet<'a> {
             }
             // Otherwise, delete `B` (we own both) and continue as `A ~> B'`
             self.vars_take(var.get_val() as usize);
+            taken += 1;
             var = val;
         }
-        return var;
+        return (var, taken);
     }
 
 }
 
 impl<'a> Drop for GNet<'a> {
     fn drop(&mut self) {
-        let nlay = Layout::array::<APair>(self.nlen).unwrap();
-        let vlay = Layout::array::<APair>(self.vlen).unwrap();
-        unsafe {
-            dealloc(self.node.as_mut_ptr() as *mut u8, nlay);
-            dealloc(self.vars.as_mut_ptr() as *mut u8, vlay);
-        }
+        unmap(self.node);
+        unmap(self.vars);
     }
 
 }
 
+impl<'n> Reporter<'n> {
+    pub fn new(report: &'n (dyn Fn(&Progress) -> bool + Sync), every_itrs: u64, every_ms: u64) -> Self {
+        Reporter { report, every_itrs, every_ms, start: Instant::now(), next_itrs: AtomicU64::new(every_itrs), next_ms: AtomicU64::new(every_ms) }
+    }
+
+    // Reports progress if it's due. Returns false if the host asked to halt.
+    fn poll(&self, net: &GNet) -> bool {
+        let itrs = net.itrs.load(Ordering::Relaxed);
+        let ms = self.start.elapsed().as_millis() as u64;
+        let due_itrs = self.every_itrs != 0 && itrs >= self.next_itrs.load(Ordering::Relaxed);
+        let due_ms = self.every_ms != 0 && ms >= self.next_ms.load(Ordering::Relaxed);
+        if !due_itrs && !due_ms {
+            return true;
+        }
+        self.next_itrs.store(itrs + self.every_itrs, Ordering::Relaxed);
+        self.next_ms.store(ms + self.every_ms, Ordering::Relaxed);
+        let progress = Progress {
+            interactions: itrs,
+            rbag_lo: net.load.iter().map(|load| load.rbag_lo.load(Ordering::Relaxed)).sum(),
+            rbag_hi: net.load.iter().map(|load| load.rbag_hi.load(Ordering::Relaxed)).sum(),
+            live_nodes: net.load.iter().map(|load| load.live.load(Ordering::Relaxed)).sum::<i64>().max(0) as u64,
+            time: self.start.elapsed().as_secs_f64(),
+        };
+        (self.report)(&progress)
+    }
+}
+
 impl TMem {
     // TODO: implement a TMem::new() fn
     pub fn new(tid: u32, tids: u32) -> Self {
@@ -564,20 +784,33 @@ impl TMem {
             tids,
             tick: 0,
             itrs: 0,
+            live: 0,
+            live_vars: 0,
+            rules: [0; 8],
+            peak_rbag: 0,
+            peak_node: 0,
+            peak_vars: 0,
             nput: 0,
             vput: 0,
-            nloc: vec![0; 0xFFF], // FIXME: move to a constant
-            vloc: vec![0; 0xFFF],
+            nloc: vec![0; MAX_ALLOC],
+            vloc: vec![0; MAX_ALLOC],
             rbag: RBag::new(),
         }
     }
 
+    // Each thread allocates from its own slice of the node and vars buffers.
+    // Slots that were never handed out are known to be free, so they're only
+    // checked after the slice wraps around. Going through the whole slice
+    // without finding enough room halts the net with HALT_OOM.
     pub fn node_alloc(&mut self, net: &GNet, num: usize) -> usize {
+        let len = net.nlen / self.tids as usize;
+        let ini = self.tid as usize * len;
         let mut got = 0;
-        for _ in 0..net.nlen {
-            self.nput += 1; // index 0 reserved
-            if self.nput < net.nlen-1 || net.is_node_free(self.nput % net.nlen) {
-                self.nloc[got] = self.nput % net.nlen;
+        for _ in 0..len {
+            self.nput += 1;
+            let loc = ini + self.nput % len;
+            if loc != 0 && (self.nput < len || net.is_node_free(loc)) { // index 0 reserved
+                self.nloc[got] = loc;
                 got += 1;
                 //println!("ALLOC NODE {} {}", got, self.nput);
             }
@@ -585,15 +818,21 @@ impl TMem {
                 break;
             }
         }
+        if got < num {
+            self.halt(net, HALT_OOM);
+        }
         return got
     }
 
     pub fn vars_alloc(&mut self, net: &GNet, num: usize) -> usize {
+        let len = net.vlen / self.tids as usize;
+        let ini = self.tid as usize * len;
         let mut got = 0;
-        for _ in 0..net.vlen {
-            self.vput += 1; // index 0 reserved for FREE
-            if self.vput < net.vlen-1 || net.is_vars_free(self.vput % net.vlen) {
-                self.vloc[got] = self.vput % net.nlen;
+        for _ in 0..len {
+            self.vput += 1;
+            let loc = ini + self.vput % len;
+            if loc != 0 && (self.vput < len || net.is_vars_free(loc)) { // index 0 reserved for FREE
+                self.vloc[got] = loc;
                 //println!("ALLOC VARS {} {}", got, self.vput);
                 got += 1;
             }
@@ -601,6 +840,9 @@ impl TMem {
                 break;
             }
         }
+        if got < num {
+            self.halt(net, HALT_OOM);
+        }
         got
     }
 
@@ -610,6 +852,44 @@ impl TMem {
         got_node >= need_node && got_vars >= need_vars
     }
 
+    // Writes a copy of a def's net to the net, like a call would, and returns
+    // its root. Redexes aren't copied. Locations are taken MAX_ALLOC at a
+    // time, so defs of any size fit as long as the net has room; otherwise
+    // returns None, with the net halted with HALT_OOM.
+    pub fn inject_def(&mut self, net: &GNet, def: &Def) -> Option<Port> {
+        let mut nloc = Vec::with_capacity(def.node.len());
+        let mut vloc = Vec::with_capacity(def.vars);
+        while nloc.len() < def.node.len() || vloc.len() < def.vars {
+            let need_node = (def.node.len() - nloc.len()).min(MAX_ALLOC);
+            let need_vars = (def.vars - vloc.len()).min(MAX_ALLOC);
+            if !self.get_resources(net, 0, need_node, need_vars) {
+                return None;
+            }
+            // Claims them right away, so later chunks can't take them again
+            for &loc in &self.nloc[..need_node] {
+                net.node_create(loc, Pair::new(Port::new(ERA, 0), Port::new(ERA, 0)));
+                nloc.push(loc);
+            }
+            for &loc in &self.vloc[..need_vars] {
+                net.vars_create(loc, NONE);
+                vloc.push(loc);
+            }
+        }
+        let adjust = |port: Port| {
+            if port.is_nod() {
+                Port::new(port.get_tag(), nloc[port.get_val() as usize] as Val)
+            } else if port.is_var() {
+                Port::new(port.get_tag(), vloc[port.get_val() as usize] as Val)
+            } else {
+                port
+            }
+        };
+        for (i, pair) in def.node.iter().enumerate() {
+            net.node_create(nloc[i], Pair::new(adjust(pair.get_fst()), adjust(pair.get_snd())));
+        }
+        Some(adjust(def.root))
+    }
+
     // Atomically Links `A ~ B`.
     pub fn link(&mut self, net: &GNet, a: Port, b: Port) {
         //println!("link {} ~ {}", a.show(), b.show());
@@ -630,7 +910,7 @@ impl TMem {
             }
 
             // While `B` is VAR: extend it (as an optimization)
-            b = net.enter(b);
+            b = self.enter(net, b);
 
             // Since `A` is VAR: point `A ~> B`.
             if true {
@@ -642,11 +922,19 @@ impl TMem {
                 }
                 // Otherwise, delete `A` (we own both) and link `A' ~ B`
                 net.vars_take(a.get_val() as usize);
+                self.live_vars -= 1;
                 a = a_;
             }
         }
     }
 
+    // Enters a var like `GNet::enter`, counting the vars it takes.
+    fn enter(&mut self, net: &GNet, var: Port) -> Port {
+        let (port, taken) = net.enter_taking(var);
+        self.live_vars -= taken;
+        port
+    }
+
     // Links `A ~ B` (as a pair).
     pub fn link_pair(&mut self, net: &GNet, ab: Pair) {
         self.link(net, ab.get_fst(), ab.get_snd());
@@ -667,28 +955,27 @@ impl TMem {
     }
 
     // The Call Interaction.
-    pub fn interact_call(&mut self, net: &GNet, a: Port, b: Port, book: &Book) -> bool {
+    pub fn interact_call(&mut self, net: &GNet, a: Port, b: Port, book: &Book) -> Result<bool, RuntimeError> {
         let fid = (a.get_val() as usize) & 0xFFFFFFF;
         let def = &book.defs[fid];
 
         // Copy Optimization.
         if b.get_tag() == DUP {
             if def.safe {
-                return self.interact_eras(net, a, b);
+                return Ok(self.interact_eras(net, a, b));
             } else {
                 // TODO:
                 // Currently, we'll not allow copying of REFs with DUPs. While this is perfectly valid on
                 // IC semantics (i.e., if the user know what they're doing), this can lead to unsound
                 // reductions when compiling λ-terms to HVM. So, for now, we'll just disable this feature,
                 // and consider it undefined behavior. We should add a `--unsafe` flag that allows it.
-                println!("ERROR: attempt to clone a non-affine global reference.\n");
-                std::process::exit(0);
+                return Err(RuntimeError::NonAffineRef(def.name.clone()));
             }
         }
 
         // Allocates needed nodes and vars.
         if !self.get_resources(net, def.rbag.len() + 1, def.node.len(), def.vars as usize) {
-            return false;
+            return Ok(false);
         }
 
         // Stores new vars.
@@ -702,6 +989,8 @@ impl TMem {
             net.node_create(self.nloc[i], def.node[i].adjust_pair(self));
             //println!("node_create node_loc[{:04X}] {:016X}", i-1, def.node[i].0);
         }
+        self.live += def.node.len() as i64;
+        self.live_vars += def.vars as i64;
 
         // Links.
         for pair in &def.rbag {
@@ -709,7 +998,7 @@ impl TMem {
         }
         self.link_pair(net, Pair::new(def.root.adjust_port(self), b));
 
-        true
+        Ok(true)
     }
 
     // The Void Interaction.
@@ -733,6 +1022,7 @@ impl TMem {
         let b_ = net.node_exchange(b.get_val() as usize, Pair(0));
         let b1 = b_.get_fst();
         let b2 = b_.get_snd();
+        self.live -= 1;
 
         // Links.
         self.link_pair(net, Pair::new(a, b1));
@@ -761,6 +1051,8 @@ impl TMem {
         let b1 = b_.get_fst();
         let b2 = b_.get_snd();
 
+        self.live -= 2;
+
         // Links.
         self.link_pair(net, Pair::new(a1, b1));
         self.link_pair(net, Pair::new(a2, b2));
@@ -799,6 +1091,8 @@ impl TMem {
         net.node_create(self.nloc[1], Pair::new(Port::new(VAR, self.vloc[2] as u32), Port::new(VAR, self.vloc[3] as u32)));
         net.node_create(self.nloc[2], Pair::new(Port::new(VAR, self.vloc[0] as u32), Port::new(VAR, self.vloc[2] as u32)));
         net.node_create(self.nloc[3], Pair::new(Port::new(VAR, self.vloc[1] as u32), Port::new(VAR, self.vloc[3] as u32)));
+        self.live += 2;
+        self.live_vars += 4;
 
         // Links.
         self.link_pair(net, Pair::new(Port::new(b.get_tag(), self.nloc[0] as u32), a1));
@@ -826,13 +1120,14 @@ impl TMem {
         let av = a.get_val();
         let b_ = net.node_take(b.get_val() as usize);
         let b1 = b_.get_fst();
-        let b2 = net.enter(b_.get_snd());
+        let b2 = self.enter(net, b_.get_snd());
 
         // Performs operation.
         if b1.get_tag() == NUM {
             let bv = b1.get_val();
             let cv = Numb::operate(Numb(av), Numb(bv));
             self.link_pair(net, Pair::new(Port::new(NUM, cv.0), b2));
+            self.live -= 1;
         } else {
             net.node_create(self.nloc[0], Pair::new(Port::new(a.get_tag(), Numb(a.get_val()).0), b2));
             self.link_pair(net, Pair::new(b1, Port::new(OPR, self.nloc[0] as u32)));
@@ -867,17 +1162,18 @@ impl TMem {
             net.node_create(self.nloc[0], Pair::new(Port::new(ERA,0), Port::new(CON, self.nloc[1] as u32)));
             net.node_create(self.nloc[1], Pair::new(Port::new(NUM, Numb::new_u24(av-1).0), b2));
             self.link_pair(net, Pair::new(Port::new(CON, self.nloc[0] as u32), b1));
+            self.live += 1;
         }
 
         true
     }
 
     // Pops a local redex and performs a single interaction.
-    pub fn interact(&mut self, net: &GNet, book: &Book) -> bool {
+    pub fn interact(&mut self, net: &GNet, book: &Book) -> Result<bool, RuntimeError> {
         // Pops a redex.
         let redex = match self.rbag.pop_redex() {
             Some(redex) => redex,
-            None => return true, // If there is no redex, stop
+            None => return Ok(true), // If there is no redex, stop
         };
 
         // Gets redex ports A and B.
@@ -899,7 +1195,7 @@ impl TMem {
 
         let success = match rule {
             LINK => self.interact_link(net, a, b),
-            CALL => self.interact_call(net, a, b, book),
+            CALL => self.interact_call(net, a, b, book)?,
             VOID => self.interact_void(net, a, b),
             ERAS => self.interact_eras(net, a, b),
             ANNI => self.interact_anni(net, a, b),
@@ -912,160 +1208,1070 @@ impl TMem {
         // If error, pushes redex back.
         if !success {
             self.rbag.push_redex(redex);
-            false
-            // Else, increments the interaction count.
-        } else if rule != LINK {
-            self.itrs += 1;
-            true
+            Ok(false)
+            // Else, increments the interaction counts.
         } else {
-            true
+            self.rules[rule as usize] += 1;
+            if rule != LINK {
+                self.itrs += 1;
+            }
+            self.peak_rbag = self.peak_rbag.max(self.rbag.len());
+            self.peak_node = self.peak_node.max(self.live);
+            self.peak_vars = self.peak_vars.max(self.live_vars);
+            Ok(true)
         }
     }
 
-    pub fn evaluator(&mut self, net: &GNet, book: &Book) {
-        // Increments the tick
-        self.tick += 1;
+    // Evaluates redexes along with the other `tids` threads normalizing the
+    // net, which may be fewer than the net was made for.
+    pub fn evaluator(&mut self, net: &GNet, book: &Book, reporter: Option<&Reporter>, tids: u32) -> Result<(), RuntimeError> {
+        // Performs some interactions
+        let mut busy = self.tid == 0;
+        loop {
+            // If we have redexes...
+            if self.rbag.len() > 0 {
+                // Increments the tick
+                self.tick = self.tick.wrapping_add(1);
+                // Stops if the net was halted
+                if (self.tick.is_multiple_of(HALT_CHECK_TICKS) && self.check_halt(net, reporter)) || self.out_of_fuel(net) {
+                    break;
+                }
+                match self.interact(net, book) {
+                    Ok(true) => {}
+                    // The redex was pushed back: retry it, unless the net ran out of memory
+                    Ok(false) => {
+                        if net.halt.load(Ordering::Relaxed) != HALT_NONE {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        // Stops the other threads too
+                        self.halt(net, HALT_FAIL);
+                        net.itrs.fetch_add(self.itrs as u64, Ordering::Relaxed);
+                        self.itrs = 0;
+                        return Err(e);
+                    }
+                }
+                self.share_redex(net);
+            // If we take back our own shared redex...
+            } else if let Some(redex) = self.take_redex(net, self.tid) {
+                self.rbag.push_redex(redex);
+            // If we have no redexes...
+            } else {
+                // Update global idle counter
+                if busy {
+                    net.idle.fetch_add(1, Ordering::SeqCst);
+                    busy = false;
+                }
+                // Attempts to steal a redex from another thread
+                if self.steal_redex(net, tids) {
+                    busy = true;
+                    continue;
+                }
+                // Halt if all threads are idle, or if the net was halted
+                if net.idle.load(Ordering::SeqCst) == tids || self.check_halt(net, reporter) {
+                    break;
+                }
+                std::thread::yield_now();
+            }
+        }
+
+        net.itrs.fetch_add(self.itrs as u64, Ordering::Relaxed);
+        self.itrs = 0;
+        self.publish_load(net);
+        Ok(())
+    }
 
-        // DEBUG:
-        //let mut max_rlen = 0;
-        //let mut max_nlen = 0;
-        //let mut max_vlen = 0;
+    // Halts the net, keeping the first reason given.
+    fn halt(&self, net: &GNet, reason: u32) {
+        _ = net.halt.compare_exchange(HALT_NONE, reason, Ordering::Relaxed, Ordering::Relaxed);
+    }
 
-        // Performs some interactions
-        while self.rbag.len() > 0 {
-            self.interact(net, book);
-
-            // DEBUG:
-            //println!("{}{}", self.rbag.show(), net.show());
-            //println!("");
-            //let rlen = self.rbag.lo.len() + self.rbag.hi.len();
-            //let mut nlen = 0;
-            //for i in 0 .. 256 {
-            //if net.node_load(i).0 != 0 {
-            //nlen += 1;
-            //}
-            //}
-            //let mut vlen = 0;
-            //for i in 0..256 {
-            //if net.vars_load(i).0 != 0 {
-            //vlen += 1;
-            //}
-            //}
-            //max_rlen = max_rlen.max(rlen);
-            //max_nlen = max_nlen.max(nlen);
-            //max_vlen = max_vlen.max(vlen);
-
-        }
-
-        // DEBUG:
-        //println!("MAX_RLEN: {}", max_rlen);
-        //println!("MAX_NLEN: {}", max_nlen);
-        //println!("MAX_VLEN: {}", max_vlen);
+    // Checks if the interaction limit was reached. Other threads' counts are
+    // only seen when they flush them, so with many threads the limit may be
+    // overshot by a few thousand interactions.
+    fn out_of_fuel(&self, net: &GNet) -> bool {
+        if net.fuel == u64::MAX || net.itrs.load(Ordering::Relaxed) + (self.itrs as u64) < net.fuel {
+            return false;
+        }
+        self.halt(net, HALT_FUEL);
+        true
+    }
 
+    // Flushes the interaction count and load, reports progress, and checks
+    // the deadline and cancellation.
+    fn check_halt(&mut self, net: &GNet, reporter: Option<&Reporter>) -> bool {
         net.itrs.fetch_add(self.itrs as u64, Ordering::Relaxed);
         self.itrs = 0;
+        self.publish_load(net);
+        if let Some(reporter) = reporter.filter(|_| self.tid == 0) {
+            if !reporter.poll(net) {
+                self.halt(net, HALT_STOP);
+            }
+        }
+        net.check_limits()
+    }
+
+    // Publishes our load, and counts our peaks since the last call, along with
+    // the other threads' loads, towards the net's.
+    fn publish_load(&mut self, net: &GNet) {
+        let load = &net.load[self.tid as usize];
+        load.rbag_lo.store(self.rbag.lo.len() as u64, Ordering::Relaxed);
+        load.rbag_hi.store(self.rbag.hi.len() as u64, Ordering::Relaxed);
+        load.live.store(self.live, Ordering::Relaxed);
+        load.live_vars.store(self.live_vars, Ordering::Relaxed);
+        let (mut rbag, mut node, mut vars) = (self.peak_rbag as u64, self.peak_node, self.peak_vars);
+        for (_, load) in net.load.iter().enumerate().filter(|(tid, _)| *tid != self.tid as usize) {
+            rbag += load.rbag_lo.load(Ordering::Relaxed) + load.rbag_hi.load(Ordering::Relaxed);
+            node += load.live.load(Ordering::Relaxed);
+            vars += load.live_vars.load(Ordering::Relaxed);
+        }
+        net.peak.rbag.fetch_max(rbag, Ordering::Relaxed);
+        net.peak.node.fetch_max(node.max(0) as u64, Ordering::Relaxed);
+        net.peak.vars.fetch_max(vars.max(0) as u64, Ordering::Relaxed);
+        self.peak_rbag = self.rbag.len();
+        self.peak_node = self.live;
+        self.peak_vars = self.live_vars;
+    }
+
+    // Puts our oldest redex up for stealing when another thread is idle.
+    fn share_redex(&mut self, net: &GNet) {
+        let slot = &net.share[self.tid as usize].0;
+        if self.rbag.lo.len() > 1 && net.idle.load(Ordering::Relaxed) > 0 && slot.load(Ordering::Relaxed) == 0 {
+            if let Some(redex) = self.rbag.lo.pop_front() {
+                slot.store(redex.0, Ordering::SeqCst);
+            }
+        }
+    }
+
+    // Takes the redex shared by thread `sid`, if any.
+    fn take_redex(&self, net: &GNet, sid: u32) -> Option<Pair> {
+        let slot = &net.share[sid as usize].0;
+        if slot.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        match slot.swap(0, Ordering::SeqCst) {
+            0 => None,
+            got => Some(Pair(got)),
+        }
+    }
+
+    // Steals a shared redex from another thread. The thread counts itself as
+    // busy before taking it, so the others can't halt while it holds work.
+    fn steal_redex(&mut self, net: &GNet, tids: u32) -> bool {
+        for i in 1..tids {
+            let sid = (self.tid + i) % tids;
+            if net.share[sid as usize].0.load(Ordering::Relaxed) == 0 {
+                continue;
+            }
+            net.idle.fetch_sub(1, Ordering::SeqCst);
+            if let Some(redex) = self.take_redex(net, sid) {
+                self.rbag.push_redex(redex);
+                return true;
+            }
+            net.idle.fetch_add(1, Ordering::SeqCst);
+        }
+        false
     }
 }
 
-// Serialization
-// -------------
+// Normalizer
+// ----------
 
-impl Book {
-    pub fn to_buffer(&self, buf: &mut Vec<u8>) {
-        // Writes the number of defs
-        buf.extend_from_slice(&(self.defs.len() as u32).to_ne_bytes());
+impl<'a> GNet<'a> {
+    // Halts the net if the host cancelled it or the deadline passed. Returns
+    // whether the net is halted.
+    pub fn check_limits(&self) -> bool {
+        let reason = if self.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed) != 0) {
+            HALT_STOP
+        } else if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            HALT_TIME
+        } else {
+            HALT_NONE
+        };
+        if reason != HALT_NONE {
+            _ = self.halt.compare_exchange(HALT_NONE, reason, Ordering::Relaxed, Ordering::Relaxed);
+        }
+        self.halt.load(Ordering::Relaxed) != HALT_NONE
+    }
 
-        // For each def
-        for (fid, def) in self.defs.iter().enumerate() {
-            // Writes the safe flag
-            buf.extend_from_slice(&(fid as u32).to_ne_bytes());
-
-            // Writes the name
-            // TODO: store as varlen to save space
-            let name_bytes = def.name.as_bytes();
-            if name_bytes.len() < 256 {
-                buf.extend_from_slice(&name_bytes[..name_bytes.len()]);
-                buf.resize(buf.len() + (256 - name_bytes.len()), 0);
-            } else {
-                panic!("Name too long: {}", def.name);
+    // Sets the initial redex.
+    pub fn boot_redex(&self, tm: &mut TMem, redex: Pair) {
+        self.vars_create(ROOT.get_val() as usize, NONE);
+        tm.rbag.push_redex(redex);
+    }
+
+    // Sets an initial redex that calls a def with `args` as its λ-arguments:
+    // `@def ~ (arg0 (arg1 ... ROOT))`. Returns None if they don't fit.
+    pub fn boot_call(&self, tm: &mut TMem, fid: Val, args: &[Def]) -> Option<()> {
+        let mut ret = ROOT;
+        for arg in args.iter().rev() {
+            let arg = tm.inject_def(self, arg)?;
+            if !tm.get_resources(self, 0, 1, 0) {
+                return None;
             }
+            self.node_create(tm.nloc[0], Pair::new(arg, ret));
+            ret = Port::new(CON, tm.nloc[0] as Val);
+        }
+        self.boot_redex(tm, Pair::new(Port::new(REF, fid), ret));
+        Some(())
+    }
+
+    // Evaluates all redexes, with one thread per TMem. The redexes must be in
+    // `tms[0]`. If a thread panics, the others are halted and the panic is
+    // resumed once they're joined. A single TMem is evaluated on the calling
+    // thread.
+    pub fn normalize(&self, tms: &mut [TMem], book: &Book, reporter: Option<&Reporter>) -> Result<(), RuntimeError> {
+        let tids = tms.len() as u32;
+        self.idle.store(tids - 1, Ordering::Relaxed);
+        if let [tm] = tms {
+            return tm.evaluator(self, book, reporter, 1);
+        }
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = tms.iter_mut().map(|tm| {
+                scope.spawn(move || {
+                    let outcome = catch_unwind(AssertUnwindSafe(|| tm.evaluator(self, book, reporter, tids)));
+                    if outcome.is_err() {
+                        // Stops the other threads, which would otherwise wait on this one forever
+                        self.halt.store(HALT_FAIL, Ordering::Relaxed);
+                    }
+                    outcome
+                })
+            }).collect();
+            let outcomes: Vec<_> = threads.into_iter().map(|thread| thread.join()).collect();
+            outcomes.into_iter().map(|outcome| match outcome {
+                Ok(Ok(outcome)) => outcome,
+                Ok(Err(payload)) | Err(payload) => resume_unwind(payload),
+            }).collect::<Result<Vec<_>, _>>()
+        })?;
+        Ok(())
+    }
 
-            // Writes the safe flag
-            buf.extend_from_slice(&(def.safe as u32).to_ne_bytes());
+    // Peeks a variable's final target without modifying it.
+    pub fn peek(&self, mut var: Port) -> Port {
+        while var.get_tag() == VAR {
+            let val = self.vars_load(var.get_val() as usize);
+            if val == NONE || val == Port(0) {
+                break;
+            }
+            var = val;
+        }
+        var
+    }
+}
+
+// Monadic IO
+// ----------
+
+// IO Magic Number
+pub const IO_MAGIC_0 : u32 = 0xD0CA11;
+pub const IO_MAGIC_1 : u32 = 0xFF1FF1;
+
+// IO Tags
+pub const IO_DONE : u32 = 0;
+pub const IO_CALL : u32 = 1;
+
+// List Type
+pub const LIST_NIL  : u32 = 0;
+pub const LIST_CONS : u32 = 1;
 
-            // Writes the rbag length
-            buf.extend_from_slice(&(def.rbag.len() as u32).to_ne_bytes());
+// File descriptors, of which 0, 1 and 2 are stdin, stdout and stderr
+pub const MAX_FILES : usize = 256;
 
-            // Writes the node length
-            buf.extend_from_slice(&(def.node.len() as u32).to_ne_bytes());
+// How long SLEEP sleeps between checks of the deadline and cancellation
+pub const SLEEP_SLICE_NS : u64 = 10_000_000;
 
-            // Writes the vars length
-            buf.extend_from_slice(&(def.vars as u32).to_ne_bytes());
+// What IO a program may do, as set by `IoPolicy` in hvm.h. Anything not
+// listed is denied, unless the policy is unrestricted, and denied calls return
+// `*` like any other failed IO call.
+pub struct IoPolicy {
+    pub dirs: Vec<(PathBuf, bool)>, // canonical paths, and whether they're writable
+    pub ffns: Vec<String>, // names of the functions programs may call
+    pub max_files: usize, // how many files may be open at once
+    pub unrestricted: bool, // allows all IO, ignoring the lists above
+}
+
+// What evaluations without a policy get
+pub static NO_IO : IoPolicy = IoPolicy { dirs: Vec::new(), ffns: Vec::new(), max_files: 0, unrestricted: false };
 
-            // Writes the root
-            buf.extend_from_slice(&def.root.0.to_ne_bytes());
+impl IoPolicy {
+    pub fn allows_ffn(&self, name: &str) -> bool {
+        self.unrestricted || self.ffns.iter().any(|ffn| ffn == name)
+    }
 
-            // Writes the rbag buffer
-            for pair in &def.rbag {
-                buf.extend_from_slice(&pair.0.to_ne_bytes());
+    // The path programs may open for `path`, for writing or not, if any.
+    // Like `policy_allows_path` in hvm.c, files that don't exist yet are
+    // resolved through their parent directory, so links can't lead out of the
+    // allowed ones. The resolved path is returned, so a link swapped in after
+    // the check isn't followed, or `path` as is when unrestricted.
+    pub fn allows_path(&self, path: &Path, write: bool) -> Option<PathBuf> {
+        if self.unrestricted {
+            return Some(path.to_path_buf());
+        }
+        let resolved = match std::fs::canonicalize(path) {
+            Ok(resolved) => resolved,
+            Err(_) => {
+                let name = path.file_name()?;
+                let parent = match path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent,
+                    _ => Path::new("."),
+                };
+                std::fs::canonicalize(parent).ok()?.join(name)
             }
+        };
+        // The innermost dir decides, so a read-only dir can be nested in a
+        // writable one
+        let dir = self.dirs.iter().filter(|(dir, _)| resolved.starts_with(dir)).max_by_key(|(dir, _)| dir.as_os_str().len());
+        dir.is_some_and(|(_, writable)| !write || *writable).then_some(resolved)
+    }
+}
 
-            // Writes the node buffer
-            for pair in &def.node {
-                buf.extend_from_slice(&pair.0.to_ne_bytes());
+// Converts an fopen mode to open options. Like `open_mode_flags` in hvm.c, it
+// takes "r", "w" or "a", then any of "+", "b" and, after "w", "x", each at
+// most once.
+fn open_options(mode: &str) -> Option<OpenOptions> {
+    let mut options = OpenOptions::new();
+    let mut chars = mode.chars();
+    let kind = chars.next()?;
+    match kind {
+        'r' => options.read(true),
+        'w' => options.write(true).create(true).truncate(true),
+        'a' => options.append(true).create(true),
+        _ => return None,
+    };
+    let (mut plus, mut binary, mut excl) = (false, false, false);
+    for chr in chars {
+        match chr {
+            '+' if !plus => {
+                plus = true;
+                options.read(true).write(true);
+            }
+            'b' if !binary => binary = true,
+            'x' if !excl && kind == 'w' => {
+                excl = true;
+                options.create_new(true);
             }
+            _ => return None,
         }
     }
+    Some(options)
+}
 
-    pub fn to_buffer_safe(&self, buf: &mut Vec<u8>) -> Result<(), String> {
-        // Writes the number of defs
-        buf.extend_from_slice(&(self.defs.len() as u32).to_ne_bytes());
+// Readback: λ-Encoded Ctr
+pub struct Ctr {
+    pub tag: u32, // u32::MAX if the port isn't a constructor
+    pub args: Vec<Port>,
+}
 
-        // For each def
-        for (fid, def) in self.defs.iter().enumerate() {
-            // Writes the safe flag
-            buf.extend_from_slice(&(fid as u32).to_ne_bytes());
-
-            // Writes the name
-            // TODO: store as varlen to save space
-            let name_bytes = def.name.as_bytes();
-            if name_bytes.len() < 256 {
-                buf.extend_from_slice(&name_bytes[..name_bytes.len()]);
-                buf.resize(buf.len() + (256 - name_bytes.len()), 0);
-            } else {
-                return Err(format!("Name too long: {}", def.name));
+// A Foreign Function
+pub struct FFn {
+    pub name: &'static str,
+    pub func: fn(&mut Io, Port) -> Port,
+}
+
+// The built-in foreign functions, as registered by `book_init` in hvm.c
+pub const FFNS : [FFn; 7] = [
+    FFn { name: "READ_CHAR", func: |io, argm| io.read_char(argm) },
+    FFn { name: "READ_LINE", func: |io, argm| io.read_line(argm) },
+    FFn { name: "OPEN_FILE", func: |io, argm| io.open_file(argm) },
+    FFn { name: "CLOSE_FILE", func: |io, argm| io.close_file(argm) },
+    FFn { name: "WRITE", func: |io, argm| io.write(argm) },
+    FFn { name: "GET_TIME", func: |io, argm| io.get_time(argm) },
+    FFn { name: "SLEEP", func: |io, argm| io.sleep(argm) },
+];
+
+// Foreign functions registered by the host, which are looked up after the
+// built-in ones
+pub trait HostFFns {
+    // Calls the function named `name`, or returns None if there's none.
+    fn call(&self, io: &mut Io, name: &str, argm: Port) -> Option<Port>;
+}
+
+// Registers no functions
+impl HostFFns for () {
+    fn call(&self, _io: &mut Io, _name: &str, _argm: Port) -> Option<Port> {
+        None
+    }
+}
+
+// Gets output as it's written, on fd 1 or 2
+pub type Sink<'n> = &'n mut dyn FnMut(u32, &[u8]);
+
+// A program's own stdio, as set by `STDIO_CAPTURE` in hvm.h. Input comes from
+// a buffer, and output is captured, or streamed to `sink` as it's written.
+pub struct Stdio<'n> {
+    pub input: &'n [u8], // advanced as it's read
+    pub output: [Vec<u8>; 2], // captured stdout and stderr
+    pub sink: Option<Sink<'n>>,
+}
+
+impl<'n> Stdio<'n> {
+    fn write(&mut self, fd: u32, bytes: &[u8]) {
+        match &mut self.sink {
+            Some(sink) => sink(fd, bytes),
+            None => self.output[fd as usize - 1].extend_from_slice(bytes),
+        }
+    }
+}
+
+// Runs IO computations on a net. Like hvm.c, strings are bytes: each cell of
+// a string read back keeps its low byte, and each byte read in is a cell.
+pub struct Io<'n, 'a> {
+    pub net: &'n GNet<'a>,
+    pub tms: &'n mut [TMem],
+    pub book: &'n Book,
+    pub host: &'n dyn HostFFns,
+    pub policy: &'n IoPolicy,
+    pub stdio: Option<Stdio<'n>>, // None = the process' stdio
+    pub files: Vec<Option<File>>, // indexed by file descriptor
+    pub reporter: Option<&'n Reporter<'n>>,
+}
+
+impl<'n, 'a> Io<'n, 'a> {
+    pub fn new(net: &'n GNet<'a>, tms: &'n mut [TMem], book: &'n Book, host: &'n dyn HostFFns, policy: &'n IoPolicy, stdio: Option<Stdio<'n>>, reporter: Option<&'n Reporter<'n>>) -> Self {
+        Io { net, tms, book, host, policy, stdio, files: (0..MAX_FILES).map(|_| None).collect(), reporter }
+    }
+
+    // Reports a failed IO call on stderr.
+    pub fn report(&mut self, message: &str) {
+        _ = self.write_bytes(2, format!("{}\n", message).as_bytes());
+    }
+
+    fn normalize(&mut self) -> Result<(), RuntimeError> {
+        self.net.normalize(self.tms, self.book, self.reporter)
+    }
+
+    // Normalizes with thread 0 alone, for strings, which are read back a cell
+    // at a time, so spawning threads would take longer than the evaluation.
+    fn normalize_cell(&mut self) -> Result<(), RuntimeError> {
+        self.net.normalize(&mut self.tms[..1], self.book, self.reporter)
+    }
+
+    fn is_halted(&self) -> bool {
+        self.net.halt.load(Ordering::Relaxed) != HALT_NONE
+    }
+
+    // Expands a REF port, keeping the root as it was.
+    pub fn expand(&mut self, port: Port) -> Result<Port, RuntimeError> {
+        let old = self.net.vars_load(ROOT.get_val() as usize);
+        let mut got = self.net.peek(port);
+        while got.get_tag() == REF && !self.is_halted() {
+            self.net.boot_redex(&mut self.tms[0], Pair::new(Port::new(REF, got.get_val()), ROOT));
+            self.normalize()?;
+            got = self.net.peek(self.net.vars_load(ROOT.get_val() as usize));
+        }
+        self.net.vars_create(ROOT.get_val() as usize, old);
+        Ok(got)
+    }
+
+    // Reads back a λ-Encoded constructor.
+    // Encoding: λt ((((t TAG) arg0) arg1) ...)
+    pub fn readback_ctr(&mut self, port: Port) -> Result<Ctr, RuntimeError> {
+        let mut ctr = Ctr { tag: u32::MAX, args: Vec::new() };
+
+        // Loads root lambda
+        let lam_port = self.expand(port)?;
+        if lam_port.get_tag() != CON {
+            return Ok(ctr);
+        }
+        let lam_node = self.net.node_load(lam_port.get_val() as usize);
+
+        // Loads first application
+        let app_port = self.expand(lam_node.get_fst())?;
+        if app_port.get_tag() != CON {
+            return Ok(ctr);
+        }
+        let mut app_node = self.net.node_load(app_port.get_val() as usize);
+
+        // Loads first argument (as the tag)
+        let arg_port = self.expand(app_node.get_fst())?;
+        if arg_port.get_tag() != NUM {
+            return Ok(ctr);
+        }
+        ctr.tag = Numb(arg_port.get_val()).get_u24();
+
+        // Loads remaining arguments
+        loop {
+            let app_port = self.expand(app_node.get_snd())?;
+            if app_port.get_tag() != CON {
+                break;
+            }
+            app_node = self.net.node_load(app_port.get_val() as usize);
+            let arg_port = self.expand(app_node.get_fst())?;
+            ctr.args.push(arg_port);
+        }
+
+        Ok(ctr)
+    }
+
+    // Reads back a λ-Encoded string, normalizing each cell in turn.
+    // Encoding:
+    // - λt (t NIL)
+    // - λt (((t CONS) head) tail)
+    pub fn readback_str(&mut self, mut port: Port) -> Result<Vec<u8>, RuntimeError> {
+        let mut text = Vec::new();
+        loop {
+            self.normalize_cell()?;
+            let ctr = self.readback_ctr(self.net.peek(port))?;
+            if ctr.tag != LIST_CONS || ctr.args.len() != 2 || ctr.args[0].get_tag() != NUM {
+                break;
+            }
+            let chr = Numb(ctr.args[0].get_val()).get_u24();
+            text.push(chr as u8);
+            self.net.boot_redex(&mut self.tms[0], Pair::new(ctr.args[1], ROOT));
+            port = ROOT;
+        }
+        Ok(text)
+    }
+
+    // Writes a λ-Encoded string to the net. Returns ERA if it doesn't fit.
+    pub fn inject_str(&mut self, text: &[u8]) -> Port {
+        let net = self.net;
+        let tm = &mut self.tms[0];
+
+        // NIL: λt (t NIL)
+        if !tm.get_resources(net, 0, 2, 1) {
+            self.report("inject_str: failed to get resources");
+            return Port::new(ERA, 0);
+        }
+        let var = Port::new(VAR, tm.vloc[0] as Val);
+        net.vars_create(tm.vloc[0], NONE);
+        net.node_create(tm.nloc[0], Pair::new(Port::new(NUM, Numb::new_u24(LIST_NIL).0), var));
+        net.node_create(tm.nloc[1], Pair::new(Port::new(CON, tm.nloc[0] as Val), var));
+        let mut port = Port::new(CON, tm.nloc[1] as Val);
+
+        // CONS: λt (((t CONS) head) tail)
+        for &chr in text.iter().rev() {
+            if !tm.get_resources(net, 0, 4, 1) {
+                self.report("inject_str: failed to get resources");
+                return Port::new(ERA, 0);
+            }
+            let var = Port::new(VAR, tm.vloc[0] as Val);
+            let head = Port::new(NUM, Numb::new_u24(chr as u32).0);
+            net.vars_create(tm.vloc[0], NONE);
+            net.node_create(tm.nloc[0], Pair::new(port, var));
+            net.node_create(tm.nloc[1], Pair::new(head, Port::new(CON, tm.nloc[0] as Val)));
+            net.node_create(tm.nloc[2], Pair::new(Port::new(NUM, Numb::new_u24(LIST_CONS).0), Port::new(CON, tm.nloc[1] as Val)));
+            net.node_create(tm.nloc[3], Pair::new(Port::new(CON, tm.nloc[2] as Val), var));
+            port = Port::new(CON, tm.nloc[3] as Val);
+        }
+
+        port
+    }
+
+    // Runs an IO computation.
+    pub fn run(&mut self, mut port: Port) -> Result<(), RuntimeError> {
+        loop {
+            // Normalizes the net
+            self.normalize()?;
+            if self.is_halted() {
+                break;
+            }
+
+            // Reads the λ-Encoded Ctr
+            let ctr = self.readback_ctr(self.net.peek(port))?;
+
+            // Checks the IO Magic Number
+            let magic = match ctr.args.first() {
+                Some(magic) if magic.get_tag() == CON => self.net.node_load(magic.get_val() as usize),
+                _ => break,
+            };
+            if magic.get_fst().get_val() != Numb::new_u24(IO_MAGIC_0).0 || magic.get_snd().get_val() != Numb::new_u24(IO_MAGIC_1).0 {
+                break;
+            }
+
+            match ctr.tag {
+                IO_DONE => break,
+                IO_CALL if ctr.args.len() >= 4 => {}
+                _ => break,
+            }
+            let func = String::from_utf8_lossy(&self.readback_str(ctr.args[1])?).into_owned();
+            let argm = ctr.args[2];
+            let cont = ctr.args[3];
+            let host = self.host;
+            let ret = match FFNS.iter().find(|ffn| ffn.name == func) {
+                _ if !self.policy.allows_ffn(&func) => {
+                    self.report(&format!("io_call: permission denied: {}", func));
+                    Port::new(ERA, 0)
+                }
+                Some(ffn) => (ffn.func)(self, argm),
+                None => match host.call(self, &func, argm) {
+                    Some(ret) => ret,
+                    None => break,
+                },
+            };
+            if self.tms[0].node_alloc(self.net, 1) < 1 {
+                self.report("run_io: failed to get resources");
+                break;
+            }
+            let loc = self.tms[0].nloc[0];
+            self.net.node_create(loc, Pair::new(ret, ROOT));
+            self.net.boot_redex(&mut self.tms[0], Pair::new(Port::new(CON, loc as Val), cont));
+            port = ROOT;
+        }
+        Ok(())
+    }
+
+    // Converts a NUM port to a file descriptor.
+    fn readback_fd(&mut self, port: Port) -> Option<usize> {
+        if port.get_tag() != NUM {
+            self.report(&format!("non-num where file descriptor was expected: {}", port.get_tag()));
+            return None;
+        }
+        let fd = Numb(port.get_val()).get_u24() as usize;
+        if fd > 2 && self.files.get(fd).is_none_or(Option::is_none) {
+            self.report("invalid file descriptor");
+            return None;
+        }
+        Some(fd)
+    }
+
+    fn read_byte(&mut self, fd: usize) -> std::io::Result<Option<u8>> {
+        let mut buf = [0u8; 1];
+        let len = match fd {
+            0 => match &mut self.stdio {
+                Some(stdio) => stdio.input.read(&mut buf)?,
+                None => std::io::stdin().read(&mut buf)?,
+            },
+            1 | 2 => return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "can't read from an output stream")),
+            _ => self.files[fd].as_mut().unwrap().read(&mut buf)?,
+        };
+        Ok(if len == 0 { None } else { Some(buf[0]) })
+    }
+
+    fn write_bytes(&mut self, fd: usize, bytes: &[u8]) -> std::io::Result<()> {
+        match fd {
+            0 => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "can't write to an input stream")),
+            1 | 2 if self.stdio.is_some() => {
+                self.stdio.as_mut().unwrap().write(fd as u32, bytes);
+                Ok(())
+            }
+            1 => std::io::stdout().write_all(bytes),
+            2 => std::io::stderr().write_all(bytes),
+            _ => self.files[fd].as_mut().unwrap().write_all(bytes),
+        }
+    }
+
+    // Reads a single char from `argm`.
+    fn read_char(&mut self, argm: Port) -> Port {
+        let fd = match self.readback_fd(self.net.peek(argm)) {
+            Some(fd) => fd,
+            None => return Port::new(ERA, 0),
+        };
+        // Reads a single byte, or nothing at the end of the input
+        let bytes = match self.read_byte(fd) {
+            Ok(Some(byte)) => vec![byte],
+            _ => Vec::new(),
+        };
+        self.inject_str(&bytes)
+    }
+
+    // Reads from `argm` until a newline is seen.
+    fn read_line(&mut self, argm: Port) -> Port {
+        let fd = match self.readback_fd(self.net.peek(argm)) {
+            Some(fd) => fd,
+            None => {
+                self.report("io_read_line: invalid file descriptor");
+                return Port::new(ERA, 0);
+            }
+        };
+        // Reads up to a newline, which is dropped. Like hvm.c, finding nothing
+        // left to read is reported.
+        let mut bytes = Vec::new();
+        loop {
+            match self.read_byte(fd) {
+                Ok(Some(b'\n')) => break,
+                Ok(Some(byte)) => bytes.push(byte),
+                Ok(None) => {
+                    if bytes.is_empty() {
+                        self.report("io_read_line: failed to read");
+                    }
+                    break;
+                }
+                Err(e) => {
+                    self.report(&format!("io_read_line: failed to read: {}", e));
+                    break;
+                }
+            }
+        }
+        self.inject_str(&bytes)
+    }
+
+    // Opens a file with the provided mode.
+    // `argm` is a tuple (CON node) of the
+    // file name and mode as strings.
+    fn open_file(&mut self, argm: Port) -> Port {
+        let argm = self.net.peek(argm);
+        if argm.get_tag() != CON {
+            self.report("io_open_file: expected tuple");
+            return Port::new(ERA, 0);
+        }
+        let args = self.net.node_load(argm.get_val() as usize);
+        let (name, mode) = match (self.readback_str(args.get_fst()), self.readback_str(args.get_snd())) {
+            (Ok(name), Ok(mode)) => (String::from_utf8_lossy(&name).into_owned(), String::from_utf8_lossy(&mode).into_owned()),
+            _ => return Port::new(ERA, 0),
+        };
+        // Any mode but "r" may write or create the file
+        let write = mode.contains(['w', 'a', '+']);
+        let path = match self.policy.allows_path(Path::new(&name), write) {
+            Some(path) => path,
+            None => {
+                self.report(&format!("io_open_file: permission denied: {}", name));
+                return Port::new(ERA, 0);
+            }
+        };
+        if !self.policy.unrestricted && self.files.iter().filter(|file| file.is_some()).count() >= self.policy.max_files {
+            self.report("io_open_file: too many open files");
+            return Port::new(ERA, 0);
+        }
+        let fd = match (3..MAX_FILES).find(|&fd| self.files[fd].is_none()) {
+            Some(fd) => fd,
+            None => {
+                self.report("io_open_file: too many open files");
+                return Port::new(ERA, 0);
+            }
+        };
+        let mut options = match open_options(&mode) {
+            Some(options) => options,
+            None => {
+                self.report(&format!("io_open_file: invalid mode: {}", mode));
+                return Port::new(ERA, 0);
+            }
+        };
+        // The checked path is opened, without following a link put there since
+        if !self.policy.unrestricted {
+            options.custom_flags(libc::O_NOFOLLOW);
+        }
+        match options.open(&path) {
+            Ok(file) => {
+                self.files[fd] = Some(file);
+                Port::new(NUM, Numb::new_u24(fd as u32).0)
+            }
+            Err(e) => {
+                self.report(&format!("io_open_file: failed to open {}: {}", name, e));
+                Port::new(ERA, 0)
+            }
+        }
+    }
+
+    // Closes a file, reclaiming the file descriptor.
+    fn close_file(&mut self, argm: Port) -> Port {
+        match self.readback_fd(self.net.peek(argm)) {
+            Some(fd) if fd > 2 => self.files[fd] = None,
+            _ => self.report("io_close_file: failed to close"),
+        }
+        Port::new(ERA, 0)
+    }
+
+    // Writes a string to a file.
+    // `argm` is a tuple (CON node) of the
+    // file descriptor and string to write.
+    fn write(&mut self, argm: Port) -> Port {
+        let argm = self.net.peek(argm);
+        if argm.get_tag() != CON {
+            self.report(&format!("io_write: expected tuple, but got {}", argm.get_tag()));
+            return Port::new(ERA, 0);
+        }
+        let args = self.net.node_load(argm.get_val() as usize);
+        let fd = self.readback_fd(self.net.peek(args.get_fst()));
+        let text = match self.readback_str(args.get_snd()) {
+            Ok(text) => text,
+            Err(_) => return Port::new(ERA, 0),
+        };
+        match fd {
+            Some(fd) => {
+                if let Err(e) = self.write_bytes(fd, &text) {
+                    self.report(&format!("io_write: failed to write: {}", e));
+                }
+            }
+            None => self.report("io_write: invalid file descriptor"),
+        }
+        Port::new(ERA, 0)
+    }
+
+    // Returns the current time as a tuple of the high
+    // and low 24 bits of a 48-bit nanosecond timestamp.
+    fn get_time(&mut self, _argm: Port) -> Port {
+        let time_ns = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+        let time_hi = ((time_ns >> 24) & 0xFFFFFFF) as u32;
+        let time_lo = (time_ns & 0xFFFFFFF) as u32;
+        if self.tms[0].node_alloc(self.net, 1) < 1 {
+            return Port::new(ERA, 0);
+        }
+        let loc = self.tms[0].nloc[0];
+        self.net.node_create(loc, Pair::new(Port::new(NUM, Numb::new_u24(time_hi).0), Port::new(NUM, Numb::new_u24(time_lo).0)));
+        Port::new(CON, loc as Val)
+    }
+
+    // Sleeps.
+    // `argm` is a tuple (CON node) of the high and low
+    // 24 bits for a 48-bit duration in nanoseconds.
+    // Sleeps in slices of SLEEP_SLICE_NS, and stops early if the net halts,
+    // so the deadline and cancellation still apply.
+    fn sleep(&mut self, argm: Port) -> Port {
+        let argm = self.net.peek(argm);
+        if argm.get_tag() != CON {
+            return Port::new(ERA, 0);
+        }
+        let dur_node = self.net.node_load(argm.get_val() as usize);
+        let dur_hi = Numb(self.net.peek(dur_node.get_fst()).get_val()).get_u24() as u64;
+        let dur_lo = Numb(self.net.peek(dur_node.get_snd()).get_val()).get_u24() as u64;
+        let end = Instant::now() + Duration::from_nanos((dur_hi << 24) | dur_lo);
+        while !self.net.check_limits() {
+            let left = end.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            std::thread::sleep(left.min(Duration::from_nanos(SLEEP_SLICE_NS)));
+        }
+        Port::new(ERA, 0)
+    }
+}
+
+// Serialization
+// -------------
+
+// Books are stored in the v2 format, which `book_load` in hvm.c reads too:
+// - header: "HVMB", then the version, the FNV-1a checksum of the body and the
+//   body length, as little-endian u32, u32 and u64
+// - body: the number of defs, then for each def its name (length-prefixed),
+//   safe flag (one byte), rbag, node and vars lengths, root, rbag and nodes
+// Counts and lengths are LEB128 varints; ports and pairs are little-endian.
+//
+// The v1 format has no header and stores native-endian u32 words: the number
+// of defs, then for each def its fid, its name padded to 256 bytes, the safe
+// flag, the lengths, the root and the buffers. It's still read.
+pub const BOOK_MAGIC : [u8; 4] = *b"HVMB";
+pub const BOOK_VERSION : u32 = 2;
+const BOOK_HEADER_LEN : usize = 20;
+
+impl Book {
+    pub fn to_buffer(&self, buf: &mut Vec<u8>) {
+        // Writes the body
+        let mut body = Vec::new();
+        write_varint(&mut body, self.defs.len() as u64);
+        for def in &self.defs {
+            write_varint(&mut body, def.name.len() as u64);
+            body.extend_from_slice(def.name.as_bytes());
+            body.push(def.safe as u8);
+            write_varint(&mut body, def.rbag.len() as u64);
+            write_varint(&mut body, def.node.len() as u64);
+            write_varint(&mut body, def.vars as u64);
+            body.extend_from_slice(&def.root.0.to_le_bytes());
+            for pair in def.rbag.iter().chain(def.node.iter()) {
+                body.extend_from_slice(&pair.0.to_le_bytes());
+            }
+        }
+
+        // Writes the header, then the body
+        buf.extend_from_slice(&BOOK_MAGIC);
+        buf.extend_from_slice(&BOOK_VERSION.to_le_bytes());
+        buf.extend_from_slice(&checksum(&body).to_le_bytes());
+        buf.extend_from_slice(&(body.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&body);
+    }
+
+    // Reads a book in either format, checking its layout and that every port
+    // points inside its def or the book.
+    pub fn from_buffer(buf: &[u8]) -> Result<Self, String> {
+        // A v1 buffer would need over a billion defs to start with the magic
+        let book = if buf.starts_with(&BOOK_MAGIC) {
+            Book::from_buffer_v2(buf)?
+        } else {
+            Book::from_buffer_v1(buf)?
+        };
+
+        // Checks the names, and the ports once all fids are known
+        for (fid, def) in book.defs.iter().enumerate() {
+            if def.name.is_empty() || def.name.contains('\0') {
+                return Err(format!("Def {} has an invalid name", fid));
+            }
+            if book.defs[..fid].iter().any(|other| other.name == def.name) {
+                return Err(format!("Duplicate definition: {}", def.name));
             }
+            def.validate(book.defs.len())?;
+        }
 
-            // Writes the safe flag
-            buf.extend_from_slice(&(def.safe as u32).to_ne_bytes());
+        Ok(book)
+    }
 
-            // Writes the rbag length
-            buf.extend_from_slice(&(def.rbag.len() as u32).to_ne_bytes());
+    fn from_buffer_v2(buf: &[u8]) -> Result<Self, String> {
+        // Reads and checks the header
+        let mut at = BOOK_MAGIC.len();
+        let version = u32::from_le_bytes(read_bytes(buf, &mut at)?);
+        if version != BOOK_VERSION {
+            return Err(format!("Unsupported book format version: {}", version));
+        }
+        let sum = u32::from_le_bytes(read_bytes(buf, &mut at)?);
+        let body_len = u64::from_le_bytes(read_bytes(buf, &mut at)?);
+        if body_len != (buf.len() - BOOK_HEADER_LEN) as u64 {
+            return Err(format!("Expected a body of {} bytes, found {}", body_len, buf.len() - BOOK_HEADER_LEN));
+        }
+        if checksum(&buf[at..]) != sum {
+            return Err("Checksum mismatch".to_string());
+        }
 
-            // Writes the node length
-            buf.extend_from_slice(&(def.node.len() as u32).to_ne_bytes());
+        // Reads the defs
+        let defs_len = read_varint(buf, &mut at)?;
+        let mut book = Book { defs: Vec::new() };
+        for _ in 0..defs_len {
+            let name_len = read_varint(buf, &mut at)? as usize;
+            let name = match buf.get(at..at.saturating_add(name_len)).map(std::str::from_utf8) {
+                Some(Ok(name)) => name.to_string(),
+                Some(Err(_)) => { return Err(format!("Def {} has a name that isn't UTF-8", book.defs.len())); }
+                None => { return Err(format!("Unexpected end of buffer at byte {}", at)); }
+            };
+            at += name_len;
+            let safe = match read_bytes::<1>(buf, &mut at)?[0] {
+                0 => false,
+                1 => true,
+                flag => { return Err(format!("Def {} has an invalid safe flag: {}", name, flag)); }
+            };
+            let rbag_len = read_varint(buf, &mut at)?;
+            let node_len = read_varint(buf, &mut at)?;
+            let vars = read_varint(buf, &mut at)? as usize;
+            let root = Port(u32::from_le_bytes(read_bytes(buf, &mut at)?));
+            let mut rbag = Vec::new();
+            for _ in 0..rbag_len {
+                rbag.push(Pair(u64::from_le_bytes(read_bytes(buf, &mut at)?)));
+            }
+            let mut node = Vec::new();
+            for _ in 0..node_len {
+                node.push(Pair(u64::from_le_bytes(read_bytes(buf, &mut at)?)));
+            }
+            book.defs.push(Def { name, safe, root, rbag, node, vars });
+        }
+        if at != buf.len() {
+            return Err(format!("Unexpected {} trailing bytes", buf.len() - at));
+        }
+
+        Ok(book)
+    }
 
-            // Writes the vars length
-            buf.extend_from_slice(&(def.vars as u32).to_ne_bytes());
+    fn from_buffer_v1(buf: &[u8]) -> Result<Self, String> {
+        let mut at = 0;
 
-            // Writes the root
-            buf.extend_from_slice(&def.root.0.to_ne_bytes());
+        // Reads the number of defs
+        let defs_len = u32::from_ne_bytes(read_bytes(buf, &mut at)?) as usize;
 
-            // Writes the rbag buffer
-            for pair in &def.rbag {
-                buf.extend_from_slice(&pair.0.to_ne_bytes());
+        // For each def
+        let mut book = Book { defs: Vec::new() };
+        for index in 0..defs_len {
+            // Reads the fid, which must match the def's position
+            let fid = u32::from_ne_bytes(read_bytes(buf, &mut at)?);
+            if fid as usize != index {
+                return Err(format!("Def {} is stored with fid {}", index, fid));
             }
 
-            // Writes the node buffer
-            for pair in &def.node {
-                buf.extend_from_slice(&pair.0.to_ne_bytes());
+            // Reads the NUL-padded name
+            let name_bytes: [u8; 256] = read_bytes(buf, &mut at)?;
+            let name_len = match name_bytes.iter().position(|&byte| byte == 0) {
+                Some(name_len) => name_len,
+                None => { return Err(format!("Def {} has an invalid name", fid)); }
+            };
+            let name = match std::str::from_utf8(&name_bytes[..name_len]) {
+                Ok(name) => name.to_string(),
+                Err(_) => { return Err(format!("Def {} has a name that isn't UTF-8", fid)); }
+            };
+
+            // Reads the safe flag
+            let safe = match u32::from_ne_bytes(read_bytes(buf, &mut at)?) {
+                0 => false,
+                1 => true,
+                flag => { return Err(format!("Def {} has an invalid safe flag: {}", name, flag)); }
+            };
+
+            // Reads the lengths and the root
+            let rbag_len = u32::from_ne_bytes(read_bytes(buf, &mut at)?);
+            let node_len = u32::from_ne_bytes(read_bytes(buf, &mut at)?);
+            let vars = u32::from_ne_bytes(read_bytes(buf, &mut at)?) as usize;
+            let root = Port(u32::from_ne_bytes(read_bytes(buf, &mut at)?));
+
+            // Reads the rbag and node buffers
+            let mut rbag = Vec::new();
+            for _ in 0..rbag_len {
+                rbag.push(Pair(u64::from_ne_bytes(read_bytes(buf, &mut at)?)));
+            }
+            let mut node = Vec::new();
+            for _ in 0..node_len {
+                node.push(Pair(u64::from_ne_bytes(read_bytes(buf, &mut at)?)));
             }
+
+            book.defs.push(Def { name, safe, root, rbag, node, vars });
+        }
+        if at != buf.len() {
+            return Err(format!("Unexpected {} trailing bytes", buf.len() - at));
         }
 
+        Ok(book)
+    }
+}
+
+impl Def {
+    // Checks that every port refers to a var or node of this def, or to a def
+    // of a book with `defs_len` defs.
+    fn validate(&self, defs_len: usize) -> Result<(), String> {
+        let ports = self.rbag.iter().chain(self.node.iter()).flat_map(|pair| [pair.get_fst(), pair.get_snd()]);
+        for port in std::iter::once(self.root).chain(ports) {
+            let val = port.get_val() as usize;
+            let valid = match port.get_tag() {
+                VAR => val < self.vars,
+                REF => val & 0x0FFFFFFF < defs_len, // may carry the par flag
+                ERA | NUM => true,
+                _ => val < self.node.len(),
+            };
+            if !valid {
+                return Err(format!("Def {} has an out of range port: {}", self.name, port.show()));
+            }
+        }
         Ok(())
     }
 }
 
+// Reads the next `N` bytes of a buffer, advancing `at`.
+fn read_bytes<const N: usize>(buf: &[u8], at: &mut usize) -> Result<[u8; N], String> {
+    match buf.get(*at..*at + N) {
+        Some(bytes) => {
+            let mut out = [0; N];
+            out.copy_from_slice(bytes);
+            *at += N;
+            Ok(out)
+        }
+        None => Err(format!("Unexpected end of buffer at byte {}", *at)),
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut val: u64) {
+    while val >= 0x80 {
+        buf.push((val as u8) | 0x80);
+        val >>= 7;
+    }
+    buf.push(val as u8);
+}
+
+fn read_varint(buf: &[u8], at: &mut usize) -> Result<u64, String> {
+    let start = *at;
+    let mut val = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_bytes::<1>(buf, at)?[0];
+        val |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(val);
+        }
+    }
+    Err(format!("Invalid varint at byte {}", start))
+}
+
+// 32-bit FNV-1a, as computed by `book_checksum` in hvm.c
+fn checksum(buf: &[u8]) -> u32 {
+    let mut hash : u32 = 0x811C9DC5;
+    for &byte in buf {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
 // Debug
 // -----
 
@@ -1110,6 +2316,12 @@ impl RBag {
 
 impl<'a> GNet<'a> {
     pub fn show(&self) -> String {
+        let mut s = self.show_node();
+        s.push_str(&self.show_vars());
+        s
+    }
+
+    pub fn show_node(&self) -> String {
         let mut s = String::new();
         s.push_str("NODE | FST-PORT     | SND-PORT     \n");
         s.push_str("---- | ------------ | ------------\n");
@@ -1121,6 +2333,11 @@ impl<'a> GNet<'a> {
             }
         }
         s.push_str("==== | ============ | ============\n");
+        s
+    }
+
+    pub fn show_vars(&self) -> String {
+        let mut s = String::new();
         s.push_str("VARS | VALUE        |\n");
         s.push_str("---- | ------------ |\n");
         //for i in 0..256 {
@@ -1130,10 +2347,18 @@ impl<'a> GNet<'a> {
                 s.push_str(&format!("{:04X} | {} |\n", i, var.show()));
             }
         }
-        let root = self.vars_load(0x1FFFFFFF);
+        let root = self.vars_load(ROOT.get_val() as usize);
         s.push_str(&format!("ROOT | {} |\n", root.show()));
         s.push_str("==== | ============ |\n");
-        return s;
+        s
+    }
+}
+
+impl RuntimeError {
+    pub fn show(&self) -> String {
+        match self {
+            RuntimeError::NonAffineRef(name) => format!("attempt to clone a non-affine global reference `@{}`", name),
+        }
     }
 }
 
@@ -1269,3 +2494,73 @@ fn test_f24() {
     assert!(Numb::new_f24(f32::from_bits(0b1_11111111_000000000000000_00000001)).get_f24().is_nan());
     assert!(Numb::new_f24(f32::from_bits(0b0_11111111_111111111111111_11111111)).get_f24().is_nan());
 }
+
+#[cfg(test)]
+fn test_book() -> Book {
+    let code = "
+@main = a
+  & @sum ~ (3 a)
+@sum = (?((0 @sum__C0) a) a)
+@sum__C0 = ({a b} c)
+  &! @sum ~ (a $([+] $(b c)))
+";
+    crate::ast::Book::parse(code).unwrap().build().unwrap()
+}
+
+#[test]
+fn test_book_v1() {
+    // Writes the book in the v1 layout
+    let book = test_book();
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&(book.defs.len() as u32).to_ne_bytes());
+    for (fid, def) in book.defs.iter().enumerate() {
+        buf.extend_from_slice(&(fid as u32).to_ne_bytes());
+        buf.extend_from_slice(def.name.as_bytes());
+        buf.resize(buf.len() + 256 - def.name.len(), 0);
+        for word in [def.safe as u32, def.rbag.len() as u32, def.node.len() as u32, def.vars as u32, def.root.0] {
+            buf.extend_from_slice(&word.to_ne_bytes());
+        }
+        for pair in def.rbag.iter().chain(def.node.iter()) {
+            buf.extend_from_slice(&pair.0.to_ne_bytes());
+        }
+    }
+
+    // Reads it back the same as the book it came from
+    let mut expected = Vec::new();
+    book.to_buffer(&mut expected);
+    let mut got = Vec::new();
+    Book::from_buffer(&buf).unwrap().to_buffer(&mut got);
+    assert_eq!(got, expected);
+
+    // Rejects a bad layout
+    assert_eq!(Book::from_buffer(&buf[..buf.len() - 1]).err().unwrap(), format!("Unexpected end of buffer at byte {}", buf.len() - 8));
+    let mut bad = buf.clone();
+    bad.push(0);
+    assert_eq!(Book::from_buffer(&bad).err().unwrap(), "Unexpected 1 trailing bytes");
+    let mut bad = buf.clone();
+    bad[4] = 9;
+    assert_eq!(Book::from_buffer(&bad).err().unwrap(), "Def 0 is stored with fid 9");
+}
+
+#[test]
+fn test_book_v2() {
+    let book = test_book();
+    let mut buf = Vec::new();
+    book.to_buffer(&mut buf);
+    assert!(buf.starts_with(&BOOK_MAGIC));
+
+    // Round-trips
+    let mut again = Vec::new();
+    Book::from_buffer(&buf).unwrap().to_buffer(&mut again);
+    assert_eq!(again, buf);
+
+    // Rejects corrupted or unknown buffers
+    let mut bad = buf.clone();
+    *bad.last_mut().unwrap() ^= 1;
+    assert_eq!(Book::from_buffer(&bad).err().unwrap(), "Checksum mismatch");
+    let mut bad = buf.clone();
+    bad[4] = 3;
+    assert_eq!(Book::from_buffer(&bad).err().unwrap(), "Unsupported book format version: 3");
+    let body_len = buf.len() - BOOK_HEADER_LEN;
+    assert_eq!(Book::from_buffer(&buf[..buf.len() - 1]).err().unwrap(), format!("Expected a body of {} bytes, found {}", body_len, body_len - 1));
+}
@@ -1,90 +1,902 @@
-use std::ffi::{CStr, CString};
+use std::ffi::{c_void, CStr, CString};
 use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use crate::ast::Book;
+use crate::builder::{BookBuilder, INVALID_ID};
+use crate::codec::{Shape, Value, ValueKind};
+use crate::ffn::{HostFFn, HostFFnsRaw, HostFn, HostValue};
+use crate::result::ResultTree;
+use crate::error::{Error, ErrorKind, ErrorRaw};
 
 mod hvm;
 mod ast;
+mod error;
+mod builder;
+mod result;
+mod codec;
+mod ffn;
 
 #[cfg(feature = "c")]
 extern "C" {
-    fn hvm_c(book_buffer: *const u32, enable_mem_dump: u32) -> *mut EvaluationResultRaw;
+    fn hvm_c(book_buffer: *const u8, book_len: u64, options: *const EvaluationOptions, host: *const HostFFnsRaw, session: *mut c_void, args: *const HostValue, args_len: u32, err_kind: *mut ErrorKind, err_buf: *mut c_char, err_len: u32) -> *mut EvaluationResultRaw;
+    fn hvm_c_session_new(options: *const EvaluationOptions) -> *mut c_void;
+    fn hvm_c_session_free(session: *mut c_void);
 }
 
+// Largest node/vars buffer addressable by a 29-bit port value
+const MAX_NET_LEN: u64 = 1 << 29;
+const DEFAULT_NET_LEN: u64 = 1 << 29;
+const MAX_THREADS: u32 = 1024;
+
+// Stack size of the threads the library starts to evaluate on. Reading back,
+// parsing and flattening a result recurse once per list item, so they get
+// more than the 8 MiB a main thread usually has.
+const EVALUATION_STACK_SIZE: usize = 64 << 20;
+
+// The host passes these as u32s, which are checked with `from_raw`, since an
+// out of range value can't be read as the enum.
 #[repr(u32)]
+#[derive(Clone, Copy)]
 pub enum RuntimeTypes {
     RUST = 0,
     C = 1
 }
 
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MemDumpModes {
+    NONE = 0,
+    NODES = 1,
+    FULL = 2,
+}
+
+// Where a program's stdin, stdout and stderr go
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum StdioModes {
+    INHERIT = 0, // the process' own
+    CAPTURE = 1, // stdin from `stdin_buf`, output captured or streamed to `output`
+}
+
+impl RuntimeTypes {
+    fn from_raw(raw: u32) -> Result<Self, Error> {
+        match raw {
+            0 => Ok(RuntimeTypes::RUST),
+            1 => Ok(RuntimeTypes::C),
+            _ => Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid runtime type: {}", raw))),
+        }
+    }
+}
+
+impl MemDumpModes {
+    fn from_raw(raw: u32) -> Result<Self, Error> {
+        match raw {
+            0 => Ok(MemDumpModes::NONE),
+            1 => Ok(MemDumpModes::NODES),
+            2 => Ok(MemDumpModes::FULL),
+            _ => Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid memory dump mode: {}", raw))),
+        }
+    }
+}
+
+impl StdioModes {
+    fn from_raw(raw: u32) -> Result<Self, Error> {
+        match raw {
+            0 => Ok(StdioModes::INHERIT),
+            1 => Ok(StdioModes::CAPTURE),
+            _ => Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid stdio mode: {}", raw))),
+        }
+    }
+}
+
+// Called with output as a program writes it, on fd 1 (stdout) or 2 (stderr)
+pub type OutputFn = unsafe extern "C" fn(ctx: *mut c_void, fd: u32, buf: *const u8, len: u64);
+
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum EvaluationStatus {
+    COMPLETED = 0,
+    FUEL_EXHAUSTED = 1,
+    TIMED_OUT = 2,
+    CANCELLED = 3,
+}
+
+// Called with progress reports during an evaluation. Returning 0 halts it,
+// and it then finishes with a `CANCELLED` status.
+pub type ProgressFn = unsafe extern "C" fn(ctx: *mut c_void, progress: *const hvm::Progress) -> u32;
+
+// Called once an async evaluation finishes, from the thread it ran on
+pub type CompletionFn = unsafe extern "C" fn(ctx: *mut c_void);
+
+// Shared with the host, which may cancel an evaluation from another thread
+#[repr(C)]
+pub struct CancellationToken {
+    cancelled: AtomicU32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct EvaluationOptions {
+    node_len: u64, // node buffer length (0 = default)
+    vars_len: u64, // vars buffer length (0 = default)
+    threads: u32, // evaluator threads (0 = runtime default)
+    mem_dump: u32, // what to dump from the net after evaluation: one of MemDumpModes
+    entry: *const c_char, // entry definition name (null = "main")
+    max_interactions: u64, // interaction limit (0 = unlimited)
+    timeout_ms: u64, // wall-clock limit in milliseconds (0 = unlimited)
+    cancellation: *const CancellationToken, // cancellation handle (null = none)
+    io_policy: *const IoPolicy, // IO allow-list (null = no IO)
+    stdio: u32, // one of StdioModes
+    stdin_buf: *const u8, // CAPTURE: what programs read from stdin
+    stdin_len: u64,
+    output: Option<OutputFn>, // CAPTURE: streams output instead of capturing it (null = capture)
+    output_ctx: *mut c_void, // passed back to `output` as is
+    args: *const *const c_void, // λ-arguments for the entry definition: `Value`s made with the `value_*` exports
+    args_len: u64,
+    progress: Option<ProgressFn>, // called with progress reports (null = none)
+    progress_ctx: *mut c_void, // passed back to `progress` as is
+    progress_interactions: u64, // reports every this many interactions (0 = never)
+    progress_interval_ms: u64, // and every this many milliseconds (0 = never)
+}
+
+// A directory programs may open files in, along with its subdirectories
+#[repr(C)]
+pub struct IoDirectory {
+    path: *const c_char,
+    writable: u32, // whether files may be written or created (0 = read-only)
+}
+
+// What IO a program may do. Anything not listed is denied, so a zeroed
+// policy denies all IO, and so does a null one. See `hvm::IoPolicy`.
+#[repr(C)]
+pub struct IoPolicy {
+    dirs: *const IoDirectory,
+    dirs_len: u64,
+    ffns: *const *const c_char, // names of the functions programs may call
+    ffns_len: u64,
+    max_files: u32, // how many files may be open at once
+    unrestricted: u32, // allows all IO, ignoring the lists above (0 = only what's listed)
+}
+
+// What `book_parse` hands to the host: the built book, plus the source nets
+// it was built from so definitions can be inspected. Deserialized books have
+// no source, so their definitions are decompiled instead.
+pub struct BookHandle {
+    book: hvm::Book,
+    source: Option<Book>,
+    ffns: Vec<HostFFn>, // registered with `book_register_ffn`
+}
+
+// What `session_new` hands to the host: a net kept alive between evaluations,
+// so they don't pay for allocating and freeing it. Evaluations on a session
+// must not overlap.
+pub struct SessionHandle {
+    net: SessionNet,
+}
+
+enum SessionNet {
+    Rust(hvm::GNet<'static>, Vec<hvm::TMem>),
+    C(*mut c_void), // the C runtime's `Net`
+}
+
+impl Drop for SessionNet {
+    fn drop(&mut self) {
+        #[cfg(feature = "c")]
+        if let SessionNet::C(net) = *self {
+            unsafe { hvm_c_session_free(net) };
+        }
+    }
+}
+
+// One evaluation of a batch: the λ-arguments for the entry definition
+#[repr(C)]
+pub struct BatchItem {
+    args: *const *const c_void, // `Value`s, as in `EvaluationOptions`
+    args_len: u64,
+}
+
+// What `book_evaluate_batch` hands to the host, with one entry per item:
+// either a result or an error
+#[repr(C)]
+pub struct BatchResultRaw {
+    results: *mut *mut EvaluationResultRaw, // null where the item failed
+    errors: *mut *mut ErrorRaw, // null where the item succeeded
+    len: u64,
+}
+
+// Host pointers shared by batch workers. The host keeps them valid for the
+// batch, and its callbacks must be safe to call from any thread.
+struct Shared<T>(T);
+
+unsafe impl<T> Send for Shared<T> {}
+unsafe impl<T> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    // Closures must capture the wrapper, not the pointers in it
+    fn get(&self) -> &T {
+        &self.0
+    }
+}
+
+// What `book_evaluate_async` hands to the host: an evaluation running on a
+// thread of its own
+pub struct EvaluationHandle {
+    state: Arc<AsyncState>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// Shared by an async evaluation and its handle
+struct AsyncState {
+    outcome: Mutex<Shared<AsyncOutcome>>,
+    done: Condvar,
+    cancellation: CancellationToken,
+}
+
+enum AsyncOutcome {
+    Running,
+    Done(*mut EvaluationResultRaw, *mut ErrorRaw), // one of them is null
+    Taken,
+}
+
+impl Drop for EvaluationHandle {
+    fn drop(&mut self) {
+        self.state.cancellation.cancelled.store(1, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            // Freed by the completion callback, which runs on that thread
+            if thread.thread().id() != std::thread::current().id() {
+                _ = thread.join();
+            }
+        }
+        if let AsyncOutcome::Done(result, err) = self.state.outcome.lock().unwrap_or_else(|e| e.into_inner()).0 {
+            unsafe {
+                if !result.is_null() {
+                    free_evaluation_result(result);
+                }
+                if !err.is_null() {
+                    ErrorRaw::free(err);
+                }
+            }
+        }
+    }
+}
+
+// What `book_builder_new` hands to the host. Failed builder calls return
+// `INVALID_ID`, and the first failure is kept for `book_builder_build`.
+pub struct BuilderHandle {
+    builder: BookBuilder,
+    error: Option<Error>,
+}
+
+// Per-definition stats reported by `book_def_info`
+#[repr(C)]
+pub struct DefInfo {
+    fid: u32,
+    safe: u32, // whether the def has no dups
+    node_len: u64,
+    rbag_len: u64,
+    vars_len: u64,
+}
+
+// What `value_get_info` reports about a value
+#[repr(C)]
+pub struct ValueInfo {
+    kind: ValueKind,
+    bits: u32, // U24/I24/BOOL: the value; F24: its bits; CTR: the tag
+    len: u64, // STRING: byte length; LIST/TUPLE/CTR: item count
+}
+
+impl BookHandle {
+    // Finds a definition by fid.
+    fn def(&self, fid: u32) -> Result<&hvm::Def, Error> {
+        match self.book.defs.get(fid as usize) {
+            Some(def) => Ok(def),
+            None => Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("No definition with fid {}", fid))),
+        }
+    }
+
+    // Finds a definition's source net, decompiling it if there's none.
+    fn def_net(&self, def: &hvm::Def) -> Result<ast::Net, Error> {
+        if let Some(net) = self.source.as_ref().and_then(|source| source.defs.get(&def.name)) {
+            return Ok(net.clone());
+        }
+        let fids = self.book.defs.iter().enumerate().map(|(fid, def)| (fid as hvm::Val, def.name.clone())).collect();
+        match ast::Net::decompile(def, &fids) {
+            Some(net) => Ok(net),
+            None => Err(Error::new(ErrorKind::FORMAT, format!("Definition `@{}` can't be decompiled", def.name))),
+        }
+    }
+
+    fn def_source(&self, fid: u32) -> Result<String, Error> {
+        Ok(self.def_net(self.def(fid)?)?.show())
+    }
+
+    // Decodes a read back term, expanding refs to this book's definitions.
+    fn decode(&self, term: &str, shape: &Shape) -> Result<Value, Error> {
+        let tree = ast::Tree::parse(term).map_err(|e| Error::new(ErrorKind::PARSE, e))?;
+        self.decode_tree(&tree, shape)
+    }
+
+    fn decode_tree(&self, tree: &ast::Tree, shape: &Shape) -> Result<Value, Error> {
+        let mut defs = |nam: &str| self.book.defs.iter().find(|def| def.name == nam).and_then(|def| self.def_net(def).ok());
+        Value::decode(tree, shape, &mut defs)
+    }
+}
+
+struct EvaluationConfig {
+    node_len: u64,
+    vars_len: u64,
+    threads: u32,
+    mem_dump: MemDumpModes,
+    entry: CString,
+    max_interactions: u64,
+    timeout_ms: u64,
+    cancellation: *const CancellationToken,
+    policy: Option<IoPolicyConfig>, // None = no IO
+    stdio: StdioModes,
+    stdin: Vec<u8>, // copied, so async evaluations don't borrow the host's buffer
+    output: Option<OutputFn>,
+    output_ctx: *mut c_void,
+    args: Vec<hvm::Def>,
+    args_node: Vec<Vec<u64>>, // the args' nodes, for the C runtime
+    raw_args: Vec<HostValue>, // points into `args_node`
+    progress: Option<ProgressFn>,
+    progress_ctx: *mut c_void,
+    progress_interactions: u64,
+    progress_interval_ms: u64,
+}
+
+// A validated IO policy, along with the copy handed to the C runtime, whose
+// dirs are canonical too
+struct IoPolicyConfig {
+    policy: hvm::IoPolicy,
+    raw: IoPolicy, // points into the buffers below
+    _dirs: Vec<CString>,
+    _raw_dirs: Vec<IoDirectory>,
+    _ffns: Vec<CString>,
+    _raw_ffns: Vec<*const c_char>,
+}
+
+impl IoPolicyConfig {
+    unsafe fn from_raw(policy: &IoPolicy) -> Result<Self, Error> {
+        if policy.max_files as usize > hvm::MAX_FILES - 3 {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid open file limit: {} (expected at most {})", policy.max_files, hvm::MAX_FILES - 3)));
+        }
+        let mut dirs = Vec::new();
+        for i in 0..policy.dirs_len as usize {
+            if policy.dirs.is_null() {
+                return Err(Error::new(ErrorKind::INVALID_ARGUMENT, "IO policy directories are null"));
+            }
+            let dir = &*policy.dirs.add(i);
+            let path = read_text(dir.path)?;
+            let canonical = match std::fs::canonicalize(path) {
+                Ok(canonical) if canonical.is_dir() => canonical,
+                Ok(_) => return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Not a directory: {}", path))),
+                Err(e) => return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid directory {}: {}", path, e))),
+            };
+            dirs.push((canonical, dir.writable != 0));
+        }
+        let mut ffns = Vec::new();
+        for i in 0..policy.ffns_len as usize {
+            if policy.ffns.is_null() {
+                return Err(Error::new(ErrorKind::INVALID_ARGUMENT, "IO policy functions are null"));
+            }
+            ffns.push(read_text(*policy.ffns.add(i))?.to_string());
+        }
+
+        // Copies the policy for the C runtime
+        let mut c_dirs = Vec::new();
+        for (dir, _) in &dirs {
+            match dir.to_str() {
+                Some(dir) => c_dirs.push(to_cstring(dir.to_string())),
+                None => return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Directory isn't UTF-8: {}", dir.display()))),
+            }
+        }
+        let raw_dirs: Vec<IoDirectory> = c_dirs.iter().zip(&dirs).map(|(path, (_, writable))| IoDirectory { path: path.as_ptr(), writable: *writable as u32 }).collect();
+        let c_ffns: Vec<CString> = ffns.iter().map(|ffn| to_cstring(ffn.clone())).collect();
+        let raw_ffns: Vec<*const c_char> = c_ffns.iter().map(|ffn| ffn.as_ptr()).collect();
+        let raw = IoPolicy {
+            dirs: raw_dirs.as_ptr(),
+            dirs_len: raw_dirs.len() as u64,
+            ffns: raw_ffns.as_ptr(),
+            ffns_len: raw_ffns.len() as u64,
+            max_files: policy.max_files,
+            unrestricted: policy.unrestricted,
+        };
+        Ok(IoPolicyConfig {
+            policy: hvm::IoPolicy { dirs, ffns, max_files: policy.max_files as usize, unrestricted: policy.unrestricted != 0 },
+            raw,
+            _dirs: c_dirs,
+            _raw_dirs: raw_dirs,
+            _ffns: c_ffns,
+            _raw_ffns: raw_ffns,
+        })
+    }
+}
+
 #[repr(C)]
 pub struct EvaluationResultRaw {
     iterations: u64,
     time: f64,
+    status: EvaluationStatus,
     result: *mut c_char,
     mem_dump: *mut c_char,
-    deallocator: extern "C" fn(*mut EvaluationResultRaw) -> ()
+    deallocator: extern "C" fn(*mut EvaluationResultRaw) -> (),
+    tree: *mut ResultTree, // structured result (null unless completed), freed by `free_evaluation_result`
+    stdout: *mut u8, // captured output (null unless captured)
+    stdout_len: u64,
+    stderr: *mut u8,
+    stderr_len: u64,
+    rules: [u64; 8], // interactions performed, indexed by rule (`iterations` counts all but LINK)
+    // Most redexes, nodes and vars in use at once: exact on one thread, and
+    // may miss short peaks on more (see `hvm::Peak`)
+    peak_rbag: u64,
+    peak_node: u64,
+    peak_vars: u64,
 }
 
 struct EvaluationResult {
     iterations: u64,
     time: f64,
+    status: EvaluationStatus,
     result: String,
     mem_dump: String,
+    tree: Option<ResultTree>,
+    stdout: Option<Vec<u8>>,
+    stderr: Option<Vec<u8>>,
+    rules: [u64; 8],
+    peak_rbag: u64,
+    peak_node: u64,
+    peak_vars: u64,
+}
+
+impl EvaluationResult {
+    fn into_raw(self) -> *mut EvaluationResultRaw {
+        let EvaluationResult { iterations, time, status, result, mem_dump, tree, stdout, stderr, rules, peak_rbag, peak_node, peak_vars } = self;
+        Box::into_raw(Box::from(EvaluationResultRaw {
+            iterations,
+            time,
+            status,
+            result: to_cstring(result).into_raw(),
+            mem_dump: to_cstring(mem_dump).into_raw(),
+            deallocator: drop_evaluation_result_raw,
+            tree: tree.map_or(std::ptr::null_mut(), |tree| Box::into_raw(Box::new(tree))),
+            stdout_len: stdout.as_ref().map_or(0, |stdout| stdout.len() as u64),
+            stdout: stdout.map_or(std::ptr::null_mut(), |stdout| Box::into_raw(stdout.into_boxed_slice()) as *mut u8),
+            stderr_len: stderr.as_ref().map_or(0, |stderr| stderr.len() as u64),
+            stderr: stderr.map_or(std::ptr::null_mut(), |stderr| Box::into_raw(stderr.into_boxed_slice()) as *mut u8),
+            rules,
+            peak_rbag,
+            peak_node,
+            peak_vars,
+        }))
+    }
+}
+
+impl EvaluationConfig {
+    // Reads and validates host options, filling in defaults.
+    unsafe fn from_raw(options: *const EvaluationOptions) -> Result<Self, Error> {
+        let mut config = EvaluationConfig {
+            node_len: DEFAULT_NET_LEN,
+            vars_len: DEFAULT_NET_LEN,
+            threads: 0,
+            mem_dump: MemDumpModes::NONE,
+            entry: CString::new("main").unwrap(),
+            max_interactions: 0,
+            timeout_ms: 0,
+            cancellation: std::ptr::null(),
+            policy: None,
+            stdio: StdioModes::INHERIT,
+            stdin: Vec::new(),
+            output: None,
+            output_ctx: std::ptr::null_mut(),
+            args: Vec::new(),
+            args_node: Vec::new(),
+            raw_args: Vec::new(),
+            progress: None,
+            progress_ctx: std::ptr::null_mut(),
+            progress_interactions: 0,
+            progress_interval_ms: 0,
+        };
+        if options.is_null() {
+            return Ok(config);
+        }
+        let options = &*options;
+        if options.node_len != 0 {
+            config.node_len = options.node_len;
+        }
+        if options.vars_len != 0 {
+            config.vars_len = options.vars_len;
+        }
+        if config.node_len < 2 || config.node_len > MAX_NET_LEN {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid node buffer length: {} (expected 2..={})", config.node_len, MAX_NET_LEN)));
+        }
+        if config.vars_len < 2 || config.vars_len > MAX_NET_LEN {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid vars buffer length: {} (expected 2..={})", config.vars_len, MAX_NET_LEN)));
+        }
+        if options.threads > MAX_THREADS {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid thread count: {} (expected at most {})", options.threads, MAX_THREADS)));
+        }
+        config.threads = options.threads;
+        config.mem_dump = MemDumpModes::from_raw(options.mem_dump)?;
+        if !options.entry.is_null() {
+            config.entry = CStr::from_ptr(options.entry).to_owned();
+        }
+        config.max_interactions = options.max_interactions;
+        config.timeout_ms = options.timeout_ms;
+        config.cancellation = options.cancellation;
+        if !options.io_policy.is_null() {
+            config.policy = Some(IoPolicyConfig::from_raw(&*options.io_policy)?);
+        }
+        config.stdio = StdioModes::from_raw(options.stdio)?;
+        if options.stdin_len != 0 && options.stdin_buf.is_null() {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, "Stdin buffer is null"));
+        }
+        if options.stdin_len != 0 {
+            config.stdin = std::slice::from_raw_parts(options.stdin_buf, options.stdin_len as usize).to_vec();
+        }
+        config.output = options.output;
+        config.output_ctx = options.output_ctx;
+        if options.args_len != 0 && options.args.is_null() {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, "Arguments are null"));
+        }
+        for i in 0..options.args_len as usize {
+            let arg = *options.args.add(i) as *const Value;
+            if arg.is_null() {
+                return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Argument {} is null", i)));
+            }
+            let def = (*arg).build(&format!("arg{}", i)).map_err(|e| Error::new(ErrorKind::INVALID_ARGUMENT, format!("Argument {}: {}", i, e.message)))?;
+            config.args_node.push(def.node.iter().map(|pair| pair.0).collect());
+            config.args.push(def);
+        }
+        config.raw_args = config.args.iter().zip(&config.args_node).map(|(def, node)| HostValue::new(def, node)).collect();
+        if options.progress.is_some() && options.progress_interactions == 0 && options.progress_interval_ms == 0 {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, "Progress reports need an interaction count or an interval"));
+        }
+        config.progress = options.progress;
+        config.progress_ctx = options.progress_ctx;
+        config.progress_interactions = options.progress_interactions;
+        config.progress_interval_ms = options.progress_interval_ms;
+        Ok(config)
+    }
+
+    // Builds the options handed to the C runtime. Borrows `entry` and the
+    // policy.
+    fn to_raw(&self) -> EvaluationOptions {
+        EvaluationOptions {
+            node_len: self.node_len,
+            vars_len: self.vars_len,
+            threads: self.threads,
+            mem_dump: self.mem_dump as u32,
+            entry: self.entry.as_ptr(),
+            max_interactions: self.max_interactions,
+            timeout_ms: self.timeout_ms,
+            cancellation: self.cancellation,
+            io_policy: self.policy.as_ref().map_or(std::ptr::null(), |policy| &policy.raw),
+            stdio: self.stdio as u32,
+            stdin_buf: self.stdin.as_ptr(),
+            stdin_len: self.stdin.len() as u64,
+            output: self.output,
+            output_ctx: self.output_ctx,
+            // Handed to `hvm_c` as `raw_args` instead
+            args: std::ptr::null(),
+            args_len: 0,
+            progress: self.progress,
+            progress_ctx: self.progress_ctx,
+            progress_interactions: self.progress_interactions,
+            progress_interval_ms: self.progress_interval_ms,
+        }
+    }
+
+    // Finds the fid of the entry definition.
+    fn entry_fid(&self, book: &hvm::Book) -> Result<usize, Error> {
+        let entry = self.entry.to_string_lossy();
+        match book.defs.iter().position(|def| def.name == entry) {
+            Some(fid) => Ok(fid),
+            None => Err(Error::new(ErrorKind::MISSING_ENTRY, format!("No `@{}` definition found", entry))),
+        }
+    }
+}
+
+fn rust_evaluate(book: &hvm::Book, host: &dyn hvm::HostFFns, config: &EvaluationConfig) -> Result<EvaluationResult, Error> {
+    // Finds the entry definition
+    let entry_fid = config.entry_fid(book)?;
+
+    let (mut net, mut tms) = rust_net(config)?;
+    rust_evaluate_on(&mut net, &mut tms, book, host, config, entry_fid)
 }
 
-fn rust_evaluate(book: &hvm::Book, enable_mem_dump: u32) -> Result<EvaluationResult, CString> {
+// Allocates a net for the Rust runtime, along with its thread memories.
+fn rust_net(config: &EvaluationConfig) -> Result<(hvm::GNet<'static>, Vec<hvm::TMem>), Error> {
+    // Each thread allocates from its own slice of the buffers
+    let threads = match config.threads {
+        0 => num_cpus::get() as u64,
+        threads => threads as u64,
+    };
+    let tids = threads.min(config.node_len / 2).min(config.vars_len / 2).max(1) as u32;
+
     // Initializes the global net
-    let net = hvm::GNet::new(1 << 29, 1 << 29);
+    let net = match hvm::GNet::new(config.node_len as usize, config.vars_len as usize, tids) {
+        Some(net) => net,
+        None => { return Err(Error::new(ErrorKind::OOM, format!("Failed to allocate a net of {} nodes and {} vars", config.node_len, config.vars_len))); }
+    };
 
     // Initializes threads
-    let mut tm = hvm::TMem::new(0, 1);
+    let tms: Vec<hvm::TMem> = (0..tids).map(|tid| hvm::TMem::new(tid, tids)).collect();
+    Ok((net, tms))
+}
 
-    // Creates an initial redex that calls main
-    let main_id = match book.defs.iter().position(|def| def.name == "main"){
-        Some(v) => v,
-        None => { return Err(CString::new("No main function found").unwrap()); }
+// Evaluates the entry definition on a clean net.
+fn rust_evaluate_on(net: &mut hvm::GNet, tms: &mut [hvm::TMem], book: &hvm::Book, host: &dyn hvm::HostFFns, config: &EvaluationConfig, entry_fid: usize) -> Result<EvaluationResult, Error> {
+    // Creates an initial redex that calls the entry definition with the arguments
+    // This only fails when thread 0's share of the net can't hold them, like
+    // in the C runtime
+    if net.boot_call(&mut tms[0], entry_fid as hvm::Val, &config.args).is_none() {
+        return Err(Error::new(ErrorKind::OOM, format!("The net ran out of space ({} nodes and {} vars)", net.nlen, net.vlen)));
+    }
+
+    // Starts the timer
+    let start = Instant::now();
+
+    // Sets the halting conditions
+    if config.max_interactions != 0 {
+        net.fuel = config.max_interactions;
+    }
+    if config.timeout_ms != 0 {
+        net.deadline = Some(start + Duration::from_millis(config.timeout_ms));
+    }
+    if !config.cancellation.is_null() {
+        net.cancel = Some(unsafe { &(*config.cancellation).cancelled });
+    }
+
+    // Gives the program its own stdio, if asked to
+    let mut sink = |fd: u32, bytes: &[u8]| {
+        if let Some(output) = config.output {
+            unsafe { output(config.output_ctx, fd, bytes.as_ptr(), bytes.len() as u64) }
+        }
+    };
+    let stdio = match config.stdio {
+        StdioModes::INHERIT => None,
+        StdioModes::CAPTURE => Some(hvm::Stdio {
+            input: &config.stdin,
+            output: [Vec::new(), Vec::new()],
+            sink: if config.output.is_some() { Some(&mut sink) } else { None },
+        }),
     };
-    tm.rbag.push_redex(hvm::Pair::new(hvm::Port::new(hvm::REF, main_id as u32), hvm::ROOT));
-    net.vars_create(hvm::ROOT.get_val() as usize, hvm::NONE);
 
-    // Starts the timerCUDA compiler not foundCUDA compiler not found
-    let start = std::time::Instant::now();
+    // Reports progress to the host, if asked to
+    let progress = Shared((config.progress, config.progress_ctx));
+    let report = move |report: &hvm::Progress| match *progress.get() {
+        (Some(progress), ctx) => unsafe { progress(ctx, report) != 0 },
+        (None, _) => true,
+    };
+    let reporter = config.progress.map(|_| hvm::Reporter::new(&report, config.progress_interactions, config.progress_interval_ms));
 
-    // Evaluates
-    tm.evaluator(&net, &book);
+    // Normalizes and runs IO
+    let mut io = hvm::Io::new(net, tms, book, host, config.policy.as_ref().map_or(&hvm::NO_IO, |policy| &policy.policy), stdio, reporter.as_ref());
+    if let Err(e) = io.run(hvm::ROOT) {
+        return Err(Error::new(ErrorKind::RUNTIME, e.show()));
+    }
+    let [stdout, stderr] = match io.stdio.take() {
+        Some(stdio) if stdio.sink.is_none() => stdio.output.map(Some),
+        _ => [None, None],
+    };
+    drop(io);
 
     // Stops the timer
     let duration = start.elapsed();
 
+    if net.halt.load(Ordering::Relaxed) == hvm::HALT_OOM {
+        return Err(Error::new(ErrorKind::OOM, format!("The net ran out of space ({} nodes and {} vars)", net.nlen, net.vlen)));
+    }
+
+    // Only a completed net can be read back
+    let status = match net.halt.load(Ordering::Relaxed) {
+        hvm::HALT_FUEL => EvaluationStatus::FUEL_EXHAUSTED,
+        hvm::HALT_TIME => EvaluationStatus::TIMED_OUT,
+        hvm::HALT_STOP => EvaluationStatus::CANCELLED,
+        _ => EvaluationStatus::COMPLETED,
+    };
     let result: String;
-    if let Some(tree) = ast::Net::readback(&net, book) {
-        result = tree.show();
+    let mut tree = None;
+    if status != EvaluationStatus::COMPLETED {
+        result = String::default();
+    } else if let Some(net) = ast::Net::readback(net, book) {
+        result = net.show();
+        tree = Some(ResultTree::new(&net.root));
     } else {
         result = String::default();
     }
 
     // Prints interactions and time
-    let iterations = net.itrs.load(std::sync::atomic::Ordering::Relaxed);
+    let iterations = net.itrs.load(Ordering::Relaxed);
+    let mut rules = [0; 8];
+    for tm in tms.iter() {
+        rules.iter_mut().zip(tm.rules).for_each(|(total, count)| *total += count);
+    }
+    let peak_rbag = net.peak.rbag.load(Ordering::Relaxed);
+    let peak_node = net.peak.node.load(Ordering::Relaxed);
+    let peak_vars = net.peak.vars.load(Ordering::Relaxed);
     let duration_secs = duration.as_secs_f64();
     // let mips = iterations as f64 / duration.as_secs_f64() / 1_000_000.0;
-    let mem_dump = if enable_mem_dump > 0 { net.show() } else { String::default() };
+    let mem_dump = match config.mem_dump {
+        MemDumpModes::NONE => String::default(),
+        MemDumpModes::NODES => net.show_node(),
+        MemDumpModes::FULL => net.show(),
+    };
     return Ok(EvaluationResult {
         iterations,
         time: duration_secs,
+        status,
         result,
         mem_dump,
+        tree,
+        stdout,
+        stderr,
+        rules,
+        peak_rbag,
+        peak_node,
+        peak_vars,
     });
 }
 
-unsafe fn c_evaluate(book_ptr: *const hvm::Book, enable_mem_dump: u32, err_out: *mut *mut c_char) -> *mut EvaluationResultRaw {
+// Evaluates on the C runtime, on `session` if it isn't null.
+unsafe fn c_evaluate(book: &hvm::Book, host: &HostFFnsRaw, session: *mut c_void, config: &EvaluationConfig, err_out: *mut *mut ErrorRaw) -> *mut EvaluationResultRaw {
     #[cfg(feature = "c")]{
+        if let Err(e) = config.entry_fid(book) {
+            *err_out = e.into_raw();
+            return 0usize as *mut EvaluationResultRaw;
+        }
         let mut data : Vec<u8> = Vec::new();
-        let book = &*book_ptr;
         book.to_buffer(&mut data);
-        return hvm_c(data.as_mut_ptr() as *const u32, enable_mem_dump);
+        let options = config.to_raw();
+        let mut err_kind = ErrorKind::OOM;
+        let mut err_buf = [0 as c_char; 256];
+        let result = hvm_c(data.as_ptr(), data.len() as u64, &options, host, session, config.raw_args.as_ptr(), config.raw_args.len() as u32, &mut err_kind, err_buf.as_mut_ptr(), err_buf.len() as u32);
+        if result.is_null() {
+            let message = CStr::from_ptr(err_buf.as_ptr()).to_string_lossy();
+            *err_out = Error::new(err_kind, message).into_raw();
+            return result;
+        }
+
+        // The C runtime only shows its result, so the tree is parsed back here
+        if (*result).status == EvaluationStatus::COMPLETED {
+            if let Ok(tree) = ast::Tree::parse(&CStr::from_ptr((*result).result).to_string_lossy()) {
+                (*result).tree = Box::into_raw(Box::new(ResultTree::new(&tree)));
+            }
+        }
+        return result;
+    }
+    #[cfg(not(feature = "c"))]{
+        _ = (book, host, session, config);
+        *err_out = Error::new(ErrorKind::RUNTIME_UNAVAILABLE, "C runtime not supported").into_raw();
+        return 0usize as *mut EvaluationResultRaw;
+    }
+}
+
+impl SessionNet {
+    // Allocates a net on `runtime_type`, with the buffer sizes and thread
+    // count from `config`.
+    unsafe fn new(runtime_type: RuntimeTypes, config: &EvaluationConfig) -> Result<Self, Error> {
+        match runtime_type {
+            RuntimeTypes::RUST => rust_net(config).map(|(net, tms)| SessionNet::Rust(net, tms)),
+            #[cfg(feature = "c")]
+            RuntimeTypes::C => {
+                let net = hvm_c_session_new(&config.to_raw());
+                if net.is_null() {
+                    return Err(Error::new(ErrorKind::OOM, format!("Failed to allocate a net of {} nodes and {} vars", config.node_len, config.vars_len)));
+                }
+                Ok(SessionNet::C(net))
+            }
+            #[cfg(not(feature = "c"))]
+            RuntimeTypes::C => Err(Error::new(ErrorKind::RUNTIME_UNAVAILABLE, "C runtime not supported")),
+        }
+    }
+
+    // Evaluates a book on this net, which is reset first.
+    unsafe fn evaluate(&mut self, handle: &BookHandle, config: &EvaluationConfig, err_out: *mut *mut ErrorRaw) -> *mut EvaluationResultRaw {
+        match self {
+            SessionNet::Rust(net, tms) => {
+                let result = config.entry_fid(&handle.book).and_then(|entry_fid| {
+                    net.reset(tms);
+                    rust_evaluate_on(net, tms, &handle.book, handle, config, entry_fid)
+                });
+                match result {
+                    Ok(result) => result.into_raw(),
+                    Err(e) => {
+                        *err_out = e.into_raw();
+                        0usize as *mut EvaluationResultRaw
+                    }
+                }
+            }
+            SessionNet::C(net) => c_evaluate(&handle.book, &HostFFnsRaw::new(handle), *net, config, err_out),
+        }
+    }
+}
+
+// Evaluates a book on a net of its own.
+unsafe fn evaluate(handle: &BookHandle, runtime_type: RuntimeTypes, config: &EvaluationConfig, err_out: *mut *mut ErrorRaw) -> *mut EvaluationResultRaw {
+    match runtime_type {
+        RuntimeTypes::RUST => {
+            match rust_evaluate(&handle.book, handle, config) {
+                Ok(result) => result.into_raw(),
+                Err(e) => {
+                    *err_out = e.into_raw();
+                    0usize as *mut EvaluationResultRaw
+                }
+            }
+        }
+        RuntimeTypes::C => c_evaluate(&handle.book, &HostFFnsRaw::new(handle), std::ptr::null_mut(), config, err_out),
+    }
+}
+
+// Converts a string for the host, dropping any interior NULs
+fn to_cstring(string: String) -> CString {
+    match CString::new(string) {
+        Ok(value) => value,
+        Err(err) => {
+            let mut bytes = err.into_vec();
+            bytes.retain(|&byte| byte != 0);
+            CString::new(bytes).unwrap_or_default()
+        }
+    }
+}
+
+// Runs the body of an export, turning a panic into a `PANIC` error in
+// `err_out` (when given) and returning `fallback`. Unwinding into the host
+// would abort it.
+unsafe fn guard<T>(err_out: *mut *mut ErrorRaw, fallback: T, body: impl FnOnce() -> T) -> T {
+    match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(value) => value,
+        Err(payload) => {
+            if !err_out.is_null() {
+                *err_out = panic_error(payload).into_raw();
+            }
+            fallback
+        }
+    }
+}
+
+fn panic_error(payload: Box<dyn std::any::Any + Send>) -> Error {
+    let message = if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown panic".to_string()
+    };
+    Error::new(ErrorKind::PANIC, message)
+}
+
+// Runs a builder call for the host. On failure (or panic), returns `fallback`
+// and keeps the error if it's the first one.
+unsafe fn with_builder<T>(builder_ptr: *mut BuilderHandle, fallback: T, body: impl FnOnce(&mut BookBuilder) -> Result<T, Error>) -> T {
+    let handle = &mut *builder_ptr;
+    let result = match catch_unwind(AssertUnwindSafe(|| body(&mut handle.builder))) {
+        Ok(result) => result,
+        Err(payload) => Err(panic_error(payload)),
+    };
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            if handle.error.is_none() {
+                handle.error = Some(e);
+            }
+            fallback
+        }
+    }
+}
+
+// Reads a string passed by the host.
+unsafe fn read_text<'a>(text: *const c_char) -> Result<&'a str, Error> {
+    if text.is_null() {
+        return Err(Error::new(ErrorKind::INVALID_ARGUMENT, "String is null"));
     }
-    *err_out = CString::new("C runtime not supported").unwrap().into_raw();
-    return 0usize as *mut EvaluationResultRaw;
+    CStr::from_ptr(text).to_str().map_err(|e| Error::new(ErrorKind::INVALID_ARGUMENT, e.to_string()))
 }
 
 extern "C" fn drop_evaluation_result_raw(this_ptr: *mut EvaluationResultRaw){
@@ -92,114 +904,1871 @@ extern "C" fn drop_evaluation_result_raw(this_ptr: *mut EvaluationResultRaw){
         let this = Box::from_raw(this_ptr);
         free_cstring(this.result);
         free_cstring(this.mem_dump);
+        for (buf, len) in [(this.stdout, this.stdout_len), (this.stderr, this.stderr_len)] {
+            if !buf.is_null() {
+                _ = Box::from_raw(std::ptr::slice_from_raw_parts_mut(buf, len as usize));
+            }
+        }
     }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn free_cstring(string_ptr: *mut c_char) {
-    _ = CString::from_raw(string_ptr);
+    guard(std::ptr::null_mut(), (), || {
+        _ = CString::from_raw(string_ptr);
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn free_book(book_ptr: *mut hvm::Book){
-    _ = Box::from_raw(book_ptr);
+pub unsafe extern "C" fn free_error(error_ptr: *mut ErrorRaw) {
+    guard(std::ptr::null_mut(), (), || {
+        ErrorRaw::free(error_ptr);
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn book_parse(code: *const c_char, err_out: *mut *mut c_char) -> *mut hvm::Book {
-    *err_out = 0usize as *mut c_char;
-    let c_str = CStr::from_ptr(code);
-    let converted_str = match c_str.to_str(){
-        Ok(value) => value,
-        Err(err) => {
-            *err_out = CString::new(err.to_string()).unwrap().into_raw();
-            return 0usize as *mut hvm::Book;
-        }
-    };
-    return match Book::parse(converted_str){
-        Ok(value) => Box::into_raw(Box::new(match value.build() {
-            Ok(v) => v,
-            Err(err) => {
-                *err_out = CString::new(err.to_string()).unwrap().into_raw();
-                return 0usize as *mut hvm::Book;
-            }
-        })),
-        Err(err) => {
-            *err_out = CString::new(err.to_string()).unwrap().into_raw();
-            0usize as *mut hvm::Book
-        }
-    }
+pub unsafe extern "C" fn free_book(book_ptr: *mut BookHandle){
+    guard(std::ptr::null_mut(), (), || {
+        _ = Box::from_raw(book_ptr);
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn book_evaluate(book_ptr: *const hvm::Book, runtime_type: RuntimeTypes, enable_mem_dump: u32, err_out: *mut *mut c_char) -> *mut EvaluationResultRaw {
-    let book: &hvm::Book = &*book_ptr;
-    *err_out = 0usize as *mut c_char;
-    match runtime_type {
-        RuntimeTypes::RUST => {
-            match rust_evaluate(&book, enable_mem_dump) {
-                Ok(EvaluationResult{
-                       iterations, time, result, mem_dump
-                   }) => Box::into_raw(Box::from(EvaluationResultRaw{
-                    iterations,
-                    time,
-                    result: CString::new(result).unwrap().into_raw(),
-                    mem_dump: CString::new(mem_dump).unwrap().into_raw(),
-                    deallocator: drop_evaluation_result_raw
-                })),
-                Err(e) => {
-                    *err_out = e.into_raw();
-                    0usize as *mut EvaluationResultRaw
+pub unsafe extern "C" fn book_parse(code: *const c_char, err_out: *mut *mut ErrorRaw) -> *mut BookHandle {
+    guard(err_out, std::ptr::null_mut(), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let c_str = CStr::from_ptr(code);
+        let converted_str = match c_str.to_str(){
+            Ok(value) => value,
+            Err(err) => {
+                *err_out = Error::new(ErrorKind::INVALID_ARGUMENT, err.to_string()).into_raw();
+                return 0usize as *mut BookHandle;
+            }
+        };
+        return match Book::parse(converted_str){
+            Ok(value) => match value.build() {
+                Ok(book) => Box::into_raw(Box::new(BookHandle { book, source: Some(value), ffns: Vec::new() })),
+                Err(err) => {
+                    *err_out = err.into_raw();
+                    0usize as *mut BookHandle
                 }
+            },
+            Err(err) => {
+                *err_out = err.into_raw();
+                0usize as *mut BookHandle
             }
         }
-        RuntimeTypes::C => c_evaluate(book_ptr, enable_mem_dump, err_out),
-        _ => {
-            let e_str = format!("Invalid runtime type: {}", runtime_type as u32);
-            *err_out = CString::new(e_str).unwrap().into_raw();
-            0usize as *mut EvaluationResultRaw
-        }
-    }
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn free_evaluation_result(result_ptr: *mut EvaluationResultRaw){
-    let this = &*result_ptr;
-    let deallocator = this.deallocator;
-    deallocator(result_ptr);
+pub unsafe extern "C" fn book_evaluate(book_ptr: *const BookHandle, runtime_type: u32, options: *const EvaluationOptions, err_out: *mut *mut ErrorRaw) -> *mut EvaluationResultRaw {
+    guard(err_out, std::ptr::null_mut(), || {
+        let handle = &*book_ptr;
+        *err_out = 0usize as *mut ErrorRaw;
+        let runtime_type = match RuntimeTypes::from_raw(runtime_type) {
+            Ok(runtime_type) => runtime_type,
+            Err(e) => {
+                *err_out = e.into_raw();
+                return 0usize as *mut EvaluationResultRaw;
+            }
+        };
+        let config = match EvaluationConfig::from_raw(options) {
+            Ok(config) => config,
+            Err(e) => {
+                *err_out = e.into_raw();
+                return 0usize as *mut EvaluationResultRaw;
+            }
+        };
+        evaluate(handle, runtime_type, &config, err_out)
+    })
 }
 
+// Starts evaluating a book on a thread of its own, returning a handle to
+// wait on and take its result from. `callback` (when given) is called with
+// `callback_ctx` once it finishes. Options are copied, so the host may free
+// them right away, except for `output_ctx` and `progress_ctx`, which are
+// passed back as is until it finishes. Cancel with `evaluation_cancel`
+// instead of `options.cancellation`, which is ignored. The book must outlive
+// the evaluation.
 #[no_mangle]
-pub unsafe extern "C" fn book_serialize(book_ptr: *const hvm::Book, err_out: *mut *mut c_char) -> *mut Vec<u8>{
-    let mut data : Vec<u8> = Vec::new();
-    let book = &*book_ptr;
-    match book.to_buffer_safe(&mut data){
-        Ok(_) => {}
-        Err(e) => {
-            *err_out = CString::new(e).unwrap().into_raw();
-            return 0usize as *mut Vec<u8>;
+pub unsafe extern "C" fn book_evaluate_async(book_ptr: *const BookHandle, runtime_type: u32, options: *const EvaluationOptions, callback: Option<CompletionFn>, callback_ctx: *mut c_void, err_out: *mut *mut ErrorRaw) -> *mut EvaluationHandle {
+    guard(err_out, std::ptr::null_mut(), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let runtime_type = match RuntimeTypes::from_raw(runtime_type) {
+            Ok(runtime_type) => runtime_type,
+            Err(e) => {
+                *err_out = e.into_raw();
+                return 0usize as *mut EvaluationHandle;
+            }
+        };
+        let mut config = match EvaluationConfig::from_raw(options) {
+            Ok(config) => config,
+            Err(e) => {
+                *err_out = e.into_raw();
+                return 0usize as *mut EvaluationHandle;
+            }
+        };
+        let state = Arc::new(AsyncState {
+            outcome: Mutex::new(Shared(AsyncOutcome::Running)),
+            done: Condvar::new(),
+            cancellation: CancellationToken { cancelled: AtomicU32::new(0) },
+        });
+        config.cancellation = &state.cancellation;
+        let shared = Shared((book_ptr, config, callback, callback_ctx));
+        let thread_state = state.clone();
+        let thread = std::thread::Builder::new().name("hvm-evaluation".to_string()).stack_size(EVALUATION_STACK_SIZE).spawn(move || {
+            let (book_ptr, config, callback, callback_ctx) = shared.get();
+            let mut err = 0usize as *mut ErrorRaw;
+            let result = guard(&mut err, std::ptr::null_mut(), || evaluate(&**book_ptr, runtime_type, config, &mut err));
+            thread_state.outcome.lock().unwrap_or_else(|e| e.into_inner()).0 = AsyncOutcome::Done(result, err);
+            thread_state.done.notify_all();
+            if let Some(callback) = callback {
+                callback(*callback_ctx);
+            }
+        });
+        match thread {
+            Ok(thread) => Box::into_raw(Box::new(EvaluationHandle { state, thread: Some(thread) })),
+            Err(e) => {
+                *err_out = Error::new(ErrorKind::RUNTIME, format!("Failed to start the evaluation: {}", e)).into_raw();
+                0usize as *mut EvaluationHandle
+            }
         }
-    }
-    return Box::into_raw(Box::new(data));
+    })
 }
 
+// Returns 1 if the evaluation finished, or 0 if it's still running.
 #[no_mangle]
-pub unsafe extern "C" fn vec_get_length(vec_ptr: *mut Vec<u8>) -> u64 {
-    let vec = Box::from_raw(vec_ptr);
-    let length = vec.len() as u64;
-    _ = Box::into_raw(vec);
-    length
+pub unsafe extern "C" fn evaluation_poll(evaluation_ptr: *const EvaluationHandle) -> u32 {
+    guard(std::ptr::null_mut(), 0, || {
+        let state = &(*evaluation_ptr).state;
+        !matches!(state.outcome.lock().unwrap_or_else(|e| e.into_inner()).0, AsyncOutcome::Running) as u32
+    })
 }
 
+// Waits up to `timeout_ms` (0 = unlimited) for the evaluation to finish.
+// Returns 1 if it did, or 0 if it's still running.
 #[no_mangle]
-pub unsafe extern "C" fn vec_copy(vec_ptr: *mut Vec<u8>, loc: *mut u8, size: u64) {
-    let vec = Box::from_raw(vec_ptr);
-    let slice = std::slice::from_raw_parts_mut(loc, size as usize);
-    slice[..vec.len()].copy_from_slice(vec.as_slice());
-    _ = Box::into_raw(vec);
+pub unsafe extern "C" fn evaluation_wait(evaluation_ptr: *const EvaluationHandle, timeout_ms: u64) -> u32 {
+    guard(std::ptr::null_mut(), 0, || {
+        let state = &(*evaluation_ptr).state;
+        let outcome = state.outcome.lock().unwrap_or_else(|e| e.into_inner());
+        let running = |outcome: &mut Shared<AsyncOutcome>| matches!(outcome.0, AsyncOutcome::Running);
+        let outcome = match timeout_ms {
+            0 => state.done.wait_while(outcome, running).unwrap_or_else(|e| e.into_inner()),
+            timeout_ms => state.done.wait_timeout_while(outcome, Duration::from_millis(timeout_ms), running).unwrap_or_else(|e| e.into_inner()).0,
+        };
+        !matches!(outcome.0, AsyncOutcome::Running) as u32
+    })
 }
 
+// Halts the evaluation, which then finishes with a `CANCELLED` status.
 #[no_mangle]
-pub unsafe extern "C" fn free_vec(vec_ptr: *mut Vec<u8>){
-    _ = Box::from_raw(vec_ptr);
+pub unsafe extern "C" fn evaluation_cancel(evaluation_ptr: *const EvaluationHandle) {
+    guard(std::ptr::null_mut(), (), || {
+        let state = &(*evaluation_ptr).state;
+        state.cancellation.cancelled.store(1, Ordering::Relaxed);
+    })
+}
+
+// Takes the result of a finished evaluation, or its error in `err_out`.
+// Returns null with no error while it's still running. The result can only
+// be taken once.
+#[no_mangle]
+pub unsafe extern "C" fn evaluation_take_result(evaluation_ptr: *const EvaluationHandle, err_out: *mut *mut ErrorRaw) -> *mut EvaluationResultRaw {
+    guard(err_out, std::ptr::null_mut(), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let state = &(*evaluation_ptr).state;
+        let mut outcome = state.outcome.lock().unwrap_or_else(|e| e.into_inner());
+        match std::mem::replace(&mut outcome.0, AsyncOutcome::Taken) {
+            AsyncOutcome::Running => {
+                outcome.0 = AsyncOutcome::Running;
+                0usize as *mut EvaluationResultRaw
+            }
+            AsyncOutcome::Done(result, err) => {
+                *err_out = err;
+                result
+            }
+            AsyncOutcome::Taken => {
+                *err_out = Error::new(ErrorKind::INVALID_ARGUMENT, "The result was already taken").into_raw();
+                0usize as *mut EvaluationResultRaw
+            }
+        }
+    })
+}
+
+// Cancels the evaluation if it's still running, waits for it, and frees it
+// along with a result that wasn't taken. May be called from the completion
+// callback.
+#[no_mangle]
+pub unsafe extern "C" fn free_evaluation(evaluation_ptr: *mut EvaluationHandle) {
+    guard(std::ptr::null_mut(), (), || {
+        _ = Box::from_raw(evaluation_ptr);
+    })
+}
+
+// Allocates a net on `runtime_type` to evaluate books on, with the buffer
+// sizes and thread count from `options`. The rest of them are ignored.
+#[no_mangle]
+pub unsafe extern "C" fn session_new(runtime_type: u32, options: *const EvaluationOptions, err_out: *mut *mut ErrorRaw) -> *mut SessionHandle {
+    guard(err_out, std::ptr::null_mut(), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let runtime_type = match RuntimeTypes::from_raw(runtime_type) {
+            Ok(runtime_type) => runtime_type,
+            Err(e) => {
+                *err_out = e.into_raw();
+                return 0usize as *mut SessionHandle;
+            }
+        };
+        let config = match EvaluationConfig::from_raw(options) {
+            Ok(config) => config,
+            Err(e) => {
+                *err_out = e.into_raw();
+                return 0usize as *mut SessionHandle;
+            }
+        };
+        match SessionNet::new(runtime_type, &config) {
+            Ok(net) => Box::into_raw(Box::new(SessionHandle { net })),
+            Err(e) => {
+                *err_out = e.into_raw();
+                0usize as *mut SessionHandle
+            }
+        }
+    })
+}
+
+// Evaluates a book on a session's net, which is reset first. The session's
+// buffer sizes and thread count are used instead of those in `options`.
+#[no_mangle]
+pub unsafe extern "C" fn session_evaluate(session_ptr: *mut SessionHandle, book_ptr: *const BookHandle, options: *const EvaluationOptions, err_out: *mut *mut ErrorRaw) -> *mut EvaluationResultRaw {
+    guard(err_out, std::ptr::null_mut(), || {
+        let session = &mut *session_ptr;
+        let handle = &*book_ptr;
+        *err_out = 0usize as *mut ErrorRaw;
+        let config = match EvaluationConfig::from_raw(options) {
+            Ok(config) => config,
+            Err(e) => {
+                *err_out = e.into_raw();
+                return 0usize as *mut EvaluationResultRaw;
+            }
+        };
+        session.net.evaluate(handle, &config, err_out)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn free_session(session_ptr: *mut SessionHandle) {
+    guard(std::ptr::null_mut(), (), || {
+        _ = Box::from_raw(session_ptr);
+    })
+}
+
+// Evaluates a book once per item, each with its own arguments, on `workers`
+// threads (0 = one per CPU) that keep a net of their own between items.
+// Every item shares `options` except for its args, and its thread count is
+// per item, with 0 meaning 1 since the items already run in parallel. Host
+// functions and `output` may be called from several workers at once.
+#[no_mangle]
+pub unsafe extern "C" fn book_evaluate_batch(book_ptr: *const BookHandle, runtime_type: u32, options: *const EvaluationOptions, items: *const BatchItem, items_len: u64, workers: u32, err_out: *mut *mut ErrorRaw) -> *mut BatchResultRaw {
+    guard(err_out, std::ptr::null_mut(), || {
+        let handle = &*book_ptr;
+        *err_out = 0usize as *mut ErrorRaw;
+        let runtime_type = match RuntimeTypes::from_raw(runtime_type) {
+            Ok(runtime_type) => runtime_type,
+            Err(e) => {
+                *err_out = e.into_raw();
+                return 0usize as *mut BatchResultRaw;
+            }
+        };
+        if items_len != 0 && items.is_null() {
+            *err_out = Error::new(ErrorKind::INVALID_ARGUMENT, "Batch items are null").into_raw();
+            return 0usize as *mut BatchResultRaw;
+        }
+        if workers > MAX_THREADS {
+            let e_str = format!("Invalid worker count: {} (expected at most {})", workers, MAX_THREADS);
+            *err_out = Error::new(ErrorKind::INVALID_ARGUMENT, e_str).into_raw();
+            return 0usize as *mut BatchResultRaw;
+        }
+
+        // A zeroed struct holds the defaults, like null options do
+        let mut base: EvaluationOptions = if options.is_null() { std::mem::zeroed() } else { *options };
+        base.threads = base.threads.max(1);
+        base.args = std::ptr::null();
+        base.args_len = 0;
+        if let Err(e) = EvaluationConfig::from_raw(&base) {
+            *err_out = e.into_raw();
+            return 0usize as *mut BatchResultRaw;
+        }
+
+        // Workers take the next item until there are none left
+        let len = items_len as usize;
+        let workers = match workers {
+            0 => num_cpus::get(),
+            workers => workers as usize,
+        };
+        let next = AtomicUsize::new(0);
+        let shared = Shared((handle, &base, items));
+        let mut results = vec![std::ptr::null_mut(); len].into_boxed_slice();
+        let mut errors = vec![std::ptr::null_mut(); len].into_boxed_slice();
+        std::thread::scope(|scope| {
+            // Workers that fail to start are skipped, as the others take their items
+            let threads: Vec<_> = (0..workers.min(len)).filter_map(|_| {
+                let builder = std::thread::Builder::new().name("hvm-batch".to_string()).stack_size(EVALUATION_STACK_SIZE);
+                builder.spawn_scoped(scope, || {
+                    let (handle, base, items) = *shared.get();
+                    let mut net = None;
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= len {
+                            break;
+                        }
+                        let item = &*items.add(i);
+                        let mut err = 0usize as *mut ErrorRaw;
+                        // A net that panicked is dropped, and the next item gets a new one
+                        let result: Result<_, Error> = guard(&mut err, Ok(std::ptr::null_mut()), || {
+                            let config = EvaluationConfig::from_raw(&EvaluationOptions { args: item.args, args_len: item.args_len, ..*base })?;
+                            let mut session = match net.take() {
+                                Some(session) => session,
+                                None => SessionNet::new(runtime_type, &config)?,
+                            };
+                            let result = session.evaluate(handle, &config, &mut err);
+                            net = Some(session);
+                            Ok(result)
+                        });
+                        let result = match result {
+                            Ok(result) => result,
+                            Err(e) => {
+                                err = e.into_raw();
+                                std::ptr::null_mut()
+                            }
+                        };
+                        done.push((i, result, err));
+                    }
+                    Shared(done)
+                }).ok()
+            }).collect();
+            for thread in threads {
+                // Panics are caught per item, so workers always finish
+                if let Ok(done) = thread.join() {
+                    for &(i, result, err) in done.get() {
+                        results[i] = result;
+                        errors[i] = err;
+                    }
+                }
+            }
+        });
+        // Only left unset if no worker could start
+        for (result, error) in results.iter().zip(errors.iter_mut()) {
+            if result.is_null() && error.is_null() {
+                *error = Error::new(ErrorKind::RUNTIME, "Failed to start the batch's workers").into_raw();
+            }
+        }
+        Box::into_raw(Box::new(BatchResultRaw {
+            results: Box::into_raw(results) as *mut *mut EvaluationResultRaw,
+            errors: Box::into_raw(errors) as *mut *mut ErrorRaw,
+            len: items_len,
+        }))
+    })
+}
+
+// Frees a batch along with all of its results and errors.
+#[no_mangle]
+pub unsafe extern "C" fn free_batch_result(batch_ptr: *mut BatchResultRaw) {
+    guard(std::ptr::null_mut(), (), || {
+        let batch = Box::from_raw(batch_ptr);
+        let results = Box::from_raw(std::ptr::slice_from_raw_parts_mut(batch.results, batch.len as usize));
+        let errors = Box::from_raw(std::ptr::slice_from_raw_parts_mut(batch.errors, batch.len as usize));
+        for &result in results.iter().filter(|result| !result.is_null()) {
+            free_evaluation_result(result);
+        }
+        for &error in errors.iter().filter(|error| !error.is_null()) {
+            ErrorRaw::free(error);
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn free_evaluation_result(result_ptr: *mut EvaluationResultRaw){
+    guard(std::ptr::null_mut(), (), || {
+        let this = &*result_ptr;
+        if !this.tree.is_null() {
+            _ = Box::from_raw(this.tree);
+        }
+        let deallocator = this.deallocator;
+        deallocator(result_ptr);
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn cancellation_token_new() -> *mut CancellationToken {
+    unsafe {
+        guard(std::ptr::null_mut(), std::ptr::null_mut(), || {
+            Box::into_raw(Box::new(CancellationToken { cancelled: AtomicU32::new(0) }))
+        })
+    }
+}
+
+/// # Safety
+/// `token_ptr` must come from `cancellation_token_new` and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn cancellation_token_cancel(token_ptr: *const CancellationToken) {
+    guard(std::ptr::null_mut(), (), || {
+        let token = &*token_ptr;
+        token.cancelled.store(1, Ordering::Relaxed);
+    })
+}
+
+/// # Safety
+/// `token_ptr` must come from `cancellation_token_new`, and no evaluation may
+/// still be using it.
+#[no_mangle]
+pub unsafe extern "C" fn free_cancellation_token(token_ptr: *mut CancellationToken) {
+    guard(std::ptr::null_mut(), (), || {
+        _ = Box::from_raw(token_ptr);
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_serialize(book_ptr: *const BookHandle, err_out: *mut *mut ErrorRaw) -> *mut Vec<u8>{
+    guard(err_out, std::ptr::null_mut(), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let mut data : Vec<u8> = Vec::new();
+        let book = &(*book_ptr).book;
+        book.to_buffer(&mut data);
+        Box::into_raw(Box::new(data))
+    })
+}
+
+// Reads a book written by `book_serialize`.
+#[no_mangle]
+pub unsafe extern "C" fn book_deserialize(data: *const u8, len: u64, err_out: *mut *mut ErrorRaw) -> *mut BookHandle {
+    guard(err_out, std::ptr::null_mut(), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let data = if len == 0 { &[] } else { std::slice::from_raw_parts(data, len as usize) };
+        match hvm::Book::from_buffer(data) {
+            Ok(book) => Box::into_raw(Box::new(BookHandle { book, source: None, ffns: Vec::new() })),
+            Err(e) => {
+                *err_out = Error::new(ErrorKind::FORMAT, e).into_raw();
+                0usize as *mut BookHandle
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_def_count(book_ptr: *const BookHandle) -> u32 {
+    guard(std::ptr::null_mut(), 0, || {
+        let handle = &*book_ptr;
+        handle.book.defs.len() as u32
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_def_name(book_ptr: *const BookHandle, fid: u32, err_out: *mut *mut ErrorRaw) -> *mut c_char {
+    guard(err_out, std::ptr::null_mut(), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let handle = &*book_ptr;
+        match handle.def(fid) {
+            Ok(def) => to_cstring(def.name.clone()).into_raw(),
+            Err(e) => {
+                *err_out = e.into_raw();
+                0usize as *mut c_char
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_def_info(book_ptr: *const BookHandle, fid: u32, info_out: *mut DefInfo, err_out: *mut *mut ErrorRaw) {
+    guard(err_out, (), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let handle = &*book_ptr;
+        match handle.def(fid) {
+            Ok(def) => {
+                *info_out = DefInfo {
+                    fid,
+                    safe: def.safe as u32,
+                    node_len: def.node.len() as u64,
+                    rbag_len: def.rbag.len() as u64,
+                    vars_len: def.vars as u64,
+                };
+            }
+            Err(e) => {
+                *err_out = e.into_raw();
+            }
+        }
+    })
+}
+
+// Returns the source text of a definition, as shown by `ast::Net::show`.
+// Definitions of deserialized books are decompiled.
+#[no_mangle]
+pub unsafe extern "C" fn book_def_source(book_ptr: *const BookHandle, fid: u32, err_out: *mut *mut ErrorRaw) -> *mut c_char {
+    guard(err_out, std::ptr::null_mut(), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let handle = &*book_ptr;
+        match handle.def_source(fid) {
+            Ok(source) => to_cstring(source).into_raw(),
+            Err(e) => {
+                *err_out = e.into_raw();
+                0usize as *mut c_char
+            }
+        }
+    })
+}
+
+// Shows the whole book as HVM core syntax, decompiled from its runtime defs.
+#[no_mangle]
+pub unsafe extern "C" fn book_decompile(book_ptr: *const BookHandle, err_out: *mut *mut ErrorRaw) -> *mut c_char {
+    guard(err_out, std::ptr::null_mut(), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let handle = &*book_ptr;
+        match Book::decompile(&handle.book) {
+            Some(book) => to_cstring(book.show()).into_raw(),
+            None => {
+                *err_out = Error::new(ErrorKind::FORMAT, "Book can't be decompiled").into_raw();
+                0usize as *mut c_char
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn book_builder_new() -> *mut BuilderHandle {
+    unsafe {
+        guard(std::ptr::null_mut(), std::ptr::null_mut(), || {
+            Box::into_raw(Box::new(BuilderHandle { builder: BookBuilder::new(), error: None }))
+        })
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn free_book_builder(builder_ptr: *mut BuilderHandle) {
+    guard(std::ptr::null_mut(), (), || {
+        _ = Box::from_raw(builder_ptr);
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_var(builder_ptr: *mut BuilderHandle, name: *const c_char) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| builder.var(read_text(name)?))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_ref(builder_ptr: *mut BuilderHandle, name: *const c_char) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| builder.reference(read_text(name)?))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_era(builder_ptr: *mut BuilderHandle) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| Ok(builder.era()))
+}
+
+// Adds a number from its raw 29-bit encoding.
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_num(builder_ptr: *mut BuilderHandle, bits: u32) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| {
+        if bits >= 1 << 29 {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Number out of range: {:#x}", bits)));
+        }
+        Ok(builder.num(hvm::Numb(bits)))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_u24(builder_ptr: *mut BuilderHandle, val: u32) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| Ok(builder.num(hvm::Numb::new_u24(val & 0xFFFFFF))))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_i24(builder_ptr: *mut BuilderHandle, val: i32) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| Ok(builder.num(hvm::Numb::new_i24(val))))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_f24(builder_ptr: *mut BuilderHandle, val: f32) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| Ok(builder.num(hvm::Numb::new_f24(val))))
+}
+
+// Adds an operator (like `[+]`), or a cast when `op` is a numeric type.
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_sym(builder_ptr: *mut BuilderHandle, op: u32) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| {
+        if op > hvm::FP_SHR as u32 {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid operator: {}", op)));
+        }
+        Ok(builder.num(hvm::Numb::new_sym(op as hvm::Tag)))
+    })
+}
+
+// Adds a partially applied operator (like `[+1]`), consuming a number tree.
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_partial(builder_ptr: *mut BuilderHandle, op: u32, operand: u32) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| {
+        if op < hvm::OP_ADD as u32 || op > hvm::FP_SHR as u32 {
+            return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Invalid operator: {}", op)));
+        }
+        builder.partial(op as hvm::Tag, operand)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_con(builder_ptr: *mut BuilderHandle, fst: u32, snd: u32) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| builder.con(fst, snd))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_dup(builder_ptr: *mut BuilderHandle, fst: u32, snd: u32) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| builder.dup(fst, snd))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_opr(builder_ptr: *mut BuilderHandle, fst: u32, snd: u32) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| builder.opr(fst, snd))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_swi(builder_ptr: *mut BuilderHandle, fst: u32, snd: u32) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| builder.swi(fst, snd))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_net(builder_ptr: *mut BuilderHandle, root: u32) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| builder.net(root))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_redex(builder_ptr: *mut BuilderHandle, net: u32, par: u32, fst: u32, snd: u32) {
+    with_builder(builder_ptr, (), |builder| builder.redex(net, par != 0, fst, snd))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_define(builder_ptr: *mut BuilderHandle, name: *const c_char, net: u32) {
+    with_builder(builder_ptr, (), |builder| builder.define(read_text(name)?, net))
+}
+
+// Adds an encoded value, which is borrowed.
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_value(builder_ptr: *mut BuilderHandle, value_ptr: *const Value) -> u32 {
+    with_builder(builder_ptr, INVALID_ID, |builder| Ok(builder.value(&*value_ptr)))
+}
+
+// Builds the definitions added so far, or reports the first failed call.
+// The builder stays usable either way.
+#[no_mangle]
+pub unsafe extern "C" fn book_builder_build(builder_ptr: *mut BuilderHandle, err_out: *mut *mut ErrorRaw) -> *mut BookHandle {
+    guard(err_out, std::ptr::null_mut(), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let handle = &mut *builder_ptr;
+        let result = match handle.error.take() {
+            Some(e) => Err(e),
+            None => {
+                let source = handle.builder.book();
+                source.build().map(|book| BookHandle { book, source: Some(source), ffns: Vec::new() })
+            }
+        };
+        match result {
+            Ok(book) => Box::into_raw(Box::new(book)),
+            Err(e) => {
+                *err_out = e.into_raw();
+                0usize as *mut BookHandle
+            }
+        }
+    })
+}
+
+// Decodes a result (or any term) of this book into the given shape, like
+// `[u24]` or `(str, bool)`. See `codec::Shape`.
+#[no_mangle]
+pub unsafe extern "C" fn book_decode(book_ptr: *const BookHandle, term: *const c_char, shape: *const c_char, err_out: *mut *mut ErrorRaw) -> *mut Value {
+    guard(err_out, std::ptr::null_mut(), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let handle = &*book_ptr;
+        let result = read_text(term).and_then(|term| {
+            let shape = Shape::parse(read_text(shape)?)?;
+            handle.decode(term, &shape)
+        });
+        match result {
+            Ok(value) => Box::into_raw(Box::new(value)),
+            Err(e) => {
+                *err_out = e.into_raw();
+                0usize as *mut Value
+            }
+        }
+    })
+}
+
+// Registers a host function, which programs run with `IO_CALL "NAME"` on
+// either runtime. Its argument is decoded with `shape`. Must not be called
+// while the book is being evaluated.
+#[no_mangle]
+pub unsafe extern "C" fn book_register_ffn(book_ptr: *mut BookHandle, name: *const c_char, shape: *const c_char, func: HostFn, data: *mut c_void, err_out: *mut *mut ErrorRaw) {
+    guard(err_out, (), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let handle = &mut *book_ptr;
+        let result = read_text(name).and_then(|name| {
+            let shape = Shape::parse(read_text(shape)?)?;
+            if name.is_empty() {
+                return Err(Error::new(ErrorKind::INVALID_ARGUMENT, "Function names can't be empty"));
+            }
+            if hvm::FFNS.iter().any(|ffn| ffn.name == name) || handle.ffns.iter().any(|ffn| ffn.name == name) {
+                return Err(Error::new(ErrorKind::INVALID_ARGUMENT, format!("Function `{}` is already defined", name)));
+            }
+            handle.ffns.push(HostFFn { name: name.to_string(), shape, func, data });
+            Ok(())
+        });
+        if let Err(e) = result {
+            *err_out = e.into_raw();
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn free_value(value_ptr: *mut Value) {
+    guard(std::ptr::null_mut(), (), || {
+        _ = Box::from_raw(value_ptr);
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn value_u24(val: u32) -> *mut Value {
+    Box::into_raw(Box::new(Value::U24(val & 0xFFFFFF)))
+}
+
+#[no_mangle]
+pub extern "C" fn value_i24(val: i32) -> *mut Value {
+    Box::into_raw(Box::new(Value::I24(val)))
+}
+
+#[no_mangle]
+pub extern "C" fn value_f24(val: f32) -> *mut Value {
+    Box::into_raw(Box::new(Value::F24(val)))
+}
+
+#[no_mangle]
+pub extern "C" fn value_bool(val: u32) -> *mut Value {
+    Box::into_raw(Box::new(Value::Bool(val != 0)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn value_string(text: *const c_char, err_out: *mut *mut ErrorRaw) -> *mut Value {
+    guard(err_out, std::ptr::null_mut(), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        match read_text(text) {
+            Ok(text) => Box::into_raw(Box::new(Value::Str(text.to_string()))),
+            Err(e) => {
+                *err_out = e.into_raw();
+                0usize as *mut Value
+            }
+        }
+    })
+}
+
+// Starts an empty list, tuple or constructor; items are added with `value_push`.
+#[no_mangle]
+pub extern "C" fn value_list() -> *mut Value {
+    Box::into_raw(Box::new(Value::List(Vec::new())))
+}
+
+#[no_mangle]
+pub extern "C" fn value_tuple() -> *mut Value {
+    Box::into_raw(Box::new(Value::Tuple(Vec::new())))
+}
+
+#[no_mangle]
+pub extern "C" fn value_ctr(tag: u32) -> *mut Value {
+    Box::into_raw(Box::new(Value::Ctr { tag: tag & 0xFFFFFF, fields: Vec::new() }))
+}
+
+// Appends an item to a list, tuple or constructor. Takes ownership of the
+// item, which is freed if it can't be added.
+#[no_mangle]
+pub unsafe extern "C" fn value_push(value_ptr: *mut Value, item_ptr: *mut Value, err_out: *mut *mut ErrorRaw) {
+    guard(err_out, (), || {
+        *err_out = 0usize as *mut ErrorRaw;
+        let item = *Box::from_raw(item_ptr);
+        match (*value_ptr).items_mut() {
+            Some(items) => items.push(item),
+            None => *err_out = Error::new(ErrorKind::INVALID_ARGUMENT, "Only lists, tuples and constructors have items").into_raw(),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn value_get_info(value_ptr: *const Value, info_out: *mut ValueInfo) {
+    guard(std::ptr::null_mut(), (), || {
+        let value = &*value_ptr;
+        let bits = match value {
+            Value::U24(val) => *val,
+            Value::I24(val) => *val as u32,
+            Value::F24(val) => val.to_bits(),
+            Value::Bool(val) => *val as u32,
+            Value::Ctr { tag, .. } => *tag,
+            _ => 0,
+        };
+        let len = match value {
+            Value::Str(text) => text.len() as u64,
+            _ => value.items().map_or(0, |items| items.len() as u64),
+        };
+        *info_out = ValueInfo { kind: value.kind(), bits, len };
+    })
+}
+
+// Borrows an item of a list, tuple or constructor, or returns null if
+// there's no such item.
+#[no_mangle]
+pub unsafe extern "C" fn value_get_item(value_ptr: *const Value, index: u64) -> *const Value {
+    guard(std::ptr::null_mut(), std::ptr::null(), || {
+        match (*value_ptr).items().and_then(|items| items.get(index as usize)) {
+            Some(item) => item as *const Value,
+            None => std::ptr::null(),
+        }
+    })
+}
+
+// Returns the text of a string value, or null if it isn't one.
+#[no_mangle]
+pub unsafe extern "C" fn value_get_string(value_ptr: *const Value) -> *mut c_char {
+    guard(std::ptr::null_mut(), std::ptr::null_mut(), || {
+        match &*value_ptr {
+            Value::Str(text) => to_cstring(text.clone()).into_raw(),
+            _ => std::ptr::null_mut(),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vec_get_length(vec_ptr: *mut Vec<u8>) -> u64 {
+    guard(std::ptr::null_mut(), 0, || {
+        let vec = &*vec_ptr;
+        vec.len() as u64
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn vec_copy(vec_ptr: *mut Vec<u8>, loc: *mut u8, size: u64) {
+    guard(std::ptr::null_mut(), (), || {
+        // Borrowed, so a panic here can't free the vector under the host
+        let vec = &*vec_ptr;
+        let slice = std::slice::from_raw_parts_mut(loc, size as usize);
+        slice[..vec.len()].copy_from_slice(vec.as_slice());
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn free_vec(vec_ptr: *mut Vec<u8>){
+    guard(std::ptr::null_mut(), (), || {
+        _ = Box::from_raw(vec_ptr);
+    })
+}
+
+// Tests
+// -----
+
+#[cfg(test)]
+const TEST_RUNTIMES: &[RuntimeTypes] = &[
+    RuntimeTypes::RUST,
+    #[cfg(feature = "c")]
+    RuntimeTypes::C,
+];
+
+// Fibonacci of 30 by iteration, with `@fib_iterative` taking any n
+#[cfg(test)]
+const TEST_FIB: &str = "
+@fib_iterative = a
+  & @fib_iterative__bend0 ~ (0 (1 a))
+@fib_iterative__bend0 = (b (c ({$([!0] ?(((a (* (* a))) @fib_iterative__bend0__C0) (b (c (d e))))) d} e)))
+@fib_iterative__bend0__C0 = (* ($([+] $(b c)) ({a b} ($([:-1] d) e))))
+  & @fib_iterative__bend0 ~ (a (c (d e)))
+@main = a
+  & @fib_iterative ~ (30 a)
+";
+
+// Sums 2^8 branches, each counting down from 256, so it's wide enough to
+// spread over threads
+#[cfg(test)]
+const TEST_STRESS: &str = "
+@fun = (?((@fun__C0 @fun__C1) a) a)
+@fun__C0 = a
+  & @loop ~ (256 a)
+@fun__C1 = ({a b} d)
+  &! @fun ~ (a $([+] $(c d)))
+  &! @fun ~ (b c)
+@loop = (?((0 @loop__C0) a) a)
+@loop__C0 = a
+  & @loop ~ a
+@main = a
+  & @fun ~ (8 a)
+";
+
+// Never terminates
+#[cfg(test)]
+const TEST_SPIN: &str = "
+@loop = (a b)
+  & @loop ~ (a b)
+@main = a
+  & @loop ~ (a *)
+";
+
+// The list of numbers below n, as `@range`
+#[cfg(test)]
+const TEST_RANGE: &str = "
+@range = (?((@range__C0 @range__C1) a) a)
+@range__C0 = ((0 a) a)
+@range__C1 = ({a b} ((1 (a (c d))) d))
+  & @range ~ (b c)
+";
+
+// The length of a list, as `@len`
+#[cfg(test)]
+const TEST_LEN: &str = "
+@len = ((@len__C0 a) a)
+@len__C0 = (?((0 @len__C1) a) a)
+@len__C1 = (* (* (a b)))
+  & @len ~ (a $([+1] b))
+";
+
+// Small nets on one thread, with output captured so tests stay quiet
+#[cfg(test)]
+fn test_options() -> EvaluationOptions {
+    EvaluationOptions {
+        node_len: 1 << 20,
+        vars_len: 1 << 20,
+        threads: 1,
+        mem_dump: MemDumpModes::NONE as u32,
+        entry: std::ptr::null(),
+        max_interactions: 0,
+        timeout_ms: 0,
+        cancellation: std::ptr::null(),
+        io_policy: std::ptr::null(),
+        stdio: StdioModes::CAPTURE as u32,
+        stdin_buf: std::ptr::null(),
+        stdin_len: 0,
+        output: None,
+        output_ctx: std::ptr::null_mut(),
+        args: std::ptr::null(),
+        args_len: 0,
+        progress: None,
+        progress_ctx: std::ptr::null_mut(),
+        progress_interactions: 0,
+        progress_interval_ms: 0,
+    }
+}
+
+// What tests read from an `EvaluationResultRaw`
+#[cfg(test)]
+#[derive(Debug)]
+struct TestResult {
+    status: u32,
+    iterations: u64,
+    result: String,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    rules: [u64; 8],
+    peaks: [u64; 3], // rbag, node, vars
+}
+
+#[cfg(test)]
+unsafe fn test_error(err: *mut ErrorRaw) -> Result<(), Error> {
+    if err.is_null() { Ok(()) } else { Err(ErrorRaw::take(err)) }
+}
+
+// Copies a result out and frees it.
+#[cfg(test)]
+unsafe fn test_result(raw: *mut EvaluationResultRaw) -> TestResult {
+    let bytes = |ptr: *mut u8, len: u64| if ptr.is_null() { vec![] } else { std::slice::from_raw_parts(ptr, len as usize).to_vec() };
+    let res = &*raw;
+    let result = TestResult {
+        status: res.status as u32,
+        iterations: res.iterations,
+        result: CStr::from_ptr(res.result).to_string_lossy().into_owned(),
+        stdout: bytes(res.stdout, res.stdout_len),
+        stderr: bytes(res.stderr, res.stderr_len),
+        rules: res.rules,
+        peaks: [res.peak_rbag, res.peak_node, res.peak_vars],
+    };
+    free_evaluation_result(raw);
+    result
+}
+
+// Write IO programs in core syntax: `test_io_call` calls `func` with `argm`
+// and passes its result to `cont`, and `test_io_done` ends with `val`. The
+// vars they add are numbered with `fresh`.
+#[cfg(test)]
+fn test_io_call(func: &str, argm: &str, cont: &str, fresh: &mut u32) -> String {
+    *fresh += 1;
+    let ret = *fresh;
+    format!("((1 (({} {}) ({} ({} ({} s{ret}))))) s{ret})", hvm::IO_MAGIC_0, hvm::IO_MAGIC_1, test_io_text(func.as_bytes(), fresh), argm, cont)
+}
+
+#[cfg(test)]
+fn test_io_done(val: &str, fresh: &mut u32) -> String {
+    *fresh += 1;
+    format!("((0 (({} {}) ({} s{ret}))) s{ret})", hvm::IO_MAGIC_0, hvm::IO_MAGIC_1, val, ret = *fresh)
+}
+
+// A string as IO takes it: a list of bytes
+#[cfg(test)]
+fn test_io_text(text: &[u8], fresh: &mut u32) -> String {
+    *fresh += 1;
+    let mut list = format!("((0 s{0}) s{0})", *fresh);
+    for byte in text.iter().rev() {
+        *fresh += 1;
+        list = format!("((1 ({} ({} s{ret}))) s{ret})", byte, list, ret = *fresh);
+    }
+    list
+}
+
+#[cfg(test)]
+fn test_book(code: &str) -> *mut BookHandle {
+    let code = CString::new(code).unwrap();
+    let mut err = std::ptr::null_mut();
+    unsafe {
+        let book = book_parse(code.as_ptr(), &mut err);
+        test_error(err).unwrap();
+        book
+    }
+}
+
+#[cfg(test)]
+fn test_evaluate(book: *const BookHandle, runtime: RuntimeTypes, options: &EvaluationOptions) -> Result<TestResult, Error> {
+    let mut err = std::ptr::null_mut();
+    unsafe {
+        let raw = book_evaluate(book, runtime as u32, options, &mut err);
+        test_error(err)?;
+        Ok(test_result(raw))
+    }
+}
+
+#[test]
+fn test_invalid_options() {
+    let book = test_book(TEST_FIB);
+    let mut err = std::ptr::null_mut();
+    unsafe {
+        let raw = book_evaluate(book, 7, &test_options(), &mut err);
+        assert!(raw.is_null());
+        let error = test_error(err).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::INVALID_ARGUMENT);
+        assert_eq!(error.message, "Invalid runtime type: 7");
+        let session = session_new(2, &test_options(), &mut err);
+        assert!(session.is_null());
+        assert_eq!(test_error(err).unwrap_err().message, "Invalid runtime type: 2");
+    }
+    for &runtime in TEST_RUNTIMES {
+        let options = EvaluationOptions { mem_dump: 9, ..test_options() };
+        assert_eq!(test_evaluate(book, runtime, &options).unwrap_err().message, "Invalid memory dump mode: 9");
+        let options = EvaluationOptions { stdio: 5, ..test_options() };
+        assert_eq!(test_evaluate(book, runtime, &options).unwrap_err().message, "Invalid stdio mode: 5");
+        let options = EvaluationOptions { threads: MAX_THREADS + 1, ..test_options() };
+        assert_eq!(test_evaluate(book, runtime, &options).unwrap_err().kind, ErrorKind::INVALID_ARGUMENT);
+        let options = EvaluationOptions { node_len: MAX_NET_LEN + 1, ..test_options() };
+        assert_eq!(test_evaluate(book, runtime, &options).unwrap_err().kind, ErrorKind::INVALID_ARGUMENT);
+    }
+    unsafe { free_book(book) };
+}
+
+#[test]
+fn test_out_of_memory() {
+    // Builds a tree of 1024 leaves, which can't fit in 64 nodes
+    let book = test_book("
+@tree = (?((0 @tree__C0) a) a)
+@tree__C0 = ({a b} (c d))
+  & @tree ~ (a c)
+  & @tree ~ (b d)
+@main = a
+  & @tree ~ (10 a)
+");
+    for &runtime in TEST_RUNTIMES {
+        for threads in [1, 2] {
+            let options = EvaluationOptions { node_len: 64, vars_len: 64, threads, ..test_options() };
+            let error = test_evaluate(book, runtime, &options).unwrap_err();
+            assert_eq!(error.kind, ErrorKind::OOM, "{}", error.message);
+        }
+        let result = test_evaluate(book, runtime, &test_options()).unwrap();
+        assert_eq!(result.status, EvaluationStatus::COMPLETED as u32);
+        assert_eq!(result.result.matches('(').count(), 1023);
+    }
+    unsafe { free_book(book) };
+}
+
+#[test]
+fn test_runtimes_agree() {
+    for code in [TEST_FIB, TEST_STRESS] {
+        let book = test_book(code);
+        let expected = test_evaluate(book, RuntimeTypes::RUST, &test_options()).unwrap();
+        for &runtime in TEST_RUNTIMES {
+            for threads in [1, 4] {
+                let options = EvaluationOptions { threads, ..test_options() };
+                let result = test_evaluate(book, runtime, &options).unwrap();
+                assert_eq!(result.result, expected.result);
+                assert_eq!(result.iterations, expected.iterations);
+            }
+        }
+        unsafe { free_book(book) };
+    }
+}
+
+#[test]
+fn test_halting() {
+    let spin = test_book(TEST_SPIN);
+    let fib = test_book(TEST_FIB);
+    for &runtime in TEST_RUNTIMES {
+        for threads in [1, 3] {
+            let options = EvaluationOptions { threads, max_interactions: 1000, ..test_options() };
+            let result = test_evaluate(spin, runtime, &options).unwrap();
+            assert_eq!(result.status, EvaluationStatus::FUEL_EXHAUSTED as u32);
+            assert!(result.iterations >= 1000);
+            let options = EvaluationOptions { threads, max_interactions: 1_000_000, ..test_options() };
+            assert_eq!(test_evaluate(fib, runtime, &options).unwrap().status, EvaluationStatus::COMPLETED as u32);
+
+            let options = EvaluationOptions { threads, timeout_ms: 50, ..test_options() };
+            assert_eq!(test_evaluate(spin, runtime, &options).unwrap().status, EvaluationStatus::TIMED_OUT as u32);
+
+            // Cancelled from another thread while running
+            let token = cancellation_token_new();
+            let options = EvaluationOptions { threads, cancellation: token, ..test_options() };
+            let token_addr = token as usize;
+            let canceller = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                unsafe { cancellation_token_cancel(token_addr as *const CancellationToken) };
+            });
+            assert_eq!(test_evaluate(spin, runtime, &options).unwrap().status, EvaluationStatus::CANCELLED as u32);
+            canceller.join().unwrap();
+            unsafe { free_cancellation_token(token) };
+        }
+    }
+    unsafe {
+        free_book(spin);
+        free_book(fib);
+    }
+}
+
+#[test]
+fn test_evaluation_errors() {
+    let code = CString::new("@main = (a").unwrap();
+    let mut err = std::ptr::null_mut();
+    unsafe {
+        assert!(book_parse(code.as_ptr(), &mut err).is_null());
+        let error = test_error(err).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::PARSE);
+        assert_eq!(error.span, Some((10, 10)));
+    }
+
+    let book = test_book(TEST_FIB);
+    let entry = CString::new("nope").unwrap();
+    for &runtime in TEST_RUNTIMES {
+        let options = EvaluationOptions { entry: entry.as_ptr(), ..test_options() };
+        let error = test_evaluate(book, runtime, &options).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::MISSING_ENTRY);
+        assert_eq!(error.message, "No `@nope` definition found");
+        assert_eq!(error.span, None);
+    }
+    unsafe { free_book(book) };
+}
+
+#[test]
+fn test_non_affine_clone() {
+    // `@f` duplicates a var, so cloning it through a dup is an error
+    let book = test_book("
+@f = ({x y} (x y))
+@main = r
+  & @f ~ {r *}
+");
+    for &runtime in TEST_RUNTIMES {
+        for threads in [1, 3] {
+            let options = EvaluationOptions { threads, ..test_options() };
+            let error = test_evaluate(book, runtime, &options).unwrap_err();
+            assert_eq!(error.kind, ErrorKind::RUNTIME);
+            assert_eq!(error.message, "attempt to clone a non-affine global reference `@f`");
+        }
+    }
+    unsafe { free_book(book) };
+}
+
+#[test]
+fn test_panics_become_errors() {
+    let mut err = std::ptr::null_mut();
+    unsafe {
+        assert_eq!(guard(&mut err, 7, || panic!("boom {}", 1)), 7);
+        let error = test_error(err).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::PANIC);
+        assert_eq!(error.message, "boom 1");
+    }
+}
+
+// Integer division by zero gives 0 rather than trapping, like in hvm.c
+#[test]
+fn test_division_by_zero() {
+    let book = test_book("
+@div = c
+  & 7 ~ $([/] $(0 c))
+@rem = c
+  & 7 ~ $([%] $(0 c))
+@neg = c
+  & -7 ~ $([/] $(0 c))
+@float = c
+  & 1.5 ~ $([/] $(0.0 c))
+");
+    for &runtime in TEST_RUNTIMES {
+        for (name, expected) in [("div", "0"), ("rem", "0"), ("neg", "0"), ("float", "+inf")] {
+            let entry = CString::new(name).unwrap();
+            let options = EvaluationOptions { entry: entry.as_ptr(), ..test_options() };
+            assert_eq!(test_evaluate(book, runtime, &options).unwrap().result, expected);
+        }
+    }
+    unsafe { free_book(book) };
+}
+
+#[test]
+fn test_introspection() {
+    let book = test_book(TEST_FIB);
+    let mut err = std::ptr::null_mut();
+    unsafe {
+        let text = |ptr: *mut c_char| {
+            let text = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+            free_cstring(ptr);
+            text
+        };
+        assert_eq!(book_def_count(book), 4);
+        let names: Vec<String> = (0..4).map(|fid| text(book_def_name(book, fid, &mut err))).collect();
+        assert_eq!(names, ["main", "fib_iterative", "fib_iterative__bend0", "fib_iterative__bend0__C0"]);
+        assert_eq!(text(book_def_source(book, 0, &mut err)), "a & @fib_iterative ~ (30 a)");
+
+        let mut info = std::mem::zeroed::<DefInfo>();
+        book_def_info(book, 2, &mut info, &mut err);
+        test_error(err).unwrap();
+        assert_eq!((info.fid, info.safe, info.node_len, info.rbag_len, info.vars_len), (2, 0, 13, 0, 5));
+
+        assert!(book_def_name(book, 4, &mut err).is_null());
+        let error = test_error(err).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::INVALID_ARGUMENT);
+        assert_eq!(error.message, "No definition with fid 4");
+        free_book(book);
+    }
+}
+
+#[test]
+fn test_serialization() {
+    let book = test_book(TEST_FIB);
+    let mut err = std::ptr::null_mut();
+    unsafe {
+        let vec = book_serialize(book, &mut err);
+        test_error(err).unwrap();
+        let mut data = vec![0; vec_get_length(vec) as usize];
+        vec_copy(vec, data.as_mut_ptr(), data.len() as u64);
+        free_vec(vec);
+
+        // Both runtimes evaluate the deserialized book
+        let copy = book_deserialize(data.as_ptr(), data.len() as u64, &mut err);
+        test_error(err).unwrap();
+        for &runtime in TEST_RUNTIMES {
+            assert_eq!(test_evaluate(copy, runtime, &test_options()).unwrap().result, "832040");
+        }
+        free_book(copy);
+
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(book_deserialize(data.as_ptr(), data.len() as u64, &mut err).is_null());
+        let error = test_error(err).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::FORMAT);
+        assert_eq!(error.message, "Checksum mismatch");
+        free_book(book);
+    }
+}
+
+#[test]
+fn test_decode_results() {
+    let book = test_book("
+@Nil = ((0 a) a)
+@Cons = (h (t ((1 (h (t r))) r)))
+@main = a
+  & @Cons ~ (104 (b a))
+  & @Cons ~ (105 (@Nil b))
+");
+    let shape = CString::new("str").unwrap();
+    let mut err = std::ptr::null_mut();
+    for &runtime in TEST_RUNTIMES {
+        let result = test_evaluate(book, runtime, &test_options()).unwrap();
+        let term = CString::new(result.result).unwrap();
+        unsafe {
+            let value = book_decode(book, term.as_ptr(), shape.as_ptr(), &mut err);
+            test_error(err).unwrap();
+            let text = value_get_string(value);
+            assert_eq!(CStr::from_ptr(text).to_str(), Ok("hi"));
+            free_cstring(text);
+            free_value(value);
+        }
+    }
+    unsafe { free_book(book) };
+}
+
+#[test]
+fn test_io() {
+    // Echoes a line from stdin, then writes to stderr. Strings are bytes, so
+    // one that isn't UTF-8 goes through as is.
+    let mut fresh = 0;
+    let done = test_io_done("*", &mut fresh);
+    let note = test_io_call("WRITE", &format!("(2 {})", test_io_text(b"done\n", &mut fresh)), &format!("(* {})", done), &mut fresh);
+    let echo = test_io_call("WRITE", "(1 line)", &format!("(* {})", note), &mut fresh);
+    let read = test_io_call("READ_LINE", "0", &format!("(line {})", echo), &mut fresh);
+    let book = test_book(&format!("@main = {}", read));
+    let policy = IoPolicy { dirs: std::ptr::null(), dirs_len: 0, ffns: std::ptr::null(), ffns_len: 0, max_files: 0, unrestricted: 1 };
+    let input = b"h\xE9llo\nrest";
+    for &runtime in TEST_RUNTIMES {
+        let options = EvaluationOptions { io_policy: &policy, stdin_buf: input.as_ptr(), stdin_len: input.len() as u64, ..test_options() };
+        let result = test_evaluate(book, runtime, &options).unwrap();
+        assert_eq!(result.status, EvaluationStatus::COMPLETED as u32);
+        assert_eq!(result.stdout, b"h\xE9llo");
+        assert_eq!(result.stderr, b"done\n");
+    }
+    unsafe { free_book(book) };
+}
+
+#[cfg(test)]
+unsafe extern "C" fn test_upper(data: *mut c_void, argm: *const Value) -> *mut Value {
+    (*(data as *const AtomicU32)).fetch_add(1, Ordering::Relaxed);
+    match &*argm {
+        Value::Str(text) if text == "big" => Box::into_raw(Box::new(Value::Str("X".repeat(5000)))),
+        Value::Str(text) => Box::into_raw(Box::new(Value::Str(text.to_uppercase()))),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+#[test]
+fn test_host_functions() {
+    // Writes `UPPER("hello")`, then calls it with a number, which isn't a
+    // string, and ends with the length of what it answers with a string it
+    // makes long
+    let mut fresh = 0;
+    let done = test_io_done("len", &mut fresh);
+    let big = test_io_call("UPPER", &test_io_text(b"big", &mut fresh), &format!("(big {})", done), &mut fresh);
+    let number = test_io_call("UPPER", "5", &format!("(* {})", big), &mut fresh);
+    let write = test_io_call("WRITE", "(1 text)", &format!("(* {})", number), &mut fresh);
+    let upper = test_io_call("UPPER", &test_io_text(b"hello", &mut fresh), &format!("(text {})", write), &mut fresh);
+    let book = test_book(&format!("@main = {}\n  & @len ~ (big len)\n{}", upper, TEST_LEN));
+    let name = CString::new("UPPER").unwrap();
+    let shape = CString::new("str").unwrap();
+    let calls = AtomicU32::new(0);
+    let mut err = std::ptr::null_mut();
+    unsafe {
+        book_register_ffn(book, name.as_ptr(), shape.as_ptr(), test_upper, &calls as *const AtomicU32 as *mut c_void, &mut err);
+        test_error(err).unwrap();
+        book_register_ffn(book, name.as_ptr(), shape.as_ptr(), test_upper, std::ptr::null_mut(), &mut err);
+        assert_eq!(test_error(err).unwrap_err().message, "Function `UPPER` is already defined");
+        let write = CString::new("WRITE").unwrap();
+        book_register_ffn(book, write.as_ptr(), shape.as_ptr(), test_upper, std::ptr::null_mut(), &mut err);
+        assert_eq!(test_error(err).unwrap_err().message, "Function `WRITE` is already defined");
+    }
+    let policy = IoPolicy { dirs: std::ptr::null(), dirs_len: 0, ffns: std::ptr::null(), ffns_len: 0, max_files: 0, unrestricted: 1 };
+    for &runtime in TEST_RUNTIMES {
+        calls.store(0, Ordering::Relaxed);
+        let options = EvaluationOptions { io_policy: &policy, ..test_options() };
+        let result = test_evaluate(book, runtime, &options).unwrap();
+        let done = ast::Tree::parse(&result.result).unwrap();
+        let done = Value::decode(&done, &Shape::Ctr, &mut |_| None).unwrap();
+        assert_eq!(done.items().unwrap()[1], Value::U24(5000));
+        assert_eq!(result.stdout, b"HELLO");
+        assert_eq!(String::from_utf8_lossy(&result.stderr), "UPPER: Expected a list, found `5`\n");
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+    unsafe { free_book(book) };
+}
+
+#[test]
+fn test_io_policy() {
+    let root = std::env::temp_dir().join(format!("hvm_io_policy_{}", std::process::id()));
+    std::fs::create_dir_all(root.join("ro")).unwrap();
+    std::fs::write(root.join("ro/in.txt"), "in\n").unwrap();
+    let path = |name: &str| root.join(name).to_str().unwrap().as_bytes().to_vec();
+
+    // Opens files in both dirs and outside them, then gets the time, and ends
+    // with what each call returned
+    let mut fresh = 0;
+    let done = test_io_done("(a (b (c (d (e f)))))", &mut fresh);
+    let open = |file: &[u8], mode: &[u8], ret: &str, cont: String, fresh: &mut u32| {
+        let argm = format!("({} {})", test_io_text(file, fresh), test_io_text(mode, fresh));
+        test_io_call("OPEN_FILE", &argm, &format!("({} {})", ret, cont), fresh)
+    };
+    let time = test_io_call("GET_TIME", "*", &format!("(f {})", done), &mut fresh);
+    let read_again = open(&path("ro/in.txt"), b"r", "e", time, &mut fresh);
+    let read = open(&path("ro/../ro/in.txt"), b"r", "d", read_again, &mut fresh);
+    let append = open(&path("ro/in.txt"), b"a", "c", read, &mut fresh);
+    let outside = open(b"/etc/hostname", b"r", "b", append, &mut fresh);
+    let write = open(&path("out.txt"), b"w", "a", outside, &mut fresh);
+    let book = test_book(&format!("@main = {}", write));
+
+    let rw = CString::new(root.to_str().unwrap()).unwrap();
+    let ro = CString::new(root.join("ro").to_str().unwrap()).unwrap();
+    let dirs = [IoDirectory { path: rw.as_ptr(), writable: 1 }, IoDirectory { path: ro.as_ptr(), writable: 0 }];
+    let open_file = CString::new("OPEN_FILE").unwrap();
+    let ffns = [open_file.as_ptr()];
+    let denied = format!("\
+io_open_file: permission denied: /etc/hostname
+io_open_file: permission denied: {}
+", root.join("ro/in.txt").display());
+    for (max_files, fds, limited) in [(5, "(3 (* (* (4 (5 *)))))", ""), (2, "(3 (* (* (4 (* *)))))", "io_open_file: too many open files\n")] {
+        let policy = IoPolicy { dirs: dirs.as_ptr(), dirs_len: 2, ffns: ffns.as_ptr(), ffns_len: 1, max_files, unrestricted: 0 };
+        for &runtime in TEST_RUNTIMES {
+            let options = EvaluationOptions { io_policy: &policy, ..test_options() };
+            let result = test_evaluate(book, runtime, &options).unwrap();
+            assert!(result.result.contains(fds), "{}", result.result);
+            assert_eq!(String::from_utf8_lossy(&result.stderr), format!("{}{}io_call: permission denied: GET_TIME\n", denied, limited));
+        }
+    }
+
+    // Without a policy, nothing is allowed
+    for &runtime in TEST_RUNTIMES {
+        let result = test_evaluate(book, runtime, &test_options()).unwrap();
+        assert!(result.result.contains("(* (* (* (* (* *)))))"), "{}", result.result);
+        assert_eq!(String::from_utf8_lossy(&result.stderr), "io_call: permission denied: OPEN_FILE\n".repeat(5) + "io_call: permission denied: GET_TIME\n");
+    }
+    unsafe { free_book(book) };
+
+    // Links are opened as where they lead, and both runtimes take the same
+    // modes
+    std::os::unix::fs::symlink(root.join("ro/in.txt"), root.join("in.txt")).unwrap();
+    std::os::unix::fs::symlink("/etc/hostname", root.join("hostname")).unwrap();
+    let mut fresh = 0;
+    let done = test_io_done("(a (b (c d)))", &mut fresh);
+    let create = open(&path("new.txt"), b"wx", "d", done, &mut fresh);
+    let invalid = open(&path("ro/in.txt"), b"rx", "c", create, &mut fresh);
+    let outside = open(&path("hostname"), b"r", "b", invalid, &mut fresh);
+    let inside = open(&path("in.txt"), b"rb", "a", outside, &mut fresh);
+    let book = test_book(&format!("@main = {}", inside));
+    let policy = IoPolicy { dirs: dirs.as_ptr(), dirs_len: 2, ffns: ffns.as_ptr(), ffns_len: 1, max_files: 5, unrestricted: 0 };
+    for &runtime in TEST_RUNTIMES {
+        let options = EvaluationOptions { io_policy: &policy, ..test_options() };
+        let result = test_evaluate(book, runtime, &options).unwrap();
+        assert!(result.result.contains("(3 (* (* 4)))"), "{}", result.result);
+        assert_eq!(String::from_utf8_lossy(&result.stderr), format!("io_open_file: permission denied: {}\nio_open_file: invalid mode: rx\n", root.join("hostname").display()));
+        std::fs::remove_file(root.join("new.txt")).unwrap();
+    }
+    unsafe { free_book(book) };
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_sleep_halts() {
+    // Sleeps for 1000 seconds
+    let mut fresh = 0;
+    let done = test_io_done("*", &mut fresh);
+    let book = test_book(&format!("@main = {}", test_io_call("SLEEP", "(1000 0)", &format!("(* {})", done), &mut fresh)));
+    let policy = IoPolicy { dirs: std::ptr::null(), dirs_len: 0, ffns: std::ptr::null(), ffns_len: 0, max_files: 0, unrestricted: 1 };
+    for &runtime in TEST_RUNTIMES {
+        let start = Instant::now();
+        let options = EvaluationOptions { io_policy: &policy, timeout_ms: 50, ..test_options() };
+        assert_eq!(test_evaluate(book, runtime, &options).unwrap().status, EvaluationStatus::TIMED_OUT as u32);
+
+        let token = cancellation_token_new();
+        let options = EvaluationOptions { io_policy: &policy, cancellation: token, ..test_options() };
+        let token_addr = token as usize;
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            unsafe { cancellation_token_cancel(token_addr as *const CancellationToken) };
+        });
+        assert_eq!(test_evaluate(book, runtime, &options).unwrap().status, EvaluationStatus::CANCELLED as u32);
+        canceller.join().unwrap();
+        unsafe { free_cancellation_token(token) };
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+    unsafe { free_book(book) };
+}
+
+#[cfg(test)]
+unsafe extern "C" fn test_output(ctx: *mut c_void, fd: u32, buf: *const u8, len: u64) {
+    let output = &mut *(ctx as *mut Vec<(u32, Vec<u8>)>);
+    output.push((fd, std::slice::from_raw_parts(buf, len as usize).to_vec()));
+}
+
+#[test]
+fn test_stdio() {
+    // Copies a line from stdin to stdout, and the next one to stderr
+    let mut fresh = 0;
+    let done = test_io_done("*", &mut fresh);
+    let err = test_io_call("WRITE", "(2 b)", &format!("(* {})", done), &mut fresh);
+    let second = test_io_call("READ_LINE", "0", &format!("(b {})", err), &mut fresh);
+    let out = test_io_call("WRITE", "(1 a)", &format!("(* {})", second), &mut fresh);
+    let first = test_io_call("READ_LINE", "0", &format!("(a {})", out), &mut fresh);
+    let book = test_book(&format!("@main = {}", first));
+    let read_line = CString::new("READ_LINE").unwrap();
+    let write = CString::new("WRITE").unwrap();
+    let ffns = [read_line.as_ptr(), write.as_ptr()];
+    let policy = IoPolicy { dirs: std::ptr::null(), dirs_len: 0, ffns: ffns.as_ptr(), ffns_len: 2, max_files: 0, unrestricted: 0 };
+    let input = b"first\nsecond";
+    for &runtime in TEST_RUNTIMES {
+        let options = EvaluationOptions { io_policy: &policy, stdin_buf: input.as_ptr(), stdin_len: input.len() as u64, ..test_options() };
+        let result = test_evaluate(book, runtime, &options).unwrap();
+        assert_eq!((result.stdout.as_slice(), result.stderr.as_slice()), (&b"first"[..], &b"second"[..]));
+
+        // Streamed instead of captured
+        let mut output: Vec<(u32, Vec<u8>)> = Vec::new();
+        let options = EvaluationOptions { output: Some(test_output), output_ctx: &mut output as *mut _ as *mut c_void, ..options };
+        let result = test_evaluate(book, runtime, &options).unwrap();
+        assert!(result.stdout.is_empty() && result.stderr.is_empty());
+        assert_eq!(output, [(1, b"first".to_vec()), (2, b"second".to_vec())]);
+
+        // Reading past the end gives empty lines, and says so
+        let options = EvaluationOptions { io_policy: &policy, ..test_options() };
+        let result = test_evaluate(book, runtime, &options).unwrap();
+        assert_eq!(result.status, EvaluationStatus::COMPLETED as u32);
+        assert!(result.stdout.is_empty());
+        assert_eq!(result.stderr, b"io_read_line: failed to read\n".repeat(2));
+    }
+    unsafe { free_book(book) };
+}
+
+#[test]
+fn test_session() {
+    let books = [test_book(TEST_FIB), test_book(TEST_STRESS), test_book(TEST_SPIN)];
+    let entry = CString::new("nope").unwrap();
+    for &runtime in TEST_RUNTIMES {
+        let expected: Vec<_> = books[..2].iter().map(|&book| test_evaluate(book, runtime, &test_options()).unwrap()).collect();
+        let mut err = std::ptr::null_mut();
+        let session = unsafe { session_new(runtime as u32, &test_options(), &mut err) };
+        unsafe { test_error(err).unwrap() };
+        let evaluate = |book: *const BookHandle, options: &EvaluationOptions| unsafe {
+            let mut err = std::ptr::null_mut();
+            let raw = session_evaluate(session, book, options, &mut err);
+            test_error(err).map(|()| test_result(raw))
+        };
+
+        // Each evaluation starts from a clean net, however the last one ended
+        for _ in 0..2 {
+            let options = EvaluationOptions { max_interactions: 10_000, ..test_options() };
+            assert_eq!(evaluate(books[2], &options).unwrap().status, EvaluationStatus::FUEL_EXHAUSTED as u32);
+            let options = EvaluationOptions { entry: entry.as_ptr(), ..test_options() };
+            assert_eq!(evaluate(books[0], &options).unwrap_err().kind, ErrorKind::MISSING_ENTRY);
+            for (&book, expected) in books.iter().zip(&expected) {
+                let result = evaluate(book, &test_options()).unwrap();
+                assert_eq!((result.result, result.iterations), (expected.result.clone(), expected.iterations));
+            }
+        }
+        unsafe { free_session(session) };
+    }
+    for book in books {
+        unsafe { free_book(book) };
+    }
+}
+
+#[test]
+fn test_full_size_nets() {
+    // Nets of the default size only take the memory they use, so many can be
+    // kept at once
+    let book = test_book(TEST_FIB);
+    let options = EvaluationOptions { node_len: 0, vars_len: 0, ..test_options() };
+    for &runtime in TEST_RUNTIMES {
+        let mut err = std::ptr::null_mut();
+        let sessions: Vec<_> = (0..8).map(|_| unsafe { session_new(runtime as u32, &options, &mut err) }).collect();
+        unsafe { test_error(err).unwrap() };
+        for &session in &sessions {
+            for _ in 0..2 {
+                let result = unsafe { test_result(session_evaluate(session, book, &options, &mut err)) };
+                assert_eq!(result.result, "832040");
+            }
+        }
+        for session in sessions {
+            unsafe { free_session(session) };
+        }
+    }
+    unsafe { free_book(book) };
+}
+
+#[test]
+fn test_arguments() {
+    let book = test_book(&format!("
+@add = ($([+] $(b c)) (b c))
+@id = (a a)
+@fst = ((a *) a)
+{}", TEST_LEN));
+    let name = |name: &str| CString::new(name).unwrap();
+    let (add, id, fst, len) = (name("add"), name("id"), name("fst"), name("len"));
+    let mut err = std::ptr::null_mut();
+    for &runtime in TEST_RUNTIMES {
+        let run = |entry: &CString, args: &[*mut Value]| {
+            let options = EvaluationOptions { entry: entry.as_ptr(), args: args.as_ptr() as *const *const c_void, args_len: args.len() as u64, ..test_options() };
+            test_evaluate(book, runtime, &options)
+        };
+        unsafe {
+            let args = [value_u24(3), value_u24(4)];
+            assert_eq!(run(&add, &args).unwrap().result, "7");
+            for arg in args {
+                free_value(arg);
+            }
+
+            let tuple = value_tuple();
+            value_push(tuple, value_u24(9), &mut err);
+            value_push(tuple, value_bool(1), &mut err);
+            assert_eq!(run(&fst, &[tuple]).unwrap().result, "9");
+            free_value(tuple);
+
+            // Strings round-trip, and are only limited by the net's size
+            let text = CString::new("héllo ".repeat(8)).unwrap();
+            let string = value_string(text.as_ptr(), &mut err);
+            test_error(err).unwrap();
+            let result = run(&id, &[string]).unwrap();
+            let tree = CString::new(result.result).unwrap();
+            let shape = CString::new("str").unwrap();
+            let decoded = book_decode(book, tree.as_ptr(), shape.as_ptr(), &mut err);
+            test_error(err).unwrap();
+            assert_eq!((*decoded).clone(), (*string).clone());
+            free_value(decoded);
+            free_value(string);
+            let text = CString::new("x".repeat(20_000)).unwrap();
+            let string = value_string(text.as_ptr(), &mut err);
+            assert_eq!(run(&len, &[string]).unwrap().result, "20000");
+            let options = EvaluationOptions { entry: len.as_ptr(), args: &string as *const *mut Value as *const *const c_void, args_len: 1, node_len: 1 << 16, ..test_options() };
+            assert_eq!(test_evaluate(book, runtime, &options).err().unwrap().kind, ErrorKind::OOM);
+            free_value(string);
+
+            assert_eq!(run(&id, &[std::ptr::null_mut()]).unwrap_err().kind, ErrorKind::INVALID_ARGUMENT);
+        }
+    }
+    unsafe { free_book(book) };
+}
+
+#[test]
+fn test_batch() {
+    let book = test_book(TEST_FIB);
+    let entry = CString::new("fib_iterative").unwrap();
+    let options = EvaluationOptions { entry: entry.as_ptr(), ..test_options() };
+    let values: Vec<[*const c_void; 1]> = (0..16).map(|n| [value_u24(n * 10) as *const c_void]).collect();
+    let mut items: Vec<BatchItem> = values.iter().map(|args| BatchItem { args: args.as_ptr(), args_len: 1 }).collect();
+    let null = [std::ptr::null::<c_void>()];
+    items[5] = BatchItem { args: null.as_ptr(), args_len: 1 };
+    let mut err = std::ptr::null_mut();
+    for &runtime in TEST_RUNTIMES {
+        unsafe {
+            // Each item gets what evaluating it alone gives, errors included
+            let batch = book_evaluate_batch(book, runtime as u32, &options, items.as_ptr(), items.len() as u64, 4, &mut err);
+            test_error(err).unwrap();
+            assert_eq!((*batch).len, items.len() as u64);
+            for (index, item) in items.iter().enumerate() {
+                let result = std::mem::replace(&mut *(*batch).results.add(index), std::ptr::null_mut());
+                let error = std::mem::replace(&mut *(*batch).errors.add(index), std::ptr::null_mut());
+                assert_eq!((result.is_null(), error.is_null()), (index == 5, index != 5));
+                let options = EvaluationOptions { args: item.args, args_len: item.args_len, ..options };
+                match test_evaluate(book, runtime, &options) {
+                    Ok(expected) => assert_eq!(test_result(result).result, expected.result),
+                    Err(expected) => assert_eq!(test_error(error).unwrap_err().message, expected.message),
+                }
+            }
+            free_batch_result(batch);
+
+            let batch = book_evaluate_batch(book, runtime as u32, &options, std::ptr::null(), 0, 0, &mut err);
+            test_error(err).unwrap();
+            assert_eq!((*batch).len, 0);
+            free_batch_result(batch);
+            assert!(book_evaluate_batch(book, runtime as u32, &options, items.as_ptr(), 2, MAX_THREADS + 1, &mut err).is_null());
+            assert_eq!(test_error(err).unwrap_err().kind, ErrorKind::INVALID_ARGUMENT);
+        }
+    }
+    for [value] in values {
+        unsafe { free_value(value as *mut Value) };
+    }
+    unsafe { free_book(book) };
+
+    // Results are read back on the workers, however long they are
+    let book = test_book(TEST_RANGE);
+    let entry = CString::new("range").unwrap();
+    let options = EvaluationOptions { entry: entry.as_ptr(), ..test_options() };
+    let args = [value_u24(4000) as *const c_void];
+    let items = [BatchItem { args: args.as_ptr(), args_len: 1 }, BatchItem { args: args.as_ptr(), args_len: 1 }];
+    for &runtime in TEST_RUNTIMES {
+        unsafe {
+            let batch = book_evaluate_batch(book, runtime as u32, &options, items.as_ptr(), 2, 2, &mut err);
+            test_error(err).unwrap();
+            for index in 0..2 {
+                let result = std::mem::replace(&mut *(*batch).results.add(index), std::ptr::null_mut());
+                assert_eq!(test_result(result).result.matches("(1 (").count(), 4000);
+            }
+            free_batch_result(batch);
+        }
+    }
+    unsafe {
+        free_value(args[0] as *mut Value);
+        free_book(book);
+    }
+}
+
+#[cfg(test)]
+unsafe extern "C" fn test_completed(ctx: *mut c_void) {
+    (*(ctx as *const AtomicU32)).fetch_add(1, Ordering::Relaxed);
+}
+
+#[test]
+fn test_async() {
+    let fib = test_book(TEST_FIB);
+    let spin = test_book(TEST_SPIN);
+    let calls = AtomicU32::new(0);
+    let ctx = &calls as *const AtomicU32 as *mut c_void;
+    let mut err = std::ptr::null_mut();
+    for &runtime in TEST_RUNTIMES {
+        unsafe {
+            // Gives what a blocking evaluation does, and only once
+            let expected = test_evaluate(fib, runtime, &test_options()).unwrap();
+            let evaluation = book_evaluate_async(fib, runtime as u32, &test_options(), Some(test_completed), ctx, &mut err);
+            test_error(err).unwrap();
+            assert_eq!(evaluation_wait(evaluation, 0), 1);
+            assert_eq!(evaluation_poll(evaluation), 1);
+            let result = evaluation_take_result(evaluation, &mut err);
+            test_error(err).unwrap();
+            assert_eq!(test_result(result).result, expected.result);
+            assert!(evaluation_take_result(evaluation, &mut err).is_null());
+            assert_eq!(test_error(err).unwrap_err().kind, ErrorKind::INVALID_ARGUMENT);
+            free_evaluation(evaluation);
+            assert_eq!(calls.swap(0, Ordering::Relaxed), 1);
+
+            // Runs until cancelled, and has no result until then
+            let evaluation = book_evaluate_async(spin, runtime as u32, &test_options(), Some(test_completed), ctx, &mut err);
+            test_error(err).unwrap();
+            assert_eq!(evaluation_wait(evaluation, 50), 0);
+            assert_eq!(evaluation_poll(evaluation), 0);
+            assert!(evaluation_take_result(evaluation, &mut err).is_null());
+            test_error(err).unwrap();
+            evaluation_cancel(evaluation);
+            assert_eq!(evaluation_wait(evaluation, 0), 1);
+            let result = evaluation_take_result(evaluation, &mut err);
+            test_error(err).unwrap();
+            assert_eq!(test_result(result).status, EvaluationStatus::CANCELLED as u32);
+            free_evaluation(evaluation);
+            assert_eq!(calls.swap(0, Ordering::Relaxed), 1);
+
+            // Freeing a running evaluation cancels it, and errors are kept
+            let evaluation = book_evaluate_async(spin, runtime as u32, &test_options(), None, std::ptr::null_mut(), &mut err);
+            test_error(err).unwrap();
+            free_evaluation(evaluation);
+            let entry = CString::new("nope").unwrap();
+            let options = EvaluationOptions { entry: entry.as_ptr(), ..test_options() };
+            let evaluation = book_evaluate_async(fib, runtime as u32, &options, Some(test_completed), ctx, &mut err);
+            test_error(err).unwrap();
+            assert_eq!(evaluation_wait(evaluation, 0), 1);
+            assert!(evaluation_take_result(evaluation, &mut err).is_null());
+            assert_eq!(test_error(err).unwrap_err().kind, ErrorKind::MISSING_ENTRY);
+            free_evaluation(evaluation);
+            assert_eq!(calls.swap(0, Ordering::Relaxed), 1);
+        }
+    }
+    unsafe {
+        free_book(fib);
+        free_book(spin);
+    }
+
+    // Results are read back on the evaluation's thread, however long they are
+    let book = test_book(TEST_RANGE);
+    let entry = CString::new("range").unwrap();
+    let args = [value_u24(4000) as *const c_void];
+    let options = EvaluationOptions { entry: entry.as_ptr(), args: args.as_ptr(), args_len: 1, ..test_options() };
+    for &runtime in TEST_RUNTIMES {
+        unsafe {
+            let evaluation = book_evaluate_async(book, runtime as u32, &options, None, std::ptr::null_mut(), &mut err);
+            test_error(err).unwrap();
+            assert_eq!(evaluation_wait(evaluation, 0), 1);
+            let result = evaluation_take_result(evaluation, &mut err);
+            test_error(err).unwrap();
+            assert_eq!(test_result(result).result.matches("(1 (").count(), 4000);
+            free_evaluation(evaluation);
+        }
+    }
+    unsafe {
+        free_value(args[0] as *mut Value);
+        free_book(book);
+    }
+}
+
+// Records reports as (interactions, time), halting after `limit` of them
+#[cfg(test)]
+struct TestProgress {
+    reports: Vec<(u64, f64)>,
+    limit: usize,
+}
+
+#[cfg(test)]
+unsafe extern "C" fn test_progress(ctx: *mut c_void, progress: *const hvm::Progress) -> u32 {
+    let this = &mut *(ctx as *mut TestProgress);
+    this.reports.push(((*progress).interactions, (*progress).time));
+    (this.reports.len() < this.limit) as u32
+}
+
+#[test]
+fn test_progress_reports() {
+    let stress = test_book(TEST_STRESS);
+    let spin = test_book(TEST_SPIN);
+    for &runtime in TEST_RUNTIMES {
+        for threads in [1, 3] {
+            let run = |book, limit, progress_interactions, progress_interval_ms| {
+                let mut progress = TestProgress { reports: Vec::new(), limit };
+                let options = EvaluationOptions {
+                    threads,
+                    progress: Some(test_progress),
+                    progress_ctx: &mut progress as *mut TestProgress as *mut c_void,
+                    progress_interactions,
+                    progress_interval_ms,
+                    ..test_options()
+                };
+                let result = test_evaluate(book, runtime, &options).unwrap();
+                (result, progress.reports)
+            };
+
+            // Reported as it goes, without changing the result
+            let expected = test_evaluate(stress, runtime, &EvaluationOptions { threads, ..test_options() }).unwrap();
+            let (result, reports) = run(stress, usize::MAX, 10_000, 0);
+            assert_eq!((result.status, result.result), (expected.status, expected.result));
+            assert!(!reports.is_empty());
+            assert!(reports.windows(2).all(|pair| pair[0].0 < pair[1].0 && pair[0].1 <= pair[1].1));
+            assert!(reports.last().unwrap().0 <= result.iterations);
+
+            // And by time, until the host asks to stop
+            let (result, reports) = run(spin, 3, 0, 5);
+            assert_eq!(result.status, EvaluationStatus::CANCELLED as u32);
+            assert_eq!(reports.len(), 3);
+            assert!(reports.windows(2).all(|pair| pair[1].1 - pair[0].1 >= 0.003));
+        }
+        let options = EvaluationOptions { progress: Some(test_progress), ..test_options() };
+        let err = test_evaluate(stress, runtime, &options).err().unwrap();
+        assert_eq!(err.kind, ErrorKind::INVALID_ARGUMENT);
+    }
+    unsafe {
+        free_book(stress);
+        free_book(spin);
+    }
+}
+
+#[test]
+fn test_rule_counts() {
+    // Only links depend on how the work is split up
+    for code in [TEST_FIB, TEST_STRESS] {
+        let book = test_book(code);
+        let mut expected = None;
+        for &runtime in TEST_RUNTIMES {
+            for threads in [1, 3] {
+                let result = test_evaluate(book, runtime, &EvaluationOptions { threads, ..test_options() }).unwrap();
+                assert_eq!(result.iterations, result.rules[1..].iter().sum::<u64>());
+                assert!(result.rules[1..].iter().all(|&count| count < result.iterations));
+                assert_eq!(*expected.get_or_insert(result.rules[1..].to_vec()), result.rules[1..]);
+            }
+        }
+        unsafe { free_book(book) };
+    }
+}
+
+#[test]
+fn test_peaks() {
+    // Exact on one thread, so both runtimes agree there
+    for code in [TEST_FIB, TEST_STRESS] {
+        let book = test_book(code);
+        let mut expected = None;
+        for &runtime in TEST_RUNTIMES {
+            for threads in [1, 3] {
+                let options = EvaluationOptions { threads, ..test_options() };
+                let result = test_evaluate(book, runtime, &options).unwrap();
+                assert!(result.peaks.iter().all(|&peak| peak > 0));
+                assert!(result.peaks[1] <= options.node_len && result.peaks[2] <= options.vars_len);
+                if threads == 1 {
+                    assert_eq!(*expected.get_or_insert(result.peaks), result.peaks);
+                }
+            }
+        }
+        unsafe { free_book(book) };
+    }
 }
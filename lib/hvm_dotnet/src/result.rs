@@ -0,0 +1,127 @@
+use crate::ast::Tree;
+use crate::hvm;
+use crate::to_cstring;
+use std::collections::BTreeMap;
+use std::os::raw::c_char;
+
+// Result Trees
+// ------------
+
+// A read back result, flattened so the host can walk it without parsing.
+// Nodes are stored in pre-order, so the root is node 0 and children always
+// come after their parent.
+#[repr(C)]
+pub struct ResultTree {
+    nodes: *mut ResultNode,
+    nodes_len: u64,
+    names: *mut *mut c_char, // ref names, indexed by `ResultNode::fst`
+    names_len: u64,
+    vars_len: u64, // number of distinct var ids
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ResultNode {
+    tag: u32, // port tag (VAR, REF, ERA, NUM, CON, DUP, OPR or SWI)
+    typ: u32, // NUM: numeric type, from `Numb::get_typ`
+    fst: u32, // VAR: var id; REF: name index; nodes: fst child index
+    snd: u32, // nodes: snd child index
+    val: u32, // NUM: the value, decoded according to `typ` (see `flatten`)
+}
+
+impl ResultTree {
+    pub fn new(tree: &Tree) -> Self {
+        let mut nodes = Vec::new();
+        let mut names = Vec::new();
+        let mut vars = BTreeMap::new();
+        flatten(tree, &mut nodes, &mut names, &mut vars);
+        let names: Vec<*mut c_char> = names.into_iter().map(|name| to_cstring(name).into_raw()).collect();
+        ResultTree {
+            nodes_len: nodes.len() as u64,
+            nodes: Box::into_raw(nodes.into_boxed_slice()) as *mut ResultNode,
+            names_len: names.len() as u64,
+            names: Box::into_raw(names.into_boxed_slice()) as *mut *mut c_char,
+            vars_len: vars.len() as u64,
+        }
+    }
+}
+
+impl Drop for ResultTree {
+    fn drop(&mut self) {
+        unsafe {
+            _ = Box::from_raw(std::ptr::slice_from_raw_parts_mut(self.nodes, self.nodes_len as usize));
+            let names = Box::from_raw(std::ptr::slice_from_raw_parts_mut(self.names, self.names_len as usize));
+            for name in names.iter() {
+                _ = std::ffi::CString::from_raw(*name);
+            }
+        }
+    }
+}
+
+// Appends a tree in pre-order, returning its index. Vars get ids in order of
+// appearance, and each ref name is stored once.
+fn flatten(tree: &Tree, nodes: &mut Vec<ResultNode>, names: &mut Vec<String>, vars: &mut BTreeMap<String, u32>) -> u32 {
+    let index = nodes.len();
+    nodes.push(ResultNode { tag: 0, typ: 0, fst: 0, snd: 0, val: 0 });
+    let node = match tree {
+        Tree::Var { nam } => {
+            let len = vars.len() as u32;
+            let id = *vars.entry(nam.clone()).or_insert(len);
+            ResultNode { tag: hvm::VAR as u32, typ: 0, fst: id, snd: 0, val: 0 }
+        }
+        Tree::Ref { nam } => {
+            let name = match names.iter().position(|name| name == nam) {
+                Some(name) => name,
+                None => {
+                    names.push(nam.clone());
+                    names.len() - 1
+                }
+            };
+            ResultNode { tag: hvm::REF as u32, typ: 0, fst: name as u32, snd: 0, val: 0 }
+        }
+        Tree::Era => {
+            ResultNode { tag: hvm::ERA as u32, typ: 0, fst: 0, snd: 0, val: 0 }
+        }
+        Tree::Num { val } => {
+            // Syms hold their operator, partials their raw 24-bit operand
+            let numb = hvm::Numb(val.0);
+            let val = match numb.get_typ() {
+                hvm::TY_SYM => numb.get_sym() as u32,
+                hvm::TY_U24 => numb.get_u24(),
+                hvm::TY_I24 => numb.get_i24() as u32,
+                hvm::TY_F24 => numb.get_f24().to_bits(),
+                _ => numb.0 >> 5,
+            };
+            ResultNode { tag: hvm::NUM as u32, typ: numb.get_typ() as u32, fst: 0, snd: 0, val }
+        }
+        Tree::Con { fst, snd } | Tree::Dup { fst, snd } | Tree::Opr { fst, snd } | Tree::Swi { fst, snd } => {
+            let tag = match tree {
+                Tree::Con { .. } => hvm::CON,
+                Tree::Dup { .. } => hvm::DUP,
+                Tree::Opr { .. } => hvm::OPR,
+                _ => hvm::SWI,
+            };
+            let fst = flatten(fst, nodes, names, vars);
+            let snd = flatten(snd, nodes, names, vars);
+            ResultNode { tag: tag as u32, typ: 0, fst, snd, val: 0 }
+        }
+    };
+    nodes[index] = node;
+    index as u32
+}
+
+#[test]
+fn test_result_tree() {
+    let tree = Tree::parse("(1.5 (-3 (@id (x {x @id}))))").unwrap();
+    let result = ResultTree::new(&tree);
+    let nodes = unsafe { std::slice::from_raw_parts(result.nodes, result.nodes_len as usize) };
+    let fields: Vec<_> = nodes.iter().map(|node| (node.tag as hvm::Tag, node.fst, node.snd)).collect();
+    assert_eq!(fields, [
+        (hvm::CON, 1, 2), (hvm::NUM, 0, 0), (hvm::CON, 3, 4), (hvm::NUM, 0, 0), (hvm::CON, 5, 6),
+        (hvm::REF, 0, 0), (hvm::CON, 7, 8), (hvm::VAR, 0, 0), (hvm::DUP, 9, 10), (hvm::VAR, 0, 0), (hvm::REF, 0, 0),
+    ]);
+    assert_eq!((nodes[1].typ as hvm::Tag, f32::from_bits(nodes[1].val)), (hvm::TY_F24, 1.5));
+    assert_eq!((nodes[3].typ as hvm::Tag, nodes[3].val as i32), (hvm::TY_I24, -3));
+    assert_eq!((result.names_len, result.vars_len), (1, 1));
+    assert_eq!(unsafe { std::ffi::CStr::from_ptr(*result.names) }.to_str(), Ok("id"));
+}
@@ -0,0 +1,43 @@
+using System.Runtime.InteropServices;
+
+namespace HVM;
+
+[StructLayout(LayoutKind.Sequential)]
+internal unsafe struct BatchItemRaw
+{
+    public void** Args;
+    public ulong ArgsLength;
+}
+
+[StructLayout(LayoutKind.Sequential)]
+internal readonly unsafe struct BatchResultRaw
+{
+    public readonly EvaluationResultRaw** Results;
+    public readonly ErrorRaw** Errors;
+    public readonly ulong Length;
+}
+
+// One item of Book.EvaluateBatch(): either its result, or the exception its
+// evaluation failed with
+public readonly struct BatchResult
+{
+    public EvaluationResult? Result { get; }
+    public Exception? Error { get; }
+
+    private BatchResult(EvaluationResult? result, Exception? error)
+    {
+        Result = result;
+        Error = error;
+    }
+
+    // Copies every item of a native batch, which stays owned by the caller
+    internal static unsafe BatchResult[] FromRaw(BatchResultRaw* raw)
+    {
+        var results = new BatchResult[raw->Length];
+        for (var i = 0; i < results.Length; i++)
+            results[i] = raw->Results[i] != null
+                ? new BatchResult(new EvaluationResult(raw->Results[i]), null)
+                : new BatchResult(null, Errors.ToException(raw->Errors[i]));
+        return results;
+    }
+}
@@ -1,16 +1,20 @@
 using System.Runtime.CompilerServices;
+using System.Runtime.InteropServices;
 
 namespace HVM;
 
 public unsafe class Book : IDisposable
 {
     private void* _ptr;
+    private readonly List<GCHandle> _functions = new();
 
-    private Book(void* ptr)
+    internal Book(void* ptr)
     {
         _ptr = ptr;
     }
 
+    internal void* Pointer => _ptr;
+
     [Obsolete("Use Parse() instead")]
     public Book()
     {
@@ -19,49 +23,294 @@ public unsafe class Book : IDisposable
 
     public static Book Parse(string code)
     {
-        byte* errPtr = null;
+        ErrorRaw* errPtr = null;
         var bookPtr = Interops.BookParse(code, &errPtr);
-        using var errString = new CString(errPtr);
-        if (errString.HasValue)
+        Errors.ThrowIfAny(errPtr);
+
+        return new Book(bookPtr);
+    }
+
+    // Loads a book written by Serialize(). Its definitions have no source text.
+    public static Book Deserialize(ReadOnlySpan<byte> data)
+    {
+        ErrorRaw* errPtr = null;
+        void* bookPtr;
+        fixed (byte* dataPtr = data)
         {
-            throw new InteropException(errString.ToString());
+            bookPtr = Interops.BookDeserialize(dataPtr, (ulong)data.Length, &errPtr);
         }
+        Errors.ThrowIfAny(errPtr);
 
         return new Book(bookPtr);
     }
 
     public EvaluationResult Evaluate(RuntimeTypes runtimeType = RuntimeTypes.Rust, bool enableMemDump = false)
     {
-        byte* errPtr = null;
-        var resultPtr = Interops.BookEvaluate(_ptr, runtimeType, enableMemDump ? 1U : 0U, &errPtr);
+        return Evaluate(runtimeType, new EvaluationOptions
+        {
+            MemDump = enableMemDump ? MemDumpModes.Full : MemDumpModes.None
+        });
+    }
+
+    public EvaluationResult Evaluate(RuntimeTypes runtimeType, EvaluationOptions options,
+        CancellationToken cancellationToken = default)
+    {
+        return Evaluate(options, cancellationToken,
+            (optionsPtr, errOut) => Interops.BookEvaluate(_ptr, runtimeType, optionsPtr, errOut));
+    }
+
+    // Evaluates on a thread of the runtime's own, so no thread is blocked
+    // meanwhile. The book must not be disposed until the task completes.
+    public Task<EvaluationResult> EvaluateAsync(RuntimeTypes runtimeType, EvaluationOptions options,
+        CancellationToken cancellationToken = default)
+    {
+        var completion = new TaskCompletionSource(TaskCreationOptions.RunContinuationsAsynchronously);
+        var completionHandle = GCHandle.Alloc(completion);
+        // Output and progress may be reported until the evaluation finishes, after the options are freed
+        var outputHandle = options.Output == null ? default : GCHandle.Alloc(options.Output);
+        var progressHandle = options.Progress == null ? default : GCHandle.Alloc(options.Progress);
+        nint evaluationPtr;
         try
         {
-            using var errString = new CString(errPtr);
-            if (errString.HasValue)
+            evaluationPtr = WithOptions(options, default, optionsPtr =>
+            {
+                if (outputHandle.IsAllocated)
+                    optionsPtr->OutputContext = (void*)GCHandle.ToIntPtr(outputHandle);
+                if (progressHandle.IsAllocated)
+                    optionsPtr->ProgressContext = (void*)GCHandle.ToIntPtr(progressHandle);
+                ErrorRaw* errPtr = null;
+                var ptr = Interops.BookEvaluateAsync(_ptr, runtimeType, optionsPtr, &CompleteEvaluation,
+                    (void*)GCHandle.ToIntPtr(completionHandle), &errPtr);
+                Errors.ThrowIfAny(errPtr);
+                return (nint)ptr;
+            });
+        }
+        catch
+        {
+            completionHandle.Free();
+            if (outputHandle.IsAllocated)
+                outputHandle.Free();
+            if (progressHandle.IsAllocated)
+                progressHandle.Free();
+            throw;
+        }
+
+        var registration = cancellationToken.Register(static ptr => Interops.EvaluationCancel((void*)(nint)ptr!), evaluationPtr);
+        return completion.Task.ContinueWith(_ =>
+        {
+            // Waits for a running callback, so the evaluation is never cancelled after it's freed
+            registration.Dispose();
+            ErrorRaw* errPtr = null;
+            var resultPtr = Interops.EvaluationTakeResult((void*)evaluationPtr, &errPtr);
+            try
+            {
+                Errors.ThrowIfAny(errPtr);
+                return new EvaluationResult(resultPtr);
+            }
+            finally
             {
-                throw new InteropException(errString.ToString());
+                if (resultPtr != null)
+                    Interops.FreeEvaluationResult(resultPtr);
+                Interops.FreeEvaluation((void*)evaluationPtr);
+                completionHandle.Free();
+                if (outputHandle.IsAllocated)
+                    outputHandle.Free();
+                if (progressHandle.IsAllocated)
+                    progressHandle.Free();
+                GC.KeepAlive(this);
             }
+        }, TaskScheduler.Default);
+    }
+
+    [UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvCdecl) })]
+    private static void CompleteEvaluation(void* context)
+    {
+        ((TaskCompletionSource)GCHandle.FromIntPtr((nint)context).Target!).TrySetResult();
+    }
+
+    // Evaluates the entry definition once per set of arguments, in parallel on
+    // `workers` threads (0 means one per CPU) that each keep a net between
+    // items. Results are in the order of `arguments`, and an item that failed
+    // has an Error instead. options.Arguments is ignored, and options.Threads
+    // is per item, where 0 means 1. Registered functions and options.Output
+    // may be called from several threads at once.
+    public BatchResult[] EvaluateBatch(RuntimeTypes runtimeType, IReadOnlyList<IReadOnlyList<Value>> arguments,
+        EvaluationOptions? options = null, uint workers = 0, CancellationToken cancellationToken = default)
+    {
+        // Every item's arguments, one after another
+        var argPtrs = new nint[arguments.Sum(set => set.Count)];
+        try
+        {
+            var next = 0;
+            foreach (var set in arguments)
+                foreach (var argument in set)
+                    argPtrs[next++] = (nint)argument.ToRaw();
+
+            return WithOptions(options ?? new EvaluationOptions(), cancellationToken, optionsPtr =>
+            {
+                var items = new BatchItemRaw[arguments.Count];
+                ErrorRaw* errPtr = null;
+                BatchResultRaw* batchPtr;
+                fixed (nint* argsPtr = argPtrs)
+                fixed (BatchItemRaw* itemsPtr = items)
+                {
+                    var offset = 0;
+                    for (var i = 0; i < items.Length; i++)
+                    {
+                        items[i] = new BatchItemRaw
+                        {
+                            Args = (void**)(argsPtr + offset),
+                            ArgsLength = (ulong)arguments[i].Count
+                        };
+                        offset += arguments[i].Count;
+                    }
+
+                    batchPtr = Interops.BookEvaluateBatch(_ptr, runtimeType, optionsPtr, itemsPtr, (ulong)items.Length,
+                        workers, &errPtr);
+                }
+                Errors.ThrowIfAny(errPtr);
 
-            return new EvaluationResult(resultPtr);
+                try
+                {
+                    return BatchResult.FromRaw(batchPtr);
+                }
+                finally
+                {
+                    Interops.FreeBatchResult(batchPtr);
+                }
+            });
         }
         finally
         {
-            if (resultPtr != null)
+            foreach (var argPtr in argPtrs)
+                if (argPtr != 0)
+                    Interops.FreeValue((void*)argPtr);
+        }
+    }
+
+    // Marshals the options for `evaluate`, which runs the evaluation
+    internal static EvaluationResult Evaluate(EvaluationOptions options, CancellationToken cancellationToken,
+        EvaluateRaw evaluate)
+    {
+        return WithOptions(options, cancellationToken, optionsPtr =>
+        {
+            ErrorRaw* errPtr = null;
+            var resultPtr = evaluate(optionsPtr, &errPtr);
+            Errors.ThrowIfAny(errPtr);
+
+            try
+            {
+                return new EvaluationResult(resultPtr);
+            }
+            finally
+            {
                 Interops.FreeEvaluationResult(resultPtr);
+            }
+        });
+    }
+
+    // Marshals the options for `body`, freeing them once it returns
+    private static T WithOptions<T>(EvaluationOptions options, CancellationToken cancellationToken,
+        WithOptionsRaw<T> body)
+    {
+        var entryPtr = (byte*)Marshal.StringToCoTaskMemUTF8(options.Entry);
+        var tokenPtr = cancellationToken.CanBeCanceled ? Interops.CancellationTokenNew() : null;
+        var policyPtr = options.IoPolicy == null ? null : options.IoPolicy.ToRaw();
+        var outputHandle = options.Output == null ? default : GCHandle.Alloc(options.Output);
+        var progressHandle = options.Progress == null ? default : GCHandle.Alloc(options.Progress);
+        var argPtrs = new nint[options.Arguments?.Count ?? 0];
+        var registration = tokenPtr == null
+            ? default
+            : cancellationToken.Register(static ptr => Interops.CancellationTokenCancel((void*)(nint)ptr!), (nint)tokenPtr);
+        try
+        {
+            for (var i = 0; i < argPtrs.Length; i++)
+                argPtrs[i] = (nint)options.Arguments![i].ToRaw();
+            fixed (byte* stdinPtr = options.Stdin)
+            fixed (nint* argsPtr = argPtrs)
+            {
+                var optionsRaw = new EvaluationOptionsRaw
+                {
+                    NodeLength = options.NodeLength,
+                    VarsLength = options.VarsLength,
+                    Threads = options.Threads,
+                    MemDump = options.MemDump,
+                    Entry = entryPtr,
+                    MaxInteractions = options.MaxInteractions,
+                    TimeoutMs = options.Timeout.HasValue ? (ulong)Math.Ceiling(options.Timeout.Value.TotalMilliseconds) : 0,
+                    Cancellation = tokenPtr,
+                    IoPolicy = policyPtr,
+                    Stdio = options.Stdio,
+                    StdinBuffer = stdinPtr,
+                    StdinLength = options.Stdin == null ? 0 : (ulong)options.Stdin.Length,
+                    Output = options.Output == null ? null : &WriteOutput,
+                    OutputContext = options.Output == null ? null : (void*)GCHandle.ToIntPtr(outputHandle),
+                    Args = (void**)argsPtr,
+                    ArgsLength = (ulong)argPtrs.Length,
+                    Progress = options.Progress == null ? null : &ReportProgress,
+                    ProgressContext = options.Progress == null ? null : (void*)GCHandle.ToIntPtr(progressHandle),
+                    ProgressInteractions = options.ProgressInteractions,
+                    ProgressIntervalMs = options.ProgressInterval.HasValue
+                        ? (ulong)Math.Ceiling(options.ProgressInterval.Value.TotalMilliseconds)
+                        : 0
+                };
+                return body(&optionsRaw);
+            }
+        }
+        finally
+        {
+            // Waits for a running callback, so the token is never used after it's freed
+            registration.Dispose();
+            if (tokenPtr != null)
+                Interops.FreeCancellationToken(tokenPtr);
+            Marshal.FreeCoTaskMem((nint)entryPtr);
+            IoPolicy.FreeRaw(policyPtr);
+            if (outputHandle.IsAllocated)
+                outputHandle.Free();
+            if (progressHandle.IsAllocated)
+                progressHandle.Free();
+            foreach (var argPtr in argPtrs)
+                if (argPtr != 0)
+                    Interops.FreeValue((void*)argPtr);
+        }
+    }
+
+    [UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvCdecl) })]
+    private static void WriteOutput(void* context, uint fd, byte* buffer, ulong length)
+    {
+        try
+        {
+            var output = (OutputHandler)GCHandle.FromIntPtr((nint)context).Target!;
+            output((int)fd, new ReadOnlySpan<byte>(buffer, (int)length));
+        }
+        catch
+        {
+            // Unwinding into native code is undefined
+        }
+    }
+
+    [UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvCdecl) })]
+    private static uint ReportProgress(void* context, ProgressRaw* progress)
+    {
+        try
+        {
+            var handler = (ProgressHandler)GCHandle.FromIntPtr((nint)context).Target!;
+            return handler(Progress.FromRaw(*progress)) ? 1u : 0u;
+        }
+        catch
+        {
+            // Unwinding into native code is undefined
+            return 1;
         }
     }
 
     public bool Serialize(Span<byte> buffer, out ulong written)
     {
-        byte* errPtr = null;
+        ErrorRaw* errPtr = null;
         var vecPtr = Interops.BookSerialize(_ptr, &errPtr);
         try
         {
-            using var errString = new CString(errPtr);
-            if (errString.HasValue)
-            {
-                throw new InteropException(errString.ToString());
-            }
+            Errors.ThrowIfAny(errPtr);
 
             var vecLength = Interops.VecGetLength(vecPtr);
             var targetBufferLength = (ulong)buffer.Length;
@@ -85,15 +334,11 @@ public unsafe class Book : IDisposable
     
     public byte[] Serialize()
     {
-        byte* errPtr = null;
+        ErrorRaw* errPtr = null;
         var vecPtr = Interops.BookSerialize(_ptr, &errPtr);
         try
         {
-            using var errString = new CString(errPtr);
-            if (errString.HasValue)
-            {
-                throw new InteropException(errString.ToString());
-            }
+            Errors.ThrowIfAny(errPtr);
 
             var vecLength = Interops.VecGetLength(vecPtr);
             var buffer = new byte[vecLength];
@@ -109,11 +354,106 @@ public unsafe class Book : IDisposable
         }
     }
 
+    public IReadOnlyList<DefinitionInfo> GetDefinitions()
+    {
+        var count = Interops.BookDefCount(_ptr);
+        var definitions = new DefinitionInfo[count];
+        for (uint fid = 0; fid < count; fid++)
+        {
+            ErrorRaw* errPtr = null;
+            var info = new DefinitionInfoRaw();
+            Interops.BookDefInfo(_ptr, fid, &info, &errPtr);
+            Errors.ThrowIfAny(errPtr);
+
+            using var name = new CString(Interops.BookDefName(_ptr, fid, &errPtr));
+            Errors.ThrowIfAny(errPtr);
+
+            definitions[fid] = new DefinitionInfo(info.Fid, name.ToString(), info.Safe != 0, info.NodeLength,
+                info.RedexLength, info.VarsLength);
+        }
+
+        return definitions;
+    }
+
+    public string GetDefinitionSource(uint fid)
+    {
+        ErrorRaw* errPtr = null;
+        using var source = new CString(Interops.BookDefSource(_ptr, fid, &errPtr));
+        Errors.ThrowIfAny(errPtr);
+
+        return source.ToString();
+    }
+
+    // Shows the book as HVM core syntax, rebuilt from its compiled definitions
+    public string Decompile()
+    {
+        ErrorRaw* errPtr = null;
+        using var text = new CString(Interops.BookDecompile(_ptr, &errPtr));
+        Errors.ThrowIfAny(errPtr);
+
+        return text.ToString();
+    }
+
+    // Decodes a result of this book into a shape like "[u24]" or "(str, bool)".
+    // Shapes are built from u24, i24, f24, bool, str, ctr and any, with [item]
+    // for lists and (item, item, ...) for tuples.
+    public Value Decode(string term, string shape)
+    {
+        ErrorRaw* errPtr = null;
+        var valuePtr = Interops.BookDecode(_ptr, term, shape, &errPtr);
+        Errors.ThrowIfAny(errPtr);
+
+        try
+        {
+            return Value.FromRaw(valuePtr);
+        }
+        finally
+        {
+            Interops.FreeValue(valuePtr);
+        }
+    }
+
+    public Value Decode(EvaluationResult result, string shape) => Decode(result.Result, shape);
+
+    // Registers a host function for `IO_CALL "NAME"`, on either runtime. Its
+    // argument is decoded with the given shape (see Decode()). Names must be
+    // unique and can't shadow builtins like "WRITE". Don't register functions
+    // while the book is being evaluated.
+    public void RegisterFunction(string name, string shape, ForeignFunction function)
+    {
+        var handle = GCHandle.Alloc(function);
+        ErrorRaw* errPtr = null;
+        Interops.BookRegisterFfn(_ptr, name, shape, &CallFunction, (void*)GCHandle.ToIntPtr(handle), &errPtr);
+        if (errPtr != null)
+            handle.Free();
+        Errors.ThrowIfAny(errPtr);
+
+        _functions.Add(handle);
+    }
+
+    [UnmanagedCallersOnly(CallConvs = new[] { typeof(CallConvCdecl) })]
+    private static void* CallFunction(void* data, void* argumentPtr)
+    {
+        try
+        {
+            var function = (ForeignFunction)GCHandle.FromIntPtr((nint)data).Target!;
+            var result = function(Value.FromRaw(argumentPtr));
+            return result == null ? null : result.ToRaw();
+        }
+        catch
+        {
+            return null;
+        }
+    }
+
     private void CleanUp()
     {
         if (_ptr == null) return;
         Interops.FreeBook(_ptr);
         _ptr = null;
+        foreach (var handle in _functions)
+            handle.Free();
+        _functions.Clear();
     }
     
     public void Dispose()
@@ -0,0 +1,94 @@
+namespace HVM;
+
+// A tree added to a BookBuilder. Each tree can be used once.
+public readonly record struct TreeHandle(uint Id);
+
+// A net added to a BookBuilder. Each net can be defined once.
+public readonly record struct NetHandle(uint Id);
+
+// Builds a book from trees instead of source text. Failed calls are reported
+// by Build().
+public sealed unsafe class BookBuilder : IDisposable
+{
+    private void* _ptr = Interops.BookBuilderNew();
+
+    // Names starting with `$` are reserved for the vars of values
+    public TreeHandle Var(string name) => new(Interops.BookBuilderVar(_ptr, name));
+
+    public TreeHandle Ref(string name) => new(Interops.BookBuilderRef(_ptr, name));
+
+    public TreeHandle Era() => new(Interops.BookBuilderEra(_ptr));
+
+    // Adds a number from its raw 29-bit encoding
+    public TreeHandle Num(uint bits) => new(Interops.BookBuilderNum(_ptr, bits));
+
+    public TreeHandle U24(uint value) => new(Interops.BookBuilderU24(_ptr, value));
+
+    public TreeHandle I24(int value) => new(Interops.BookBuilderI24(_ptr, value));
+
+    public TreeHandle F24(float value) => new(Interops.BookBuilderF24(_ptr, value));
+
+    // Adds a λ-encoded value, like a string or a list
+    public TreeHandle Value(Value value)
+    {
+        var valuePtr = value.ToRaw();
+        try
+        {
+            return new(Interops.BookBuilderValue(_ptr, valuePtr));
+        }
+        finally
+        {
+            Interops.FreeValue(valuePtr);
+        }
+    }
+
+    // Adds an operator like `[+]`, or a cast like `[u24]`
+    public TreeHandle Operator(NumericOperators op) => new(Interops.BookBuilderSym(_ptr, op));
+
+    // Adds a partially applied operator like `[+1]`, consuming the operand
+    public TreeHandle Partial(NumericOperators op, TreeHandle operand) =>
+        new(Interops.BookBuilderPartial(_ptr, op, operand.Id));
+
+    public TreeHandle Con(TreeHandle fst, TreeHandle snd) => new(Interops.BookBuilderCon(_ptr, fst.Id, snd.Id));
+
+    public TreeHandle Dup(TreeHandle fst, TreeHandle snd) => new(Interops.BookBuilderDup(_ptr, fst.Id, snd.Id));
+
+    public TreeHandle Opr(TreeHandle fst, TreeHandle snd) => new(Interops.BookBuilderOpr(_ptr, fst.Id, snd.Id));
+
+    public TreeHandle Swi(TreeHandle fst, TreeHandle snd) => new(Interops.BookBuilderSwi(_ptr, fst.Id, snd.Id));
+
+    public NetHandle Net(TreeHandle root) => new(Interops.BookBuilderNet(_ptr, root.Id));
+
+    public void Redex(NetHandle net, TreeHandle fst, TreeHandle snd, bool parallel = false) =>
+        Interops.BookBuilderRedex(_ptr, net.Id, parallel ? 1u : 0u, fst.Id, snd.Id);
+
+    public void Define(string name, NetHandle net) => Interops.BookBuilderDefine(_ptr, name, net.Id);
+
+    // Builds the definitions added so far. The builder stays usable afterwards.
+    public Book Build()
+    {
+        ErrorRaw* errPtr = null;
+        var bookPtr = Interops.BookBuilderBuild(_ptr, &errPtr);
+        Errors.ThrowIfAny(errPtr);
+
+        return new Book(bookPtr);
+    }
+
+    private void CleanUp()
+    {
+        if (_ptr == null) return;
+        Interops.FreeBookBuilder(_ptr);
+        _ptr = null;
+    }
+
+    public void Dispose()
+    {
+        CleanUp();
+        GC.SuppressFinalize(this);
+    }
+
+    ~BookBuilder()
+    {
+        CleanUp();
+    }
+}
@@ -0,0 +1,21 @@
+using System.Runtime.InteropServices;
+
+namespace HVM;
+
+[StructLayout(LayoutKind.Sequential)]
+internal struct DefinitionInfoRaw
+{
+    public uint Fid;
+    public uint Safe;
+    public ulong NodeLength;
+    public ulong RedexLength;
+    public ulong VarsLength;
+}
+
+public sealed record DefinitionInfo(
+    uint Fid,
+    string Name,
+    bool Safe,
+    ulong NodeLength,
+    ulong RedexLength,
+    ulong VarsLength);
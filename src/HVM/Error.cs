@@ -0,0 +1,49 @@
+using System.Runtime.InteropServices;
+
+namespace HVM;
+
+[StructLayout(LayoutKind.Sequential)]
+internal readonly ref struct ErrorRaw
+{
+    public readonly ErrorKinds Kind;
+    public readonly uint HasSpan;
+    public readonly ulong SpanStart;
+    public readonly ulong SpanEnd;
+    public readonly RawCString Message;
+}
+
+internal static unsafe class Errors
+{
+    // Frees a native error and throws the matching exception
+    public static void ThrowIfAny(ErrorRaw* errPtr)
+    {
+        if (errPtr == null) return;
+        var exception = ToException(errPtr);
+        Interops.FreeError(errPtr);
+        throw exception;
+    }
+
+    // Reads a native error as the matching exception, without freeing it
+    public static Exception ToException(ErrorRaw* errPtr)
+    {
+        var kind = errPtr->Kind;
+        var message = errPtr->Message.ToString();
+        SourceSpan? span = errPtr->HasSpan != 0 ? new SourceSpan(errPtr->SpanStart, errPtr->SpanEnd) : null;
+        return kind switch
+        {
+            ErrorKinds.Parse => new ParseException(message, span),
+            ErrorKinds.Build => new BuildException(message),
+            ErrorKinds.MissingEntry => new MissingEntryException(message),
+            ErrorKinds.OutOfMemory => new NetOutOfMemoryException(message),
+            ErrorKinds.RuntimeUnavailable => new RuntimeUnavailableException(message),
+            ErrorKinds.Cancelled => new OperationCanceledException(message),
+            ErrorKinds.IO => new IOException(message),
+            ErrorKinds.InvalidArgument => new ArgumentException(message),
+            ErrorKinds.Runtime => new EvaluationException(message),
+            ErrorKinds.Panic => new InternalLibraryException(message),
+            ErrorKinds.Format => new BookFormatException(message),
+            ErrorKinds.Decode => new DecodeException(message),
+            _ => new InteropException(kind, message)
+        };
+    }
+}
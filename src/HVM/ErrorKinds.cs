@@ -0,0 +1,16 @@
+namespace HVM;
+
+public enum ErrorKinds : uint {
+    Parse = 0,
+    Build = 1,
+    MissingEntry = 2,
+    OutOfMemory = 3,
+    RuntimeUnavailable = 4,
+    Cancelled = 5,
+    IO = 6,
+    InvalidArgument = 7,
+    Runtime = 8,
+    Panic = 9,
+    Format = 10,
+    Decode = 11
+}
@@ -0,0 +1,65 @@
+using System.Runtime.InteropServices;
+
+namespace HVM;
+
+[StructLayout(LayoutKind.Sequential)]
+internal unsafe struct EvaluationOptionsRaw
+{
+    public ulong NodeLength;
+    public ulong VarsLength;
+    public uint Threads;
+    public MemDumpModes MemDump;
+    public byte* Entry;
+    public ulong MaxInteractions;
+    public ulong TimeoutMs;
+    public void* Cancellation;
+    public IoPolicyRaw* IoPolicy;
+    public StdioModes Stdio;
+    public byte* StdinBuffer;
+    public ulong StdinLength;
+    public delegate* unmanaged[Cdecl]<void*, uint, byte*, ulong, void> Output;
+    public void* OutputContext;
+    public void** Args;
+    public ulong ArgsLength;
+    public delegate* unmanaged[Cdecl]<void*, ProgressRaw*, uint> Progress;
+    public void* ProgressContext;
+    public ulong ProgressInteractions;
+    public ulong ProgressIntervalMs;
+}
+
+internal unsafe delegate EvaluationResultRaw* EvaluateRaw(EvaluationOptionsRaw* options, ErrorRaw** errOut);
+
+internal unsafe delegate T WithOptionsRaw<out T>(EvaluationOptionsRaw* options);
+
+public sealed class EvaluationOptions
+{
+    // Zero means the runtime default
+    public ulong NodeLength { get; init; }
+    public ulong VarsLength { get; init; }
+    public uint Threads { get; init; }
+    public MemDumpModes MemDump { get; init; } = MemDumpModes.None;
+    // Null means "main"
+    public string? Entry { get; init; }
+    // Zero means unlimited
+    public ulong MaxInteractions { get; init; }
+    // Null means no timeout
+    public TimeSpan? Timeout { get; init; }
+    // Null denies all IO; IoPolicy.Unrestricted allows any
+    public IoPolicy? IoPolicy { get; init; }
+    public StdioModes Stdio { get; init; } = StdioModes.Inherit;
+    // StdioModes.Capture: what programs read from stdin; null means empty
+    public byte[]? Stdin { get; init; }
+    // StdioModes.Capture: null means the output is captured in the result
+    public OutputHandler? Output { get; init; }
+    // Applied to the entry definition, which must take as many λ-arguments.
+    // They're copied into the net before it runs, so they must fit in it
+    // along with the program; a string takes 4 nodes per character, plus 2.
+    public IReadOnlyList<Value>? Arguments { get; init; }
+    // Called every ProgressInteractions interactions and every ProgressInterval,
+    // at least one of which must be set
+    public ProgressHandler? Progress { get; init; }
+    // Zero means never
+    public ulong ProgressInteractions { get; init; }
+    // Null means never
+    public TimeSpan? ProgressInterval { get; init; }
+}
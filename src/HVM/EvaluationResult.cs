@@ -3,33 +3,63 @@ using System.Runtime.InteropServices;
 namespace HVM;
 
 [StructLayout(LayoutKind.Sequential)]
-internal readonly ref struct EvaluationResultRaw
+internal readonly unsafe ref struct EvaluationResultRaw
 {
     public readonly ulong Iterations;
     public readonly double Duration;
+    public readonly EvaluationStatus Status;
     public readonly RawCString Result;
     public readonly RawCString MemDump;
     public readonly nuint Deallocator;
+    public readonly ResultTreeRaw* Tree;
+    public readonly byte* Stdout;
+    public readonly ulong StdoutLength;
+    public readonly byte* Stderr;
+    public readonly ulong StderrLength;
+    public readonly RuleCounts Rules;
+    public readonly ulong PeakRedexes;
+    public readonly ulong PeakNodes;
+    public readonly ulong PeakVars;
 }
 
 public readonly struct EvaluationResult
 {
     public ulong Iterations { get; }
+    public RuleCounts Rules { get; }
+    // Most redexes, nodes and vars in use at once. Exact with one thread; with
+    // more, peaks shorter than about a thousand interactions may be missed.
+    public ulong PeakRedexes { get; }
+    public ulong PeakNodes { get; }
+    public ulong PeakVars { get; }
     public TimeSpan Duration { get; }
     public double IterationsPerSecond => Iterations / Duration.TotalSeconds;
+    public EvaluationStatus Status { get; }
     public string Result { get; }
     public string MemDump { get; }
+    // Null unless the evaluation completed
+    public ResultTree? Tree { get; }
+    // Null unless captured, see StdioModes.Capture
+    public byte[]? Stdout { get; }
+    public byte[]? Stderr { get; }
 
     internal unsafe EvaluationResult(EvaluationResultRaw* raw)
     {
         Iterations = raw->Iterations;
+        Rules = raw->Rules;
+        PeakRedexes = raw->PeakRedexes;
+        PeakNodes = raw->PeakNodes;
+        PeakVars = raw->PeakVars;
         Duration = TimeSpan.FromSeconds(raw->Duration);
+        Status = raw->Status;
         Result = raw->Result.ToString();
         MemDump = raw->MemDump.ToString();
+        Tree = raw->Tree == null ? null : new ResultTree(raw->Tree);
+        Stdout = raw->Stdout == null ? null : new ReadOnlySpan<byte>(raw->Stdout, (int)raw->StdoutLength).ToArray();
+        Stderr = raw->Stderr == null ? null : new ReadOnlySpan<byte>(raw->Stderr, (int)raw->StderrLength).ToArray();
     }
 
     public override string ToString()
     {
-        return string.Create(null, stackalloc char[256], $"{{ Iterations = {Iterations}, Duration = {Duration}, IPS = {IterationsPerSecond}, Result = {Result}, MemDump = {(string.IsNullOrEmpty(MemDump) ? "<empty>" : $"\n{MemDump}")} }}");
+        return string.Create(null, stackalloc char[256], $"{{ Iterations = {Iterations}, Duration = {Duration}, IPS = {IterationsPerSecond}, Status = {Status}, Result = {Result}, MemDump = {(string.IsNullOrEmpty(MemDump) ? "<empty>" : $"\n{MemDump}")} }}");
     }
 }
\ No newline at end of file
@@ -0,0 +1,8 @@
+namespace HVM;
+
+public enum EvaluationStatus : uint {
+    Completed = 0,
+    FuelExhausted = 1,
+    TimedOut = 2,
+    Cancelled = 3
+}
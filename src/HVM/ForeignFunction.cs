@@ -0,0 +1,6 @@
+namespace HVM;
+
+// A host function, run by programs with `IO_CALL "NAME"`. Its argument is
+// decoded into the shape it was registered with; returning null passes `*`
+// back to the program. Exceptions are caught and also return `*`.
+public delegate Value? ForeignFunction(Value argument);
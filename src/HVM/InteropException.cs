@@ -1,3 +1,30 @@
 namespace HVM;
 
-public class InteropException(string? msg = null) : Exception(msg);
\ No newline at end of file
+public class InteropException(ErrorKinds kind, string? msg = null) : Exception(msg)
+{
+    public ErrorKinds Kind { get; } = kind;
+}
+
+// Byte offsets into the parsed source
+public readonly record struct SourceSpan(ulong Start, ulong End);
+
+public class ParseException(string? msg = null, SourceSpan? span = null) : InteropException(ErrorKinds.Parse, msg)
+{
+    public SourceSpan? Span { get; } = span;
+}
+
+public class BuildException(string? msg = null) : InteropException(ErrorKinds.Build, msg);
+
+public class MissingEntryException(string? msg = null) : InteropException(ErrorKinds.MissingEntry, msg);
+
+public class NetOutOfMemoryException(string? msg = null) : InteropException(ErrorKinds.OutOfMemory, msg);
+
+public class RuntimeUnavailableException(string? msg = null) : InteropException(ErrorKinds.RuntimeUnavailable, msg);
+
+public class EvaluationException(string? msg = null) : InteropException(ErrorKinds.Runtime, msg);
+
+public class InternalLibraryException(string? msg = null) : InteropException(ErrorKinds.Panic, msg);
+
+public class BookFormatException(string? msg = null) : InteropException(ErrorKinds.Format, msg);
+
+public class DecodeException(string? msg = null) : InteropException(ErrorKinds.Decode, msg);
@@ -8,27 +8,239 @@ public static class Interops
     
     [DllImport(DllName, EntryPoint = "book_parse")]
     [SuppressGCTransition]
-    internal static extern unsafe void* BookParse([MarshalAs(UnmanagedType.LPUTF8Str)] string code, byte** errOut);
+    internal static extern unsafe void* BookParse([MarshalAs(UnmanagedType.LPUTF8Str)] string code, ErrorRaw** errOut);
     
     [DllImport(DllName, EntryPoint = "free_cstring", CallingConvention = CallingConvention.Cdecl)]
     [SuppressGCTransition]
     internal static extern unsafe void* FreeCString(void* stringPtr);
     
+    [DllImport(DllName, EntryPoint = "free_error", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void FreeError(ErrorRaw* errorPtr);
+    
     [DllImport(DllName, EntryPoint = "free_book", CallingConvention = CallingConvention.Cdecl)]
     [SuppressGCTransition]
     internal static extern unsafe void* FreeBook(void* bookPtr);
     
+    // Long-running: keeps the GC transition so other threads can collect and cancel meanwhile
     [DllImport(DllName, EntryPoint = "book_evaluate", CallingConvention = CallingConvention.Cdecl)]
+    internal static extern unsafe EvaluationResultRaw* BookEvaluate(void* bookPtr, RuntimeTypes runtimeType, EvaluationOptionsRaw* options, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "session_new", CallingConvention = CallingConvention.Cdecl)]
+    internal static extern unsafe void* SessionNew(RuntimeTypes runtimeType, EvaluationOptionsRaw* options, ErrorRaw** errOut);
+    
+    // Long-running: keeps the GC transition so other threads can collect and cancel meanwhile
+    [DllImport(DllName, EntryPoint = "session_evaluate", CallingConvention = CallingConvention.Cdecl)]
+    internal static extern unsafe EvaluationResultRaw* SessionEvaluate(void* sessionPtr, void* bookPtr, EvaluationOptionsRaw* options, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "free_session", CallingConvention = CallingConvention.Cdecl)]
+    internal static extern unsafe void FreeSession(void* sessionPtr);
+    
+    [DllImport(DllName, EntryPoint = "book_evaluate_async", CallingConvention = CallingConvention.Cdecl)]
+    internal static extern unsafe void* BookEvaluateAsync(void* bookPtr, RuntimeTypes runtimeType, EvaluationOptionsRaw* options, delegate* unmanaged[Cdecl]<void*, void> callback, void* callbackContext, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "evaluation_cancel", CallingConvention = CallingConvention.Cdecl)]
     [SuppressGCTransition]
-    internal static extern unsafe EvaluationResultRaw* BookEvaluate(void* bookPtr, RuntimeTypes runtimeType, uint enableMemDump, byte** errOut);
+    internal static extern unsafe void EvaluationCancel(void* evaluationPtr);
+    
+    [DllImport(DllName, EntryPoint = "evaluation_take_result", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe EvaluationResultRaw* EvaluationTakeResult(void* evaluationPtr, ErrorRaw** errOut);
+    
+    // Waits for the evaluation's thread to exit
+    [DllImport(DllName, EntryPoint = "free_evaluation", CallingConvention = CallingConvention.Cdecl)]
+    internal static extern unsafe void FreeEvaluation(void* evaluationPtr);
+    
+    // Long-running: keeps the GC transition so other threads can collect and cancel meanwhile
+    [DllImport(DllName, EntryPoint = "book_evaluate_batch", CallingConvention = CallingConvention.Cdecl)]
+    internal static extern unsafe BatchResultRaw* BookEvaluateBatch(void* bookPtr, RuntimeTypes runtimeType, EvaluationOptionsRaw* options, BatchItemRaw* items, ulong itemsLength, uint workers, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "free_batch_result", CallingConvention = CallingConvention.Cdecl)]
+    internal static extern unsafe void FreeBatchResult(BatchResultRaw* batchPtr);
     
     [DllImport(DllName, EntryPoint = "free_evaluation_result", CallingConvention = CallingConvention.Cdecl)]
     [SuppressGCTransition]
     internal static extern unsafe void FreeEvaluationResult(EvaluationResultRaw* resultPtr);
     
+    [DllImport(DllName, EntryPoint = "cancellation_token_new", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* CancellationTokenNew();
+    
+    [DllImport(DllName, EntryPoint = "cancellation_token_cancel", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void CancellationTokenCancel(void* tokenPtr);
+    
+    [DllImport(DllName, EntryPoint = "free_cancellation_token", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void FreeCancellationToken(void* tokenPtr);
+    
     [DllImport(DllName, EntryPoint = "book_serialize", CallingConvention = CallingConvention.Cdecl)]
     [SuppressGCTransition]
-    internal static extern unsafe void* BookSerialize(void* bookPtr, byte** errOut);
+    internal static extern unsafe void* BookSerialize(void* bookPtr, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "book_deserialize", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* BookDeserialize(byte* data, ulong length, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "book_def_count", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookDefCount(void* bookPtr);
+    
+    [DllImport(DllName, EntryPoint = "book_def_name", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* BookDefName(void* bookPtr, uint fid, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "book_def_info", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void BookDefInfo(void* bookPtr, uint fid, DefinitionInfoRaw* infoOut, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "book_def_source", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* BookDefSource(void* bookPtr, uint fid, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "book_decompile", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* BookDecompile(void* bookPtr, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_new", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* BookBuilderNew();
+    
+    [DllImport(DllName, EntryPoint = "free_book_builder", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void FreeBookBuilder(void* builderPtr);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_var", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderVar(void* builderPtr, [MarshalAs(UnmanagedType.LPUTF8Str)] string name);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_ref", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderRef(void* builderPtr, [MarshalAs(UnmanagedType.LPUTF8Str)] string name);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_era", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderEra(void* builderPtr);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_num", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderNum(void* builderPtr, uint bits);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_u24", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderU24(void* builderPtr, uint value);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_i24", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderI24(void* builderPtr, int value);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_f24", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderF24(void* builderPtr, float value);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_sym", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderSym(void* builderPtr, NumericOperators op);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_partial", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderPartial(void* builderPtr, NumericOperators op, uint operand);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_con", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderCon(void* builderPtr, uint fst, uint snd);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_dup", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderDup(void* builderPtr, uint fst, uint snd);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_opr", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderOpr(void* builderPtr, uint fst, uint snd);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_swi", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderSwi(void* builderPtr, uint fst, uint snd);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_net", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderNet(void* builderPtr, uint root);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_redex", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void BookBuilderRedex(void* builderPtr, uint net, uint par, uint fst, uint snd);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_define", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void BookBuilderDefine(void* builderPtr, [MarshalAs(UnmanagedType.LPUTF8Str)] string name, uint net);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_build", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* BookBuilderBuild(void* builderPtr, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "book_builder_value", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe uint BookBuilderValue(void* builderPtr, void* valuePtr);
+    
+    [DllImport(DllName, EntryPoint = "book_decode", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* BookDecode(void* bookPtr, [MarshalAs(UnmanagedType.LPUTF8Str)] string term, [MarshalAs(UnmanagedType.LPUTF8Str)] string shape, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "book_register_ffn", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void BookRegisterFfn(void* bookPtr, [MarshalAs(UnmanagedType.LPUTF8Str)] string name, [MarshalAs(UnmanagedType.LPUTF8Str)] string shape, delegate* unmanaged[Cdecl]<void*, void*, void*> func, void* data, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "free_value", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void FreeValue(void* valuePtr);
+    
+    [DllImport(DllName, EntryPoint = "value_u24", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* ValueU24(uint value);
+    
+    [DllImport(DllName, EntryPoint = "value_i24", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* ValueI24(int value);
+    
+    [DllImport(DllName, EntryPoint = "value_f24", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* ValueF24(float value);
+    
+    [DllImport(DllName, EntryPoint = "value_bool", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* ValueBool(uint value);
+    
+    [DllImport(DllName, EntryPoint = "value_string", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* ValueString([MarshalAs(UnmanagedType.LPUTF8Str)] string text, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "value_list", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* ValueList();
+    
+    [DllImport(DllName, EntryPoint = "value_tuple", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* ValueTuple();
+    
+    [DllImport(DllName, EntryPoint = "value_ctr", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* ValueCtr(uint tag);
+    
+    [DllImport(DllName, EntryPoint = "value_push", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void ValuePush(void* valuePtr, void* itemPtr, ErrorRaw** errOut);
+    
+    [DllImport(DllName, EntryPoint = "value_get_info", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void ValueGetInfo(void* valuePtr, ValueInfoRaw* infoOut);
+    
+    [DllImport(DllName, EntryPoint = "value_get_item", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* ValueGetItem(void* valuePtr, ulong index);
+    
+    [DllImport(DllName, EntryPoint = "value_get_string", CallingConvention = CallingConvention.Cdecl)]
+    [SuppressGCTransition]
+    internal static extern unsafe void* ValueGetString(void* valuePtr);
     
     [DllImport(DllName, EntryPoint = "vec_get_length", CallingConvention = CallingConvention.Cdecl)]
     [SuppressGCTransition]
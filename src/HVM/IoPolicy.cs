@@ -0,0 +1,77 @@
+using System.Runtime.InteropServices;
+
+namespace HVM;
+
+[StructLayout(LayoutKind.Sequential)]
+internal unsafe struct IoDirectoryRaw
+{
+    public byte* Path;
+    public uint Writable;
+}
+
+[StructLayout(LayoutKind.Sequential)]
+internal unsafe struct IoPolicyRaw
+{
+    public IoDirectoryRaw* Directories;
+    public ulong DirectoriesLength;
+    public byte** Functions;
+    public ulong FunctionsLength;
+    public uint MaxOpenFiles;
+    public uint Unrestricted;
+}
+
+// What IO an evaluation may do. Anything not allowed is denied, so a new
+// policy denies all IO. Denied calls return `*` to the program, like any
+// other failed IO call.
+public sealed class IoPolicy
+{
+    // Allows all IO, with no limit on open files besides the runtime's own
+    public static IoPolicy Unrestricted { get; } = new() { AllowAll = true };
+
+    // Allows all IO, ignoring the lists below
+    public bool AllowAll { get; init; }
+    // Directories programs may read files in, along with their subdirectories
+    public IReadOnlyList<string> ReadOnlyDirectories { get; init; } = Array.Empty<string>();
+    // Directories programs may also write and create files in. A read-only
+    // directory inside a writable one stays read-only.
+    public IReadOnlyList<string> WritableDirectories { get; init; } = Array.Empty<string>();
+    // Names of the functions programs may call, like "WRITE" or registered ones
+    public IReadOnlyList<string> Functions { get; init; } = Array.Empty<string>();
+    // At most 253
+    public uint MaxOpenFiles { get; init; }
+
+    // Creates a native copy, to be freed with FreeRaw()
+    internal unsafe IoPolicyRaw* ToRaw()
+    {
+        var directories = ReadOnlyDirectories.Select(path => (path, false))
+            .Concat(WritableDirectories.Select(path => (path, true))).ToArray();
+        var raw = (IoPolicyRaw*)NativeMemory.AllocZeroed((nuint)sizeof(IoPolicyRaw));
+        raw->Directories = (IoDirectoryRaw*)NativeMemory.AllocZeroed((nuint)directories.Length, (nuint)sizeof(IoDirectoryRaw));
+        raw->DirectoriesLength = (ulong)directories.Length;
+        for (var i = 0; i < directories.Length; i++)
+        {
+            raw->Directories[i].Path = (byte*)Marshal.StringToCoTaskMemUTF8(directories[i].path);
+            raw->Directories[i].Writable = directories[i].Item2 ? 1u : 0u;
+        }
+
+        raw->Functions = (byte**)NativeMemory.AllocZeroed((nuint)Functions.Count, (nuint)sizeof(byte*));
+        raw->FunctionsLength = (ulong)Functions.Count;
+        for (var i = 0; i < Functions.Count; i++)
+            raw->Functions[i] = (byte*)Marshal.StringToCoTaskMemUTF8(Functions[i]);
+        raw->MaxOpenFiles = MaxOpenFiles;
+        raw->Unrestricted = AllowAll ? 1u : 0u;
+        return raw;
+    }
+
+    internal static unsafe void FreeRaw(IoPolicyRaw* raw)
+    {
+        if (raw == null) return;
+        for (ulong i = 0; i < raw->DirectoriesLength; i++)
+            Marshal.FreeCoTaskMem((nint)raw->Directories[i].Path);
+        for (ulong i = 0; i < raw->FunctionsLength; i++)
+            Marshal.FreeCoTaskMem((nint)raw->Functions[i]);
+        NativeMemory.Free(raw->Directories);
+        NativeMemory.Free(raw->Functions);
+        NativeMemory.Free(raw);
+    }
+}
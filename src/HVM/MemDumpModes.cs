@@ -0,0 +1,7 @@
+namespace HVM;
+
+public enum MemDumpModes : uint {
+    None = 0,
+    Nodes = 1,
+    Full = 2
+}
@@ -0,0 +1,26 @@
+namespace HVM;
+
+public enum NumericOperators : uint {
+    U24 = 0x01,
+    I24 = 0x02,
+    F24 = 0x03,
+    Add = 0x04,
+    Sub = 0x05,
+    FlippedSub = 0x06,
+    Mul = 0x07,
+    Div = 0x08,
+    FlippedDiv = 0x09,
+    Rem = 0x0A,
+    FlippedRem = 0x0B,
+    Eq = 0x0C,
+    Neq = 0x0D,
+    Lt = 0x0E,
+    Gt = 0x0F,
+    And = 0x10,
+    Or = 0x11,
+    Xor = 0x12,
+    Shl = 0x13,
+    FlippedShl = 0x14,
+    Shr = 0x15,
+    FlippedShr = 0x16
+}
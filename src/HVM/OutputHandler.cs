@@ -0,0 +1,5 @@
+namespace HVM;
+
+// Gets a program's output as it's written, on fd 1 (stdout) or 2 (stderr).
+// The data is only valid during the call. Exceptions are caught and ignored.
+public delegate void OutputHandler(int fd, ReadOnlySpan<byte> data);
@@ -0,0 +1,30 @@
+using System.Runtime.InteropServices;
+
+namespace HVM;
+
+[StructLayout(LayoutKind.Sequential)]
+internal readonly struct ProgressRaw
+{
+    public readonly ulong Interactions;
+    public readonly ulong RedexesLow;
+    public readonly ulong RedexesHigh;
+    public readonly ulong LiveNodes;
+    public readonly double Time;
+}
+
+// How far an evaluation got, as passed to a ProgressHandler
+public sealed record Progress(
+    ulong Interactions,
+    // Redexes waiting in all threads' bags
+    ulong RedexesLow,
+    ulong RedexesHigh,
+    // Estimated from the nodes interactions made and took
+    ulong LiveNodes,
+    TimeSpan Elapsed)
+{
+    internal static Progress FromRaw(in ProgressRaw raw)
+    {
+        return new Progress(raw.Interactions, raw.RedexesLow, raw.RedexesHigh, raw.LiveNodes,
+            TimeSpan.FromSeconds(raw.Time));
+    }
+}
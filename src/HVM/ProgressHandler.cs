@@ -0,0 +1,5 @@
+namespace HVM;
+
+// Gets progress reports while a program runs. Returning false cancels the
+// evaluation. Exceptions are caught and ignored.
+public delegate bool ProgressHandler(Progress progress);
@@ -0,0 +1,64 @@
+using System.Runtime.InteropServices;
+
+namespace HVM;
+
+[StructLayout(LayoutKind.Sequential)]
+internal readonly unsafe struct ResultTreeRaw
+{
+    public readonly ResultNode* Nodes;
+    public readonly ulong NodesLength;
+    public readonly byte** Names;
+    public readonly ulong NamesLength;
+    public readonly ulong VarsLength;
+}
+
+public enum ResultNodeTags : uint {
+    Var = 0,
+    Ref = 1,
+    Era = 2,
+    Num = 3,
+    Con = 4,
+    Dup = 5,
+    Opr = 6,
+    Swi = 7
+}
+
+// Numeric types of Num nodes. Any other type is a partially applied operator
+// (see NumericOperators).
+public enum NumberTypes : uint {
+    Sym = 0,
+    U24 = 1,
+    I24 = 2,
+    F24 = 3
+}
+
+// A node of a read back result. Fst is a var id for Var nodes, an index into
+// ResultTree.Names for Ref nodes, and a child index for Con/Dup/Opr/Swi nodes.
+[StructLayout(LayoutKind.Sequential)]
+public readonly record struct ResultNode(ResultNodeTags Tag, NumberTypes Type, uint Fst, uint Snd, uint Value)
+{
+    public uint U24 => Value;
+    public int I24 => (int)Value;
+    public float F24 => BitConverter.UInt32BitsToSingle(Value);
+    public NumericOperators Operator => (NumericOperators)(Type == NumberTypes.Sym ? Value : (uint)Type);
+}
+
+// A read back result, in pre-order: the root is Nodes[0] and children always
+// come after their parent.
+public sealed class ResultTree
+{
+    public IReadOnlyList<ResultNode> Nodes { get; }
+    public IReadOnlyList<string> Names { get; }
+    public ulong VarCount { get; }
+    public ResultNode Root => Nodes[0];
+
+    internal unsafe ResultTree(ResultTreeRaw* raw)
+    {
+        Nodes = new ReadOnlySpan<ResultNode>(raw->Nodes, checked((int)raw->NodesLength)).ToArray();
+        var names = new string[raw->NamesLength];
+        for (var i = 0; i < names.Length; i++)
+            names[i] = new RawCString(raw->Names[i]).ToString();
+        Names = names;
+        VarCount = raw->VarsLength;
+    }
+}
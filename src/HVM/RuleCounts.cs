@@ -0,0 +1,23 @@
+using System.Runtime.InteropServices;
+
+namespace HVM;
+
+// Interactions an evaluation performed, by rule. EvaluationResult.Iterations
+// counts all but Link.
+[StructLayout(LayoutKind.Sequential)]
+public readonly struct RuleCounts
+{
+    public readonly ulong Link;
+    public readonly ulong Call;
+    public readonly ulong Void;
+    public readonly ulong Eras;
+    public readonly ulong Anni;
+    public readonly ulong Comm;
+    public readonly ulong Oper;
+    public readonly ulong Swit;
+
+    public override string ToString()
+    {
+        return $"{{ Link = {Link}, Call = {Call}, Void = {Void}, Eras = {Eras}, Anni = {Anni}, Comm = {Comm}, Oper = {Oper}, Swit = {Swit} }}";
+    }
+}
@@ -0,0 +1,58 @@
+namespace HVM;
+
+// Keeps a net alive between evaluations, so they don't pay for allocating and
+// freeing it. Evaluations on a session run one at a time.
+public sealed unsafe class Session : IDisposable
+{
+    private void* _ptr;
+    private readonly object _lock = new();
+
+    // The buffer sizes and thread count are taken from `options`, and are the
+    // ones used by every evaluation on this session
+    public Session(RuntimeTypes runtimeType, EvaluationOptions? options = null)
+    {
+        options ??= new EvaluationOptions();
+        var optionsRaw = new EvaluationOptionsRaw
+        {
+            NodeLength = options.NodeLength,
+            VarsLength = options.VarsLength,
+            Threads = options.Threads
+        };
+        ErrorRaw* errPtr = null;
+        _ptr = Interops.SessionNew(runtimeType, &optionsRaw, &errPtr);
+        Errors.ThrowIfAny(errPtr);
+    }
+
+    // Buffer sizes and thread count in `options` are ignored
+    public EvaluationResult Evaluate(Book book, EvaluationOptions? options = null,
+        CancellationToken cancellationToken = default)
+    {
+        lock (_lock)
+        {
+            ObjectDisposedException.ThrowIf(_ptr == null, this);
+            return Book.Evaluate(options ?? new EvaluationOptions(), cancellationToken,
+                (optionsPtr, errOut) => Interops.SessionEvaluate(_ptr, book.Pointer, optionsPtr, errOut));
+        }
+    }
+
+    private void CleanUp()
+    {
+        lock (_lock)
+        {
+            if (_ptr == null) return;
+            Interops.FreeSession(_ptr);
+            _ptr = null;
+        }
+    }
+
+    public void Dispose()
+    {
+        CleanUp();
+        GC.SuppressFinalize(this);
+    }
+
+    ~Session()
+    {
+        CleanUp();
+    }
+}
@@ -0,0 +1,9 @@
+namespace HVM;
+
+public enum StdioModes : uint {
+    // Programs use the process' own stdin, stdout and stderr
+    Inherit = 0,
+    // Programs read EvaluationOptions.Stdin, and their output is captured in
+    // the result, or passed to EvaluationOptions.Output
+    Capture = 1
+}
@@ -0,0 +1,138 @@
+using System.Runtime.InteropServices;
+
+namespace HVM;
+
+public enum ValueKinds : uint {
+    U24 = 0,
+    I24 = 1,
+    F24 = 2,
+    Bool = 3,
+    String = 4,
+    List = 5,
+    Tuple = 6,
+    Ctr = 7
+}
+
+[StructLayout(LayoutKind.Sequential)]
+internal readonly struct ValueInfoRaw
+{
+    public readonly ValueKinds Kind;
+    public readonly uint Bits;
+    public readonly ulong Length;
+}
+
+// Data passed to and from λ-encoded terms. Read results with Book.Decode(),
+// and pass values in with BookBuilder.Value().
+public abstract record Value
+{
+    public sealed record U24(uint Number) : Value;
+
+    public sealed record I24(int Number) : Value;
+
+    public sealed record F24(float Number) : Value;
+
+    // `False` (tag 0) or `True` (tag 1)
+    public sealed record Bool(bool Flag) : Value;
+
+    // A list of code points
+    public sealed record Str(string Text) : Value;
+
+    // Built from `Nil` (tag 0) and `Cons head tail` (tag 1)
+    public sealed record List(IReadOnlyList<Value> Items) : Value;
+
+    // Nests to the right: `(a, b, c)` is `(a (b c))`
+    public sealed record Tuple(IReadOnlyList<Value> Items) : Value;
+
+    // `λt (((t Tag) field0) field1 ...)`
+    public sealed record Ctr(uint Tag, IReadOnlyList<Value> Fields) : Value;
+
+    // Copies a native value, which stays owned by the caller
+    internal static unsafe Value FromRaw(void* ptr)
+    {
+        var info = new ValueInfoRaw();
+        Interops.ValueGetInfo(ptr, &info);
+        switch (info.Kind)
+        {
+            case ValueKinds.U24:
+                return new U24(info.Bits);
+            case ValueKinds.I24:
+                return new I24((int)info.Bits);
+            case ValueKinds.F24:
+                return new F24(BitConverter.UInt32BitsToSingle(info.Bits));
+            case ValueKinds.Bool:
+                return new Bool(info.Bits != 0);
+            case ValueKinds.String:
+            {
+                var textPtr = Interops.ValueGetString(ptr);
+                try
+                {
+                    return new Str(Marshal.PtrToStringUTF8((nint)textPtr) ?? string.Empty);
+                }
+                finally
+                {
+                    Interops.FreeCString(textPtr);
+                }
+            }
+        }
+
+        var items = new Value[info.Length];
+        for (var i = 0; i < items.Length; i++)
+            items[i] = FromRaw(Interops.ValueGetItem(ptr, (ulong)i));
+        return info.Kind switch
+        {
+            ValueKinds.List => new List(items),
+            ValueKinds.Tuple => new Tuple(items),
+            _ => new Ctr(info.Bits, items)
+        };
+    }
+
+    // Creates a native copy, to be freed with Interops.FreeValue()
+    internal unsafe void* ToRaw()
+    {
+        ErrorRaw* errPtr = null;
+        void* ptr;
+        IReadOnlyList<Value> items;
+        switch (this)
+        {
+            case U24 value:
+                return Interops.ValueU24(value.Number);
+            case I24 value:
+                return Interops.ValueI24(value.Number);
+            case F24 value:
+                return Interops.ValueF24(value.Number);
+            case Bool value:
+                return Interops.ValueBool(value.Flag ? 1u : 0u);
+            case Str value:
+                ptr = Interops.ValueString(value.Text, &errPtr);
+                Errors.ThrowIfAny(errPtr);
+                return ptr;
+            case List value:
+                (ptr, items) = (Interops.ValueList(), value.Items);
+                break;
+            case Tuple value:
+                (ptr, items) = (Interops.ValueTuple(), value.Items);
+                break;
+            case Ctr value:
+                (ptr, items) = (Interops.ValueCtr(value.Tag), value.Fields);
+                break;
+            default:
+                throw new NotSupportedException(GetType().Name);
+        }
+
+        try
+        {
+            foreach (var item in items)
+            {
+                Interops.ValuePush(ptr, item.ToRaw(), &errPtr);
+                Errors.ThrowIfAny(errPtr);
+            }
+        }
+        catch
+        {
+            Interops.FreeValue(ptr);
+            throw;
+        }
+
+        return ptr;
+    }
+}